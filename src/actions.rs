@@ -0,0 +1,411 @@
+use log::{debug, error, info};
+use serenity::{all::*, async_trait};
+
+use crate::{
+    data::AppData,
+    groups::{self, RoleHistory},
+    metrics,
+    policy::{self, Decision, GuildPolicy, MemberSnapshot, Trigger},
+};
+
+/// Calls the bot makes to Discord when enforcing the policy, so enforcement can run against a fake server in tests
+#[async_trait]
+pub trait GuildActions: Send + Sync {
+    /// Fetch a single member of a server
+    async fn member(&self, guild_id: GuildId, user_id: UserId) -> serenity::Result<Member>;
+
+    /// Fetch a page of the members of a server, ordered by user ID
+    ///
+    /// @param limit Most members to return, the API maximum if None
+    /// @param after Only return members with a higher user ID than this
+    async fn members(&self, guild_id: GuildId, limit: Option<u64>, after: Option<UserId>) -> serenity::Result<Vec<Member>>;
+
+    /// Give roles to a member
+    async fn add_roles(&self, guild_id: GuildId, user_id: UserId, roles: &[RoleId]) -> serenity::Result<()>;
+
+    /// Take roles from a member
+    async fn remove_roles(&self, guild_id: GuildId, user_id: UserId, roles: &[RoleId]) -> serenity::Result<()>;
+
+    /// Stop a member from talking until the given time
+    async fn timeout(&self, guild_id: GuildId, user_id: UserId, until: Timestamp) -> serenity::Result<()>;
+
+    /// Kick a member from a server
+    async fn kick(&self, guild_id: GuildId, user_id: UserId, reason: &str) -> serenity::Result<()>;
+
+    /// Send a direct message to a user
+    async fn send_dm(&self, user_id: UserId, content: String) -> serenity::Result<()>;
+
+    /// Post a message in a channel
+    async fn send_message(&self, channel_id: ChannelId, content: String) -> serenity::Result<()>;
+}
+
+#[async_trait]
+impl GuildActions for Http {
+    async fn member(&self, guild_id: GuildId, user_id: UserId) -> serenity::Result<Member> {
+        self.get_member(guild_id, user_id).await
+    }
+
+    async fn members(&self, guild_id: GuildId, limit: Option<u64>, after: Option<UserId>) -> serenity::Result<Vec<Member>> {
+        guild_id.members(self, limit, after).await
+    }
+
+    async fn add_roles(&self, guild_id: GuildId, user_id: UserId, roles: &[RoleId]) -> serenity::Result<()> {
+        for role in roles {
+            self.add_member_role(guild_id, user_id, *role, None).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn remove_roles(&self, guild_id: GuildId, user_id: UserId, roles: &[RoleId]) -> serenity::Result<()> {
+        for role in roles {
+            self.remove_member_role(guild_id, user_id, *role, None).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn timeout(&self, guild_id: GuildId, user_id: UserId, until: Timestamp) -> serenity::Result<()> {
+        guild_id.edit_member(self, user_id, EditMember::new().disable_communication_until_datetime(until)).await.map(|_| ())
+    }
+
+    async fn kick(&self, guild_id: GuildId, user_id: UserId, reason: &str) -> serenity::Result<()> {
+        self.kick_member(guild_id, user_id, Some(reason)).await
+    }
+
+    async fn send_dm(&self, user_id: UserId, content: String) -> serenity::Result<()> {
+        user_id.dm(self, CreateMessage::new().content(content)).await.map(|_| ())
+    }
+
+    async fn send_message(&self, channel_id: ChannelId, content: String) -> serenity::Result<()> {
+        channel_id.send_message(self, CreateMessage::new().content(content)).await.map(|_| ())
+    }
+}
+
+/// Carry out what the policy decided for a member, recording the outcome in the metrics
+///
+/// @param actions Connection to Discord
+/// @param guild_id ID of the member's server
+/// @param user_id ID of the member
+/// @param decision What the policy decided
+/// @param trigger What caused the policy to be applied
+///
+/// @return True if anything was done, or the error reported by Discord
+pub async fn carry_out(actions: &dyn GuildActions, guild_id: GuildId, user_id: UserId, decision: &Decision, trigger: Trigger) -> serenity::Result<bool> {
+    let (operation, result) = match decision {
+        Decision::Compliant | Decision::SkipBot => return Ok(false),
+        Decision::RemoveRoles(roles) => ("remove_roles", actions.remove_roles(guild_id, user_id, roles).await),
+        Decision::AssignRole(role) => ("add_roles", actions.add_roles(guild_id, user_id, &[*role]).await),
+        Decision::Timeout(until) => ("timeout", actions.timeout(guild_id, user_id, *until).await),
+        Decision::Kick => ("kick", actions.kick(guild_id, user_id, "Did not get the primary role in time").await),
+    };
+
+    if let Err(error) = result {
+        error!("Failed to {} for {}: {}", operation, user_id, error);
+        metrics::DISCORD_ERRORS.with_label_values(&[operation]).inc();
+        return Err(error);
+    }
+
+    info!("Carried out {} for {} ({})", operation, user_id, trigger.label());
+    match decision {
+        Decision::RemoveRoles(roles) => metrics::ROLES_REMOVED.with_label_values(&[trigger.label()]).inc_by(roles.len() as u64),
+        _ => metrics::MEMBERS_PUNISHED.with_label_values(&[operation, trigger.label()]).inc(),
+    }
+
+    Ok(true)
+}
+
+/// Apply the policy to a member whose roles just changed, if auto scanning is enabled in their server
+///
+/// @param actions Connection to Discord
+/// @param app_data Database of server configuration
+/// @param guild_id ID of the member's server
+/// @param member Member as of the update
+///
+/// @return What was done to the member, Compliant if nothing was
+pub async fn auto_scan(actions: &dyn GuildActions, app_data: &mut AppData, guild_id: GuildId, member: &MemberSnapshot) -> Decision {
+    if !app_data.is_auto_scan_enabled(&guild_id) {
+        metrics::AUTO_SCAN_EVENTS.with_label_values(&["skipped"]).inc();
+        return Decision::Compliant; // Do nothing, auto scan is disabled.
+    }
+
+    let Some(policy) = GuildPolicy::load(app_data, &guild_id) else {
+        error!("Failed to get a primary role or role rules for {}", guild_id);
+        metrics::AUTO_SCAN_EVENTS.with_label_values(&["skipped"]).inc();
+        return Decision::Compliant;
+    };
+
+    let decision = policy::decide(member, &policy, Trigger::AutoScan, Timestamp::now());
+    if !decision.is_action() {
+        debug!("Member {} is compliant", member.user_id);
+        metrics::AUTO_SCAN_EVENTS.with_label_values(&["skipped"]).inc();
+        return Decision::Compliant;
+    }
+
+    metrics::AUTO_SCAN_EVENTS.with_label_values(&["handled"]).inc();
+
+    match carry_out(actions, guild_id, member.user_id, &decision, Trigger::AutoScan).await {
+        Ok(_) => decision,
+        Err(_) => Decision::Compliant,
+    }
+}
+
+/// Take the other roles of a group from a member who just gained one of its roles
+///
+/// @param actions Connection to Discord
+/// @param app_data Database of server configuration
+/// @param history Roles of members as of their previous update
+/// @param guild_id ID of the member's server
+/// @param member Member as of the update
+/// @param previous Roles the member held before the update, if the bot's cache knows them
+///
+/// @return Roles removed from the member
+pub async fn enforce_groups(
+    actions: &dyn GuildActions,
+    app_data: &mut AppData,
+    history: &RoleHistory,
+    guild_id: GuildId,
+    member: &MemberSnapshot,
+    previous: Option<Vec<RoleId>>,
+) -> Vec<RoleId> {
+    let groups = app_data.get_role_groups(&guild_id);
+    if groups.is_empty() || member.bot {
+        return Vec::new();
+    }
+
+    let previous = history.replace(guild_id, member.user_id, member.roles.clone()).or(previous);
+    let roles = groups::removals(&groups, previous.as_deref(), &member.roles, &app_data.get_exempt_roles(&guild_id));
+    if roles.is_empty() {
+        return Vec::new();
+    }
+
+    if carry_out(actions, guild_id, member.user_id, &Decision::RemoveRoles(roles.clone()), Trigger::RoleGroup).await.is_err() {
+        return Vec::new();
+    }
+
+    history.replace(guild_id, member.user_id, member.roles.iter().filter(|role| !roles.contains(role)).copied().collect());
+    return roles;
+}
+
+/// Call made to a fake server
+#[cfg(test)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    AddRoles(UserId, Vec<RoleId>),
+    RemoveRoles(UserId, Vec<RoleId>),
+    Timeout(UserId, Timestamp),
+    Kick(UserId),
+    SendDm(UserId, String),
+    SendMessage(ChannelId, String),
+}
+
+/// Server held in memory, recording every change made to it
+#[cfg(test)]
+#[derive(Default)]
+pub struct FakeActions {
+    pub members: std::sync::Mutex<Vec<Member>>,
+    pub actions: std::sync::Mutex<Vec<Action>>,
+}
+
+#[cfg(test)]
+impl FakeActions {
+    /// Create a server with members holding the given roles
+    ///
+    /// @param members User ID and role IDs of each member
+    pub fn with_members(members: &[(u64, &[u64])]) -> Self {
+        let members = members
+            .iter()
+            .map(|(user_id, roles)| {
+                let mut member = Member::default();
+                member.user.id = UserId::new(*user_id);
+                member.roles = roles.iter().map(|role| RoleId::new(*role)).collect();
+                member
+            })
+            .collect();
+
+        Self { members: std::sync::Mutex::new(members), ..Default::default() }
+    }
+
+    /// Get every change made so far
+    pub fn actions(&self) -> Vec<Action> {
+        self.actions.lock().unwrap().clone()
+    }
+
+    /// Change the roles of a member
+    ///
+    /// @return False if they are not in the server
+    fn update_roles(&self, user_id: UserId, update: impl FnOnce(&mut Vec<RoleId>)) -> bool {
+        let mut members = self.members.lock().unwrap();
+        let Some(member) = members.iter_mut().find(|member| member.user.id == user_id) else {
+            return false;
+        };
+
+        update(&mut member.roles);
+        true
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl GuildActions for FakeActions {
+    async fn member(&self, _guild_id: GuildId, user_id: UserId) -> serenity::Result<Member> {
+        self.members.lock().unwrap().iter().find(|member| member.user.id == user_id).cloned().ok_or(serenity::Error::Other("Unknown member"))
+    }
+
+    async fn members(&self, _guild_id: GuildId, limit: Option<u64>, after: Option<UserId>) -> serenity::Result<Vec<Member>> {
+        let mut members = self.members.lock().unwrap().clone();
+        members.sort_by_key(|member| member.user.id);
+
+        Ok(members.into_iter().filter(|member| after.is_none_or(|after| member.user.id > after)).take(limit.unwrap_or(1000) as usize).collect())
+    }
+
+    async fn add_roles(&self, _guild_id: GuildId, user_id: UserId, roles: &[RoleId]) -> serenity::Result<()> {
+        if !self.update_roles(user_id, |held| held.extend(roles.iter().filter(|role| !held.contains(role)).copied().collect::<Vec<_>>())) {
+            return Err(serenity::Error::Other("Unknown member"));
+        }
+        self.actions.lock().unwrap().push(Action::AddRoles(user_id, roles.to_vec()));
+        Ok(())
+    }
+
+    async fn remove_roles(&self, _guild_id: GuildId, user_id: UserId, roles: &[RoleId]) -> serenity::Result<()> {
+        if !self.update_roles(user_id, |held| held.retain(|role| !roles.contains(role))) {
+            return Err(serenity::Error::Other("Unknown member"));
+        }
+        self.actions.lock().unwrap().push(Action::RemoveRoles(user_id, roles.to_vec()));
+        Ok(())
+    }
+
+    async fn timeout(&self, _guild_id: GuildId, user_id: UserId, until: Timestamp) -> serenity::Result<()> {
+        if !self.update_roles(user_id, |_| ()) {
+            return Err(serenity::Error::Other("Unknown member"));
+        }
+        self.actions.lock().unwrap().push(Action::Timeout(user_id, until));
+        Ok(())
+    }
+
+    async fn kick(&self, _guild_id: GuildId, user_id: UserId, _reason: &str) -> serenity::Result<()> {
+        if !self.update_roles(user_id, |_| ()) {
+            return Err(serenity::Error::Other("Unknown member"));
+        }
+        self.members.lock().unwrap().retain(|member| member.user.id != user_id);
+        self.actions.lock().unwrap().push(Action::Kick(user_id));
+        Ok(())
+    }
+
+    async fn send_dm(&self, user_id: UserId, content: String) -> serenity::Result<()> {
+        self.actions.lock().unwrap().push(Action::SendDm(user_id, content));
+        Ok(())
+    }
+
+    async fn send_message(&self, channel_id: ChannelId, content: String) -> serenity::Result<()> {
+        self.actions.lock().unwrap().push(Action::SendMessage(channel_id, content));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::policy::PolicyAction;
+
+    const GUILD: GuildId = GuildId::new(1);
+
+    fn snapshot(user_id: u64, roles: &[u64]) -> MemberSnapshot {
+        MemberSnapshot { user_id: UserId::new(user_id), roles: roles.iter().map(|role| RoleId::new(*role)).collect(), ..Default::default() }
+    }
+
+    fn configure(app_data: &mut AppData, action: PolicyAction) {
+        app_data.new_server(&GUILD).unwrap();
+        app_data.update_server_primary_role(&GUILD, &RoleId::new(10)).unwrap();
+        app_data.add_exempt_role(&GUILD, &RoleId::new(2)).unwrap();
+        app_data.set_policy_action(&GUILD, &action).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_auto_scan() {
+        let discord = FakeActions::with_members(&[(5, &[1, 2, 3]), (6, &[10, 3])]);
+        let mut app_data = AppData::new(":memory:");
+        configure(&mut app_data, PolicyAction::RemoveAll);
+
+        let decision = auto_scan(&discord, &mut app_data, GUILD, &snapshot(5, &[1, 2, 3])).await;
+        assert_eq!(Decision::RemoveRoles(vec![RoleId::new(1), RoleId::new(3)]), decision);
+        assert_eq!(Decision::Compliant, auto_scan(&discord, &mut app_data, GUILD, &snapshot(6, &[10, 3])).await);
+
+        assert_eq!(vec![Action::RemoveRoles(UserId::new(5), vec![RoleId::new(1), RoleId::new(3)])], discord.actions());
+        assert_eq!(vec![RoleId::new(2)], discord.member(GUILD, UserId::new(5)).await.unwrap().roles);
+    }
+
+    #[tokio::test]
+    async fn test_auto_scan_skips() {
+        let discord = FakeActions::with_members(&[(5, &[1])]);
+        let mut app_data = AppData::new(":memory:");
+
+        // Unknown server, then no primary role, then auto scan disabled
+        assert_eq!(Decision::Compliant, auto_scan(&discord, &mut app_data, GUILD, &snapshot(5, &[1])).await);
+        app_data.new_server(&GUILD).unwrap();
+        assert_eq!(Decision::Compliant, auto_scan(&discord, &mut app_data, GUILD, &snapshot(5, &[1])).await);
+        app_data.update_server_primary_role(&GUILD, &RoleId::new(10)).unwrap();
+        app_data.disable_auto_scan(&GUILD).unwrap();
+        assert_eq!(Decision::Compliant, auto_scan(&discord, &mut app_data, GUILD, &snapshot(5, &[1])).await);
+
+        assert!(discord.actions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_auto_scan_actions() {
+        let discord = FakeActions::with_members(&[(5, &[1, 2]), (6, &[1]), (7, &[2])]);
+        let mut app_data = AppData::new(":memory:");
+
+        configure(&mut app_data, PolicyAction::Quarantine { role: RoleId::new(20) });
+        assert_eq!(Decision::AssignRole(RoleId::new(20)), auto_scan(&discord, &mut app_data, GUILD, &snapshot(5, &[1, 2])).await);
+        // Only exempt roles, nothing to quarantine
+        assert_eq!(Decision::Compliant, auto_scan(&discord, &mut app_data, GUILD, &snapshot(7, &[2])).await);
+
+        app_data.set_policy_action(&GUILD, &PolicyAction::Timeout { minutes: 60 }).unwrap();
+        let Decision::Timeout(until) = auto_scan(&discord, &mut app_data, GUILD, &snapshot(6, &[1])).await else {
+            panic!("Expected a timeout");
+        };
+        assert!(until.unix_timestamp() > Timestamp::now().unix_timestamp() + 59 * 60);
+
+        app_data.set_policy_action(&GUILD, &PolicyAction::Kick { after_days: 0 }).unwrap();
+        let joined = MemberSnapshot { joined_at: Some(Timestamp::now()), ..snapshot(7, &[2]) };
+        assert_eq!(Decision::Kick, auto_scan(&discord, &mut app_data, GUILD, &joined).await);
+
+        assert_eq!(vec![Action::AddRoles(UserId::new(5), vec![RoleId::new(20)]), Action::Timeout(UserId::new(6), until), Action::Kick(UserId::new(7))], discord.actions());
+        assert!(discord.member(GUILD, UserId::new(7)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_failed_action() {
+        let discord = FakeActions::default();
+
+        assert!(carry_out(&discord, GUILD, UserId::new(5), &Decision::RemoveRoles(vec![RoleId::new(1)]), Trigger::Enforce).await.is_err());
+        assert!(carry_out(&discord, GUILD, UserId::new(5), &Decision::Kick, Trigger::Enforce).await.is_err());
+        assert!(!carry_out(&discord, GUILD, UserId::new(5), &Decision::Compliant, Trigger::Enforce).await.unwrap());
+        assert!(discord.actions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_enforce_groups() {
+        let discord = FakeActions::with_members(&[(5, &[1, 2, 3]), (6, &[2, 3])]);
+        let history = RoleHistory::default();
+        let mut app_data = AppData::new(":memory:");
+        for role in [1, 2] {
+            app_data.add_group_role(&GUILD, "colour", &RoleId::new(role)).unwrap();
+        }
+
+        // The cache knew member 5 held role 1, so role 2 was just gained
+        let removed = enforce_groups(&discord, &mut app_data, &history, GUILD, &snapshot(5, &[1, 2, 3]), Some(vec![RoleId::new(1), RoleId::new(3)])).await;
+        assert_eq!(vec![RoleId::new(1)], removed);
+
+        // Later updates are compared with the roles the bot saw last
+        assert!(enforce_groups(&discord, &mut app_data, &history, GUILD, &snapshot(5, &[2, 3]), None).await.is_empty());
+        let removed = enforce_groups(&discord, &mut app_data, &history, GUILD, &snapshot(5, &[1, 2, 3]), None).await;
+        assert_eq!(vec![RoleId::new(2)], removed);
+
+        // Nothing is known about member 6, so both roles stay until the next update shows which is new
+        assert!(enforce_groups(&discord, &mut app_data, &history, GUILD, &snapshot(6, &[1, 2]), None).await.is_empty());
+        assert!(enforce_groups(&discord, &mut app_data, &history, GUILD, &snapshot(6, &[1]), None).await.is_empty());
+        assert_eq!(vec![Action::RemoveRoles(UserId::new(5), vec![RoleId::new(1)]), Action::RemoveRoles(UserId::new(5), vec![RoleId::new(2)])], discord.actions());
+    }
+}
@@ -0,0 +1,191 @@
+use std::{fs, io, path::PathBuf};
+
+use clap::Subcommand;
+use serenity::all::{GuildId, RoleId};
+
+use crate::{
+    config::{Config, TokenSource},
+    data::AppData,
+    export::{ConfigDocument, ImportMode},
+};
+
+/// Offline maintenance commands, run instead of starting the bot
+#[derive(Subcommand, Debug)]
+pub enum AdminCommand {
+    /// Inspect or repair the database
+    #[command(subcommand)]
+    Db(DbCommand),
+
+    /// Validate the configuration, token and database without connecting to Discord
+    CheckConfig,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DbCommand {
+    /// Apply any pending schema migrations
+    Migrate,
+
+    /// Show everything configured for a server
+    Show {
+        #[arg(value_parser = clap::value_parser!(u64).range(1..))]
+        guild: u64,
+    },
+
+    /// Set the primary role of a server, registering the server if needed
+    SetPrimary {
+        #[arg(value_parser = clap::value_parser!(u64).range(1..))]
+        guild: u64,
+        #[arg(value_parser = clap::value_parser!(u64).range(1..))]
+        role: u64,
+    },
+
+    /// Write the configuration of every server as a JSON document
+    Export {
+        /// File to write to, defaults to stdout
+        file: Option<PathBuf>,
+
+        /// Only export this server
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+        guild: Option<u64>,
+    },
+
+    /// Load server configuration written by export
+    Import {
+        /// File to read from, defaults to stdin
+        file: Option<PathBuf>,
+
+        /// How to combine the document with the stored configuration
+        #[arg(long, value_enum, default_value_t)]
+        mode: ImportMode,
+
+        /// Only import this server from the document
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+        guild: Option<u64>,
+    },
+}
+
+/// Run an admin command
+///
+/// @param command Command given on the command line
+/// @param config Resolved bot configuration
+///
+/// @return Nothing on success, or a message describing the failure
+pub fn run(command: AdminCommand, config: &Config) -> Result<(), String> {
+    match command {
+        AdminCommand::Db(DbCommand::Migrate) => migrate(config),
+        AdminCommand::Db(DbCommand::Show { guild }) => show(&open_database(config)?, GuildId::new(guild)),
+        AdminCommand::Db(DbCommand::SetPrimary { guild, role }) => set_primary(&mut open_database(config)?, GuildId::new(guild), RoleId::new(role)),
+        AdminCommand::Db(DbCommand::Export { file, guild }) => export(&open_database(config)?, file, guild.map(GuildId::new)),
+        AdminCommand::Db(DbCommand::Import { file, mode, guild }) => import(&mut open_database(config)?, file, mode, guild.map(GuildId::new)),
+        AdminCommand::CheckConfig => check_config(config),
+    }
+}
+
+/// Open the configured database without checking its schema
+fn connect(config: &Config) -> Result<AppData, String> {
+    AppData::open_configured(config).map_err(|error| format!("Could not open database {}: {}", database_name(config), error))
+}
+
+/// Describe the configured database, without any password in its URL
+fn database_name(config: &Config) -> String {
+    match &config.database_url {
+        Some(url) => match url.split_once('@') {
+            Some((_, host)) => format!("postgres://{}", host),
+            None => url.clone(),
+        },
+        None => config.database.display().to_string(),
+    }
+}
+
+/// Open the configured database, refusing to work on an outdated schema
+fn open_database(config: &Config) -> Result<AppData, String> {
+    let app_data = connect(config)?;
+    let pending = app_data.pending_migrations().map_err(|error| format!("Could not read the database schema version: {}", error))?;
+
+    if pending > 0 {
+        return Err(format!("Database has {} pending migrations, run `discord_bot db migrate` first", pending));
+    }
+
+    return Ok(app_data);
+}
+
+fn migrate(config: &Config) -> Result<(), String> {
+    let mut app_data = connect(config)?;
+    let applied = app_data.migrate().map_err(|error| format!("Migration failed: {}", error))?;
+
+    println!("Applied {} migrations, schema is at version {}", applied, app_data.schema_version().unwrap_or_default());
+    return Ok(());
+}
+
+fn show(app_data: &AppData, guild_id: GuildId) -> Result<(), String> {
+    let Some(config) = app_data.get_guild_config(&guild_id) else {
+        return Err(format!("Server {} is not registered", guild_id));
+    };
+
+    let exempt_roles = config.exempt_roles.iter().map(|role| role.to_string()).collect::<Vec<_>>().join(", ");
+    println!("Server:       {}", config.guild_id);
+    println!("Primary role: {}", config.primary_role.map_or("not set".to_string(), |role| role.to_string()));
+    println!("Auto scan:    {}", if config.auto_scan { "enabled" } else { "disabled" });
+    println!("Exempt roles: {}", if exempt_roles.is_empty() { "none" } else { &exempt_roles });
+    println!("Action:       {}", config.policy_action.unwrap_or_default().describe());
+
+    return Ok(());
+}
+
+fn set_primary(app_data: &mut AppData, guild_id: GuildId, role_id: RoleId) -> Result<(), String> {
+    app_data
+        .new_server(&guild_id)
+        .and_then(|_| app_data.update_server_primary_role(&guild_id, &role_id))
+        .map_err(|error| format!("Failed to update the primary role: {}", error))?;
+
+    println!("Primary role of server {} is now {}", guild_id, role_id);
+    return Ok(());
+}
+
+fn export(app_data: &AppData, file: Option<PathBuf>, guild: Option<GuildId>) -> Result<(), String> {
+    let guilds = match guild {
+        Some(guild_id) => vec![guild_id],
+        None => app_data.get_guild_ids(),
+    };
+
+    let document = ConfigDocument::export(app_data, &guilds);
+    let json = document.to_json()?;
+
+    match file {
+        Some(path) => fs::write(&path, json).map_err(|error| format!("Could not write {}: {}", path.display(), error))?,
+        None => println!("{}", json),
+    }
+
+    eprintln!("Exported {} servers", document.guilds.len());
+    return Ok(());
+}
+
+fn import(app_data: &mut AppData, file: Option<PathBuf>, mode: ImportMode, guild: Option<GuildId>) -> Result<(), String> {
+    let document = match file {
+        Some(path) => fs::read_to_string(&path).map_err(|error| format!("Could not read {}: {}", path.display(), error))?,
+        None => io::read_to_string(io::stdin()).map_err(|error| format!("Could not read stdin: {}", error))?,
+    };
+
+    let imported = ConfigDocument::parse(&document)?.import(app_data, mode, guild)?;
+
+    eprintln!("Imported {} servers", imported);
+    return Ok(());
+}
+
+fn check_config(config: &Config) -> Result<(), String> {
+    config.read_token()?;
+    open_database(config)?;
+
+    let token_source = match &config.token {
+        TokenSource::Value(_) => "given directly".to_string(),
+        TokenSource::File(path) => format!("read from {}", path.display()),
+    };
+
+    println!("Configuration is valid");
+    println!("Token:    {}", token_source);
+    println!("Database: {}", database_name(config));
+    println!("Intents:  {}", config.intents.iter_names().map(|(name, _)| name).collect::<Vec<_>>().join(", "));
+    println!("Logging:  {}", config.log_level);
+
+    return Ok(());
+}
@@ -0,0 +1,250 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::{error, info};
+use serenity::all::Timestamp;
+
+use crate::{config::BackupSettings, data::AppData};
+
+const BACKUP_PREFIX: &str = "config-";
+const BACKUP_EXTENSION: &str = ".sqlite";
+
+/// Check that a database file opens and passes SQLite's integrity check
+///
+/// @param path Database file to check
+///
+/// @return Nothing if the file is intact, or a message describing the problem
+pub fn verify(path: &Path) -> Result<(), String> {
+    let database = AppData::open(path).map_err(|error| format!("Could not open {}: {}", path.display(), error))?;
+
+    match database.integrity_check() {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(format!("{} failed the integrity check", path.display())),
+        Err(error) => Err(format!("Could not check the integrity of {}: {}", path.display(), error)),
+    }
+}
+
+/// List the backups in a directory, oldest first
+///
+/// @param directory Directory backups are written to
+pub fn list(directory: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(directory) else {
+        return Vec::new();
+    };
+
+    let mut backups: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(BACKUP_PREFIX) && name.ends_with(BACKUP_EXTENSION))
+        })
+        .collect();
+
+    // Names embed a sortable timestamp, so name order is age order
+    backups.sort();
+    backups
+}
+
+/// Take a verified backup of the database and remove backups beyond the retention limit
+///
+/// @param app_data Database to back up
+/// @param directory Directory to write the backup to
+/// @param keep Number of backups to keep, including the new one
+///
+/// @return Path of the new backup, or a message describing the failure
+pub fn create(app_data: &AppData, directory: &Path, keep: usize) -> Result<PathBuf, String> {
+    fs::create_dir_all(directory).map_err(|error| format!("Could not create backup directory {}: {}", directory.display(), error))?;
+
+    let name = format!("{}{}{}", BACKUP_PREFIX, Timestamp::now().format("%Y%m%dT%H%M%S%.3fZ"), BACKUP_EXTENSION);
+    let destination = directory.join(name);
+    let partial = destination.with_extension("partial");
+
+    // Write to a temporary name first so a half written backup is never picked up as the latest one
+    app_data.backup_to(&partial).map_err(|error| format!("Failed to write backup {}: {}", partial.display(), error))?;
+
+    if let Err(message) = verify(&partial) {
+        fs::remove_file(&partial).ok();
+        return Err(message);
+    }
+
+    fs::rename(&partial, &destination).map_err(|error| format!("Failed to move backup into place at {}: {}", destination.display(), error))?;
+
+    let backups = list(directory);
+    for old_backup in backups.iter().take(backups.len().saturating_sub(keep)) {
+        if let Err(error) = fs::remove_file(old_backup) {
+            error!("Failed to remove old backup {}: {}", old_backup.display(), error);
+        }
+    }
+
+    return Ok(destination);
+}
+
+/// Get the path of a file SQLite or a restore keeps next to the database, such as its rollback journal
+///
+/// @param database Database file
+/// @param suffix Suffix appended to the database's file name
+fn sidecar(database: &Path, suffix: &str) -> PathBuf {
+    let mut path = database.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Replace the database file with a backup, must be done before the database is opened
+///
+/// @param database Database file to overwrite
+/// @param source Backup file to restore, or "latest" for the newest backup in the backup directory
+/// @param settings Backup settings, used to find the latest backup
+///
+/// @return Path of the restored backup, or a message describing the failure
+pub fn restore(database: &Path, source: &str, settings: &BackupSettings) -> Result<PathBuf, String> {
+    let source = if source == "latest" {
+        let Some(directory) = &settings.directory else {
+            return Err("No backup directory is configured to restore the latest backup from".to_string());
+        };
+
+        list(directory).pop().ok_or(format!("No backups found in {}", directory.display()))?
+    } else {
+        PathBuf::from(source)
+    };
+
+    verify(&source)?;
+
+    // Copy next to the database and swap it in, so an interrupted restore never leaves a truncated database behind
+    let partial = sidecar(database, "-restoring");
+    let copy = || -> std::io::Result<()> {
+        fs::copy(&source, &partial)?;
+        fs::File::open(&partial)?.sync_all()
+    };
+    if let Err(error) = copy() {
+        fs::remove_file(&partial).ok();
+        return Err(format!("Failed to copy {} to {}: {}", source.display(), partial.display(), error));
+    }
+
+    // SQLite would apply journals left by the old database to the restored one
+    for suffix in ["-journal", "-wal", "-shm"] {
+        let leftover = sidecar(database, suffix);
+        match fs::remove_file(&leftover) {
+            Err(error) if error.kind() != std::io::ErrorKind::NotFound => {
+                fs::remove_file(&partial).ok();
+                return Err(format!("Failed to remove {}: {}", leftover.display(), error));
+            }
+            _ => {}
+        }
+    }
+
+    fs::rename(&partial, database).map_err(|error| format!("Failed to move {} into place at {}: {}", partial.display(), database.display(), error))?;
+
+    return Ok(source);
+}
+
+/// Back up the database on a fixed interval for as long as the bot runs
+///
+/// Each backup is taken on its own connection away from the async runtime, so commands and member updates keep using
+/// the shared database meanwhile. SQLite still makes their writes wait for the copy, up to its busy timeout
+///
+/// @param database Database file to back up
+/// @param settings Backup settings, nothing is done if no directory is configured
+pub async fn run_periodic(database: PathBuf, settings: BackupSettings) {
+    let Some(directory) = settings.directory else {
+        return;
+    };
+
+    let mut interval = tokio::time::interval(settings.interval);
+    loop {
+        interval.tick().await;
+
+        let (database, directory) = (database.clone(), directory.clone());
+        let result = tokio::task::spawn_blocking(move || {
+            let app_data = AppData::open(&database).map_err(|error| format!("Could not open {}: {}", database.display(), error))?;
+            create(&app_data, &directory, settings.keep)
+        })
+        .await;
+
+        match result {
+            Ok(Ok(path)) => info!("Backed up the database to {}", path.display()),
+            Ok(Err(message)) => error!("Database backup failed: {}", message),
+            Err(error) => error!("Database backup task failed: {}", error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serenity::all::{GuildId, RoleId};
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("discord_bot_{}_{}", name, std::process::id()));
+        fs::remove_dir_all(&directory).ok();
+        directory
+    }
+
+    #[test]
+    fn test_backup_retention() {
+        let directory = temp_dir("retention");
+        let app_data = AppData::new(":memory:");
+
+        for _ in 0..4 {
+            create(&app_data, &directory, 2).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+
+        let backups = list(&directory);
+        assert_eq!(2, backups.len());
+        assert!(backups.iter().all(|backup| verify(backup).is_ok()));
+
+        fs::remove_dir_all(&directory).ok();
+    }
+
+    #[test]
+    fn test_restore_latest() {
+        let directory = temp_dir("restore");
+        let settings = BackupSettings {
+            directory: Some(directory.clone()),
+            interval: std::time::Duration::from_secs(60),
+            keep: 3,
+        };
+
+        let mut app_data = AppData::new(":memory:");
+        app_data.new_server(&GuildId::new(1)).unwrap();
+        app_data.update_server_primary_role(&GuildId::new(1), &RoleId::new(2)).unwrap();
+        create(&app_data, &directory, settings.keep).unwrap();
+
+        let database = directory.join("restored.sqlite");
+        restore(&database, "latest", &settings).unwrap();
+        assert_eq!(Some(RoleId::new(2)), AppData::new(&database).get_primary_role(&GuildId::new(1)));
+
+        fs::write(directory.join("corrupt.sqlite"), "not a database").unwrap();
+        assert!(restore(&database, directory.join("corrupt.sqlite").to_str().unwrap(), &settings).is_err());
+
+        fs::remove_dir_all(&directory).ok();
+    }
+
+    #[test]
+    fn test_restore_over_journal() {
+        let directory = temp_dir("journal");
+        let settings = BackupSettings { directory: Some(directory.clone()), interval: std::time::Duration::from_secs(60), keep: 1 };
+
+        let mut app_data = AppData::new(":memory:");
+        app_data.new_server(&GuildId::new(1)).unwrap();
+        app_data.update_server_primary_role(&GuildId::new(1), &RoleId::new(2)).unwrap();
+        create(&app_data, &directory, settings.keep).unwrap();
+
+        // Journals of the replaced database are removed rather than applied to the backup
+        let database = directory.join("live.sqlite");
+        let journal = sidecar(&database, "-journal");
+        fs::write(&database, "old database").unwrap();
+        fs::write(&journal, "journal of the old database").unwrap();
+
+        restore(&database, "latest", &settings).unwrap();
+        assert!(!journal.exists());
+        assert!(!sidecar(&database, "-restoring").exists());
+        assert_eq!(Some(RoleId::new(2)), AppData::new(&database).get_primary_role(&GuildId::new(1)));
+
+        fs::remove_dir_all(&directory).ok();
+    }
+}
@@ -1,6 +1,9 @@
 use serenity::all::*;
 
-use crate::{commands::commands::DiscordCommand, data::AppData};
+use crate::{
+    commands::commands::{CommandResponse, DiscordCommand},
+    data::AppData,
+};
 
 pub struct ScanningCommands;
 
@@ -43,18 +46,20 @@ impl ScanningCommands{
 #[async_trait]
 impl DiscordCommand for ScanningCommands {
     
-    async fn run(&self, _ctx: &Context, command: &CommandInteraction, data: &mut AppData) -> String {
+    async fn run(&self, _ctx: &Context, command: &CommandInteraction, data: &mut AppData) -> CommandResponse {
 
-        let Some(subcommand) = command.data.options.get(0) else {
-            return "No subcommand given".to_string();
+        let Some(subcommand) = command.data.options.first() else {
+            return "No subcommand given".to_string().into();
         };
 
-        match subcommand.name.as_str() {
+        let content = match subcommand.name.as_str() {
             "enable" => ScanningCommands::enable(command, data).await,
             "disable" => ScanningCommands::disable(command, data).await,
             "status" => ScanningCommands::status(command, data).await,
             _ => "Unknown subcommand".to_string(),
-        }
+        };
+
+        return content.into();
     }
 
     fn register(&self) -> CreateCommand {
@@ -2,11 +2,65 @@ use serenity::all::*;
 
 use crate::data::AppData;
 
+/// Reply sent back to the user after handling an interaction, always ephemeral
+pub enum CommandResponse {
+    /// Plain text message
+    Message(String),
+    /// Rich embed
+    Embed(Box<CreateEmbed>),
+    /// Text message with a file attached
+    File(String, CreateAttachment),
+    /// Form for the user to fill in, only allowed in reply to commands and components
+    Modal(CreateModal),
+    /// New text for the message the component belongs to, dropping its components, only allowed in reply to components
+    UpdateMessage(String),
+}
+
+impl From<String> for CommandResponse {
+    fn from(content: String) -> Self {
+        CommandResponse::Message(content)
+    }
+}
+
+impl From<CreateEmbed> for CommandResponse {
+    fn from(embed: CreateEmbed) -> Self {
+        CommandResponse::Embed(Box::new(embed))
+    }
+}
+
+impl CommandResponse {
+    /// Build the interaction response to send to Discord
+    pub fn into_interaction_response(self) -> CreateInteractionResponse {
+        let data = match self {
+            CommandResponse::Message(content) => CreateInteractionResponseMessage::new().content(content),
+            CommandResponse::Embed(embed) => CreateInteractionResponseMessage::new().embed(*embed),
+            CommandResponse::File(content, file) => CreateInteractionResponseMessage::new().content(content).add_file(file),
+            CommandResponse::Modal(modal) => return CreateInteractionResponse::Modal(modal),
+            CommandResponse::UpdateMessage(content) => {
+                return CreateInteractionResponse::UpdateMessage(CreateInteractionResponseMessage::new().content(content).components(Vec::new()));
+            }
+        };
+
+        return CreateInteractionResponse::Message(data.ephemeral(true));
+    }
+}
+
 #[async_trait]
 pub trait DiscordCommand: Send + Sync {
     fn register(&self) -> CreateCommand;
 
-    async fn run(&self, ctx: &Context, command: &CommandInteraction, data: &mut AppData) -> String;
+    async fn run(&self, ctx: &Context, command: &CommandInteraction, data: &mut AppData) -> CommandResponse;
+
+    /// Suggest values for the option the user is currently typing
+    ///
+    /// @param ctx Context object for the interaction being processed
+    /// @param command Partially filled in command, the focused option is found with `command.data.autocomplete()`
+    /// @param data Database of primary roles
+    ///
+    /// @return Suggestions to show the user, or None if this command has no autocompleted options
+    async fn autocomplete(&self, _ctx: &Context, _command: &CommandInteraction, _data: &mut AppData) -> Option<CreateAutocompleteResponse> {
+        None
+    }
 }
 
 /// Retrieve a given option from the list of provided options
@@ -24,3 +78,34 @@ pub fn get_option(name: &str, vec: &Vec<CommandDataOption>) -> Option<CommandDat
 
     return None;
 }
+
+/// Parse a list of roles typed into a string option
+///
+/// @param value Role IDs or mentions separated by spaces or commas
+///
+/// @return Parsed role IDs, or None if the list is empty or any entry is invalid
+pub fn parse_role_list(value: &str) -> Option<Vec<RoleId>> {
+    let roles = value.split([',', ' ']).filter(|entry| !entry.trim().is_empty()).map(parse_role_id).collect::<Option<Vec<_>>>()?;
+
+    if roles.is_empty() {
+        return None;
+    }
+
+    return Some(roles);
+}
+
+/// Parse a role ID typed or autocompleted into a string option
+///
+/// @param value Raw ID or role mention
+///
+/// @return Parsed role ID, or None if the value is not a valid ID
+pub fn parse_role_id(value: &str) -> Option<RoleId> {
+    let value = value.trim().trim_start_matches("<@&").trim_end_matches('>');
+    let id = value.parse::<u64>().ok()?;
+
+    if id == 0 {
+        return None;
+    }
+
+    return Some(RoleId::new(id));
+}
@@ -0,0 +1,68 @@
+use serenity::all::*;
+
+use crate::{commands::commands::CommandResponse, data::AppData};
+
+/// Separator between the routing prefix and the handler specific part of a custom ID
+pub const CUSTOM_ID_SEPARATOR: char = ':';
+
+#[async_trait]
+pub trait DiscordComponent: Send + Sync {
+    /// Handle a button or select menu interaction whose custom ID starts with this handler's prefix
+    ///
+    /// @param ctx Context object for the interaction being processed
+    /// @param interaction Component interaction being processed
+    /// @param args Remainder of the custom ID after the prefix
+    /// @param data Database of primary roles
+    ///
+    /// @return Reply to display to the user
+    async fn run_component(&self, _ctx: &Context, _interaction: &ComponentInteraction, _args: &str, _data: &mut AppData) -> CommandResponse {
+        "This component is not supported".to_string().into()
+    }
+
+    /// Handle a modal submission whose custom ID starts with this handler's prefix
+    ///
+    /// @param ctx Context object for the interaction being processed
+    /// @param interaction Modal submission being processed
+    /// @param args Remainder of the custom ID after the prefix
+    /// @param data Database of primary roles
+    ///
+    /// @return Reply to display to the user
+    async fn run_modal(&self, _ctx: &Context, _interaction: &ModalInteraction, _args: &str, _data: &mut AppData) -> CommandResponse {
+        "This form is not supported".to_string().into()
+    }
+}
+
+/// Split a custom ID into its routing prefix and the remaining arguments
+///
+/// @param custom_id Custom ID attached to the component or modal
+///
+/// @return Tuple of (prefix, args), args is empty if there is no separator
+pub fn split_custom_id(custom_id: &str) -> (&str, &str) {
+    return custom_id.split_once(CUSTOM_ID_SEPARATOR).unwrap_or((custom_id, ""));
+}
+
+/// Get what the user typed into a text input of a modal
+///
+/// @param interaction Modal submission being processed
+/// @param custom_id Custom ID of the text input
+///
+/// @return Text typed, or None if the modal has no such input or it was left empty
+pub fn modal_value(interaction: &ModalInteraction, custom_id: &str) -> Option<String> {
+    return interaction.data.components.iter().flat_map(|row| &row.components).find_map(|component| match component {
+        ActionRowComponent::InputText(input) if input.custom_id == custom_id => input.value.clone().filter(|value| !value.is_empty()),
+        _ => None,
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split_custom_id() {
+        assert_eq!(("verify", ""), split_custom_id("verify"));
+        assert_eq!(("application", "approve:42"), split_custom_id("application:approve:42"));
+        assert_eq!(("", "args"), split_custom_id(":args"));
+        assert_eq!(("", ""), split_custom_id(""));
+    }
+}
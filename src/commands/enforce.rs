@@ -0,0 +1,71 @@
+use log::info;
+use serenity::all::*;
+
+use crate::{
+    actions::{self, GuildActions},
+    commands::commands::{get_option, CommandResponse, DiscordCommand},
+    data::AppData,
+    policy::{self, GuildPolicy, MemberSnapshot, Trigger},
+};
+
+pub struct EnforceCommand;
+
+impl EnforceCommand {
+    /// Apply the auto scan policy to a single member, regardless of whether auto scanning is enabled.
+    ///
+    /// @param discord Connection to Discord
+    /// @param command Command being processed
+    /// @param app_data Database of primary roles
+    ///
+    /// @return Result message to display to the user
+    async fn enforce(discord: &dyn GuildActions, command: &CommandInteraction, app_data: &mut AppData) -> String {
+        let Some(guild_id) = command.guild_id else {
+            return "No server ID was given".to_string();
+        };
+        let Some(user_id) = get_option("user", &command.data.options).and_then(|option| option.value.as_user_id()) else {
+            return "No valid member given".to_string();
+        };
+        let dry_run = get_option("dry_run", &command.data.options).and_then(|option| option.value.as_bool()).unwrap_or(false);
+
+        let Some(policy) = GuildPolicy::load(app_data, &guild_id) else {
+            return "No primary role or role rules are set for this server".to_string();
+        };
+
+        let Ok(member) = discord.member(guild_id, user_id).await else {
+            return format!("{} is not a member of this server", user_id.mention()).to_string();
+        };
+
+        let decision = policy::decide(&MemberSnapshot::from(&member), &policy, Trigger::Enforce, Timestamp::now());
+        if !decision.is_action() {
+            return format!("{} is compliant, nothing to do", user_id.mention()).to_string();
+        }
+
+        if dry_run {
+            return format!("Dry run for {}: {}", user_id.mention(), decision.describe()).to_string();
+        }
+
+        if actions::carry_out(discord, guild_id, user_id, &decision, Trigger::Enforce).await.is_err() {
+            return format!("Failed to apply the policy to {}", user_id.mention()).to_string();
+        }
+
+        info!("Applied the policy to {} on request of {}", user_id, command.user.id);
+        return format!("Applied the policy to {}: {}", user_id.mention(), decision.describe()).to_string();
+    }
+}
+
+#[async_trait]
+impl DiscordCommand for EnforceCommand {
+    async fn run(&self, ctx: &Context, command: &CommandInteraction, app_data: &mut AppData) -> CommandResponse {
+        EnforceCommand::enforce(&*ctx.http, command, app_data).await.into()
+    }
+
+    /// Create the command to register with Discord
+    fn register(&self) -> CreateCommand {
+        CreateCommand::new("enforce")
+            .description("Apply the primary role policy to a single member")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .add_option(CreateCommandOption::new(CommandOptionType::User, "user", "Member to enforce the policy on").required(true))
+            .add_option(CreateCommandOption::new(CommandOptionType::Boolean, "dry_run", "Only report what would be done"))
+            .add_context(InteractionContext::Guild)
+    }
+}
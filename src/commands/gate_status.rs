@@ -0,0 +1,150 @@
+use serenity::all::*;
+
+use crate::{
+    actions::GuildActions,
+    commands::commands::{CommandResponse, DiscordCommand},
+    data::AppData,
+    policy::{self, Decision, GuildPolicy, MemberSnapshot, Trigger},
+};
+
+pub struct GateStatusCommand;
+
+impl GateStatusCommand {
+    /// Format a list of roles for display in an embed field
+    fn format_roles(roles: &[RoleId]) -> String {
+        if roles.is_empty() {
+            return "None".to_string();
+        }
+
+        return roles.iter().map(|role| role.mention().to_string()).collect::<Vec<_>>().join(", ");
+    }
+
+    async fn status(discord: &dyn GuildActions, command: &CommandInteraction, data: &mut AppData) -> Result<CreateEmbed, String> {
+        let Some(guild_id) = command.guild_id else {
+            return Err("No server ID found".to_string());
+        };
+        let Some(ResolvedTarget::User(user, _)) = command.data.target() else {
+            return Err("No member selected".to_string());
+        };
+
+        return GateStatusCommand::member_status(discord, guild_id, user.id, data).await;
+    }
+
+    /// Explain how the role gate applies to a member
+    ///
+    /// @param discord Connection to Discord
+    /// @param guild_id ID of the member's server
+    /// @param user_id ID of the member
+    /// @param data Database of primary roles
+    ///
+    /// @return Embed describing the member's status, or an error message
+    async fn member_status(discord: &dyn GuildActions, guild_id: GuildId, user_id: UserId, data: &mut AppData) -> Result<CreateEmbed, String> {
+        let Ok(member) = discord.member(guild_id, user_id).await else {
+            return Err(format!("{} is not a member of this server", user_id.mention()));
+        };
+
+        let auto_scan = data.is_auto_scan_enabled(&guild_id);
+        let exempt_roles = data.get_exempt_roles(&guild_id);
+        let held_exempt_roles: Vec<RoleId> = member.roles.iter().filter(|role| exempt_roles.contains(role)).copied().collect();
+
+        let embed = CreateEmbed::new().title(format!("Role gate status for {}", member.display_name())).field(
+            "Auto scan",
+            if auto_scan { "Enabled" } else { "Disabled" },
+            true,
+        );
+
+        let Some(policy) = GuildPolicy::load(data, &guild_id) else {
+            return Ok(embed.description("No primary role or role rules are set for this server, the bot will not remove any roles").colour(Colour::LIGHT_GREY));
+        };
+
+        let primary_role = match policy.primary_role {
+            Some(role) => format!("{} ({})", role.mention(), if member.roles.contains(&role) { "held" } else { "missing" }),
+            None => "Not set, only role rules apply".to_string(),
+        };
+        let snapshot = MemberSnapshot::from(&member);
+        let now = Timestamp::now();
+        let decision = policy::decide(&snapshot, &policy, Trigger::AutoScan, now);
+
+        let mut outcome = if !decision.is_action() {
+            "Nothing, the member is compliant".to_string()
+        } else if !auto_scan {
+            format!("Nothing while auto scan is disabled, a sweep would: {}", decision.describe())
+        } else {
+            format!("{} on their next update", decision.describe())
+        };
+
+        if policy::decide(&snapshot, &policy, Trigger::Sweep, now) == Decision::SkipBot && decision.is_action() {
+            outcome.push_str(". Sweeps skip bots");
+        }
+
+        let colour = if decision.is_action() { Colour::ORANGE } else { Colour::DARK_GREEN };
+
+        return Ok(embed
+            .field("Primary role", primary_role, true)
+            .field("Exempt roles held", GateStatusCommand::format_roles(&held_exempt_roles), false)
+            .field("Policy action", policy.action.describe(), false)
+            .field("Auto scan would", outcome, false)
+            .colour(colour));
+    }
+}
+
+#[async_trait]
+impl DiscordCommand for GateStatusCommand {
+    /// Explain how the role gate applies to the selected member
+    ///
+    /// @param ctx Context object for the command being processed
+    /// @param command Context menu command being processed
+    /// @param data Database of primary roles
+    ///
+    /// @return Embed describing the member's status, or an error message
+    async fn run(&self, ctx: &Context, command: &CommandInteraction, data: &mut AppData) -> CommandResponse {
+        match GateStatusCommand::status(&*ctx.http, command, data).await {
+            Ok(embed) => embed.into(),
+            Err(message) => message.into(),
+        }
+    }
+
+    /// Create the user context menu command to register with Discord
+    fn register(&self) -> CreateCommand {
+        CreateCommand::new("Check role gate status")
+            .kind(CommandType::User)
+            .default_member_permissions(Permissions::MANAGE_ROLES)
+            .add_context(InteractionContext::Guild)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::actions::FakeActions;
+
+    const GUILD: GuildId = GuildId::new(1);
+
+    /// Get the value of an embed field by its name
+    fn field(embed: &CreateEmbed, name: &str) -> Option<String> {
+        let embed = serde_json::to_value(embed).unwrap();
+        return embed["fields"].as_array()?.iter().find(|field| field["name"] == name).and_then(|field| field["value"].as_str().map(str::to_string));
+    }
+
+    #[tokio::test]
+    async fn test_member_status() {
+        let discord = FakeActions::with_members(&[(5, &[1, 2, 3]), (6, &[3])]);
+        let mut data = AppData::new(":memory:");
+        data.new_server(&GUILD).unwrap();
+        data.update_server_primary_role(&GUILD, &RoleId::new(1)).unwrap();
+        data.add_exempt_role(&GUILD, &RoleId::new(2)).unwrap();
+
+        let embed = GateStatusCommand::member_status(&discord, GUILD, UserId::new(5), &mut data).await.unwrap();
+        assert_eq!(Some("<@&1> (held)".to_string()), field(&embed, "Primary role"));
+        assert_eq!(Some("<@&2>".to_string()), field(&embed, "Exempt roles held"));
+        assert_eq!(Some("Nothing, the member is compliant".to_string()), field(&embed, "Auto scan would"));
+
+        let embed = GateStatusCommand::member_status(&discord, GUILD, UserId::new(6), &mut data).await.unwrap();
+        assert_eq!(Some("<@&1> (missing)".to_string()), field(&embed, "Primary role"));
+        assert!(field(&embed, "Auto scan would").unwrap().ends_with("on their next update"));
+
+        // Looking at a member changes nothing
+        assert!(discord.actions().is_empty());
+        assert_eq!(Err("<@7> is not a member of this server".to_string()), GateStatusCommand::member_status(&discord, GUILD, UserId::new(7), &mut data).await.map(|_| ()));
+    }
+}
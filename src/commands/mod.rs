@@ -1,4 +1,13 @@
 pub mod bot_management;
+#[allow(clippy::module_inception)]
 pub mod commands;
+pub mod components;
+pub mod enforce;
+pub mod gate_status;
 pub mod primary_role;
+pub mod role_groups;
+pub mod rules;
+pub mod server_config;
 pub mod sweep;
+pub mod temp_role;
+pub mod verification;
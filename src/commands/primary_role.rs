@@ -1,8 +1,11 @@
+use std::collections::HashMap;
+
 use serenity::all::*;
 
 use crate::{
-    commands::commands::{get_option, DiscordCommand},
+    commands::commands::{get_option, parse_role_id, parse_role_list, CommandResponse, DiscordCommand},
     data::AppData,
+    policy::{PolicyAction, MAX_TIMEOUT_MINUTES},
 };
 
 pub struct PrimaryRoleCommands;
@@ -15,6 +18,7 @@ impl PrimaryRoleCommands {
             return "Invalid command data".to_string();
         };
 
+        #[allow(clippy::needless_borrow)]
         let Some(new_id) = get_option("role_id", &options) else {
             return "No role ID given".to_string();
         };
@@ -54,20 +58,179 @@ impl PrimaryRoleCommands {
 
         return format!("The primary role for this server is {}", primary_role.get()).to_string();
     }
+
+    async fn exempt(ctx: &Context, guild_id: Option<GuildId>, command: &CommandDataOptionValue, data: &mut AppData) -> String {
+        let CommandDataOptionValue::SubCommandGroup(options) = command else {
+            return "Invalid command data".to_string();
+        };
+        let Some(guild_id) = guild_id else {
+            return "No server ID found".to_string();
+        };
+        let Some(subcommand) = options.first() else {
+            return "No subcommand given".to_string();
+        };
+        let CommandDataOptionValue::SubCommand(options) = &subcommand.value else {
+            return "Invalid command data".to_string();
+        };
+
+        match subcommand.name.as_str() {
+            "add" => PrimaryRoleCommands::exempt_add(ctx, guild_id, options, data).await,
+            "remove" => PrimaryRoleCommands::exempt_remove(guild_id, options, data).await,
+            "list" => PrimaryRoleCommands::exempt_list(guild_id, data).await,
+            _ => "Unknown subcommand".to_string(),
+        }
+    }
+
+    async fn exempt_add(ctx: &Context, guild_id: GuildId, options: &Vec<CommandDataOption>, data: &mut AppData) -> String {
+        let Some(role_id) = get_option("role", options).and_then(|option| option.value.as_role_id()) else {
+            return "No valid role given".to_string();
+        };
+
+        let Ok(roles) = guild_id.roles(&ctx).await else {
+            return "Failed to get list of roles from the server".to_string();
+        };
+
+        if !roles.contains_key(&role_id) {
+            return "Given role is not in this server".to_string();
+        }
+
+        if data.add_exempt_role(&guild_id, &role_id).is_err() {
+            return "Failed to add the exempt role to the database".to_string();
+        }
+
+        return format!("Role {} will no longer be removed by the bot", role_id.mention()).to_string();
+    }
+
+    async fn exempt_remove(guild_id: GuildId, options: &Vec<CommandDataOption>, data: &mut AppData) -> String {
+        let Some(role_id) = get_option("role", options).as_ref().and_then(|option| option.value.as_str()).and_then(parse_role_id) else {
+            return "No valid role given".to_string();
+        };
+
+        if !data.get_exempt_roles(&guild_id).contains(&role_id) {
+            return "Given role is not exempt".to_string();
+        }
+
+        if data.remove_exempt_role(&guild_id, &role_id).is_err() {
+            return "Failed to remove the exempt role from the database".to_string();
+        }
+
+        return format!("Role {} is no longer exempt", role_id.mention()).to_string();
+    }
+
+    async fn exempt_list(guild_id: GuildId, data: &mut AppData) -> String {
+        let exempt_roles = data.get_exempt_roles(&guild_id);
+        if exempt_roles.is_empty() {
+            return "No roles are exempt in this server".to_string();
+        }
+
+        let roles = exempt_roles.iter().map(|role| role.mention().to_string()).collect::<Vec<_>>().join(", ");
+        return format!("Exempt roles: {}", roles).to_string();
+    }
+
+    async fn action(ctx: &Context, guild_id: Option<GuildId>, command: &CommandDataOptionValue, data: &mut AppData) -> String {
+        let CommandDataOptionValue::SubCommandGroup(options) = command else {
+            return "Invalid command data".to_string();
+        };
+        let Some(guild_id) = guild_id else {
+            return "No server ID found".to_string();
+        };
+        let Some(subcommand) = options.first() else {
+            return "No subcommand given".to_string();
+        };
+        let CommandDataOptionValue::SubCommand(options) = &subcommand.value else {
+            return "Invalid command data".to_string();
+        };
+
+        let action = match subcommand.name.as_str() {
+            "show" => return format!("Members without the primary role are handled as follows: {}", data.get_policy_action(&guild_id).describe()).to_string(),
+            "remove_all" => PolicyAction::RemoveAll,
+            "remove_listed" => {
+                let Some(value) = get_option("roles", options).and_then(|option| option.value.as_str().map(str::to_string)) else {
+                    return "No roles given".to_string();
+                };
+                let Some(roles) = parse_role_list(&value) else {
+                    return "Roles must be role mentions or IDs separated by spaces or commas".to_string();
+                };
+                PolicyAction::RemoveListed { roles }
+            }
+            "quarantine" => {
+                let Some(role) = get_option("role", options).and_then(|option| option.value.as_role_id()) else {
+                    return "No valid role given".to_string();
+                };
+                let Ok(roles) = guild_id.roles(&ctx).await else {
+                    return "Failed to get list of roles from the server".to_string();
+                };
+                if !roles.contains_key(&role) {
+                    return "Given role is not in this server".to_string();
+                }
+                PolicyAction::Quarantine { role }
+            }
+            "timeout" => {
+                let Some(minutes) = get_option("minutes", options).and_then(|option| option.value.as_i64()).and_then(|minutes| u32::try_from(minutes).ok()) else {
+                    return "No valid duration given".to_string();
+                };
+                if minutes == 0 || minutes > MAX_TIMEOUT_MINUTES {
+                    return format!("Timeouts must last between 1 and {} minutes", MAX_TIMEOUT_MINUTES).to_string();
+                }
+                PolicyAction::Timeout { minutes }
+            }
+            "kick" => {
+                let Some(after_days) = get_option("after_days", options).and_then(|option| option.value.as_i64()).and_then(|days| u32::try_from(days).ok()) else {
+                    return "No valid number of days given".to_string();
+                };
+                PolicyAction::Kick { after_days }
+            }
+            _ => return "Unknown subcommand".to_string(),
+        };
+
+        if data.set_policy_action(&guild_id, &action).is_err() {
+            return "Failed to update the policy action in the database".to_string();
+        }
+
+        return format!("Members without the primary role are now handled as follows: {}", action.describe()).to_string();
+    }
 }
 
 #[async_trait]
 impl DiscordCommand for PrimaryRoleCommands {
-    async fn run(&self, ctx: &Context, command: &CommandInteraction, data: &mut AppData) -> String {
-        let Some(subcommand) = command.data.options.get(0) else {
-            return "No subcommand given".to_string();
+    async fn run(&self, ctx: &Context, command: &CommandInteraction, data: &mut AppData) -> CommandResponse {
+        let Some(subcommand) = command.data.options.first() else {
+            return "No subcommand given".to_string().into();
         };
 
-        match subcommand.name.as_str() {
+        let content = match subcommand.name.as_str() {
             "set" => PrimaryRoleCommands::set(ctx, command.guild_id, &subcommand.value, data).await,
             "get" => PrimaryRoleCommands::get(command, data).await,
+            "exempt" => PrimaryRoleCommands::exempt(ctx, command.guild_id, &subcommand.value, data).await,
+            "action" => PrimaryRoleCommands::action(ctx, command.guild_id, &subcommand.value, data).await,
             _ => "Unknown subcommand".to_string(),
+        };
+
+        return content.into();
+    }
+
+    /// Suggest only the roles currently on the exempt list when removing an exempt role
+    async fn autocomplete(&self, ctx: &Context, command: &CommandInteraction, data: &mut AppData) -> Option<CreateAutocompleteResponse> {
+        let focused = command.data.autocomplete()?;
+        let guild_id = command.guild_id?;
+
+        if focused.name != "role" || command.data.options.first().is_none_or(|subcommand| subcommand.name != "exempt") {
+            return None;
         }
+
+        // Answered on every keystroke while the database is locked, so role names come from the cache rather than the API
+        let role_names: HashMap<RoleId, String> = guild_id.to_guild_cached(&ctx.cache).map(|guild| guild.roles.iter().map(|(role_id, role)| (*role_id, role.name.clone())).collect()).unwrap_or_default();
+        let search = focused.value.to_lowercase();
+
+        let suggestions = data
+            .get_exempt_roles(&guild_id)
+            .into_iter()
+            .map(|role_id| (role_names.get(&role_id).cloned().unwrap_or_else(|| role_id.get().to_string()), role_id))
+            .filter(|(name, _)| name.to_lowercase().contains(&search))
+            .take(25)
+            .fold(CreateAutocompleteResponse::new(), |response, (name, role_id)| response.add_string_choice(name, role_id.get().to_string()));
+
+        return Some(suggestions);
     }
 
     fn register(&self) -> CreateCommand {
@@ -83,5 +246,42 @@ impl DiscordCommand for PrimaryRoleCommands {
                 "get",
                 "Get the current primary role for this server",
             ))
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::SubCommandGroup, "exempt", "Manage roles the bot will never remove")
+                    .add_sub_option(
+                        CreateCommandOption::new(CommandOptionType::SubCommand, "add", "Exempt a role from being removed")
+                            .add_sub_option(CreateCommandOption::new(CommandOptionType::Role, "role", "Role to exempt").required(true)),
+                    )
+                    .add_sub_option(
+                        CreateCommandOption::new(CommandOptionType::SubCommand, "remove", "Stop exempting a role")
+                            .add_sub_option(CreateCommandOption::new(CommandOptionType::String, "role", "Exempt role to remove").required(true).set_autocomplete(true)),
+                    )
+                    .add_sub_option(CreateCommandOption::new(CommandOptionType::SubCommand, "list", "List the exempt roles")),
+            )
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::SubCommandGroup, "action", "Choose what happens to members without the primary role")
+                    .add_sub_option(CreateCommandOption::new(CommandOptionType::SubCommand, "show", "Show the current action"))
+                    .add_sub_option(CreateCommandOption::new(CommandOptionType::SubCommand, "remove_all", "Remove every role that is not exempt"))
+                    .add_sub_option(
+                        CreateCommandOption::new(CommandOptionType::SubCommand, "remove_listed", "Only remove the listed roles")
+                            .add_sub_option(CreateCommandOption::new(CommandOptionType::String, "roles", "Role mentions or IDs separated by spaces").required(true)),
+                    )
+                    .add_sub_option(
+                        CreateCommandOption::new(CommandOptionType::SubCommand, "quarantine", "Give members a quarantine role instead of removing roles")
+                            .add_sub_option(CreateCommandOption::new(CommandOptionType::Role, "role", "Quarantine role").required(true)),
+                    )
+                    .add_sub_option(
+                        CreateCommandOption::new(CommandOptionType::SubCommand, "timeout", "Time members out instead of removing roles").add_sub_option(
+                            CreateCommandOption::new(CommandOptionType::Integer, "minutes", "Length of the timeout")
+                                .required(true)
+                                .min_int_value(1)
+                                .max_int_value(MAX_TIMEOUT_MINUTES as u64),
+                        ),
+                    )
+                    .add_sub_option(
+                        CreateCommandOption::new(CommandOptionType::SubCommand, "kick", "Kick members who have not got the primary role some days after joining")
+                            .add_sub_option(CreateCommandOption::new(CommandOptionType::Integer, "after_days", "Days members have to get the primary role").required(true).min_int_value(0)),
+                    ),
+            )
     }
 }
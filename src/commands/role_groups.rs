@@ -0,0 +1,95 @@
+use serenity::all::*;
+
+use crate::{
+    commands::commands::{get_option, CommandResponse, DiscordCommand},
+    data::AppData,
+};
+
+pub struct RoleGroupCommands;
+
+impl RoleGroupCommands {
+    async fn add(ctx: &Context, guild_id: GuildId, options: &Vec<CommandDataOption>, data: &mut AppData) -> String {
+        let Some(group) = get_option("group", options).and_then(|option| option.value.as_str().map(|name| name.trim().to_string())).filter(|name| !name.is_empty()) else {
+            return "No group name given".to_string();
+        };
+        let Some(role_id) = get_option("role", options).and_then(|option| option.value.as_role_id()) else {
+            return "No valid role given".to_string();
+        };
+
+        let Ok(roles) = guild_id.roles(&ctx).await else {
+            return "Failed to get list of roles from the server".to_string();
+        };
+
+        if !roles.contains_key(&role_id) {
+            return "Given role is not in this server".to_string();
+        }
+
+        if data.add_group_role(&guild_id, &group, &role_id).is_err() {
+            return "Failed to add the role to the group in the database".to_string();
+        }
+
+        return format!("Role {} is now in the {} group, members gaining it lose the group's other roles", role_id.mention(), group).to_string();
+    }
+
+    async fn remove(guild_id: GuildId, options: &Vec<CommandDataOption>, data: &mut AppData) -> String {
+        let Some(role_id) = get_option("role", options).and_then(|option| option.value.as_role_id()) else {
+            return "No valid role given".to_string();
+        };
+
+        match data.remove_group_role(&guild_id, &role_id) {
+            Ok(true) => format!("Role {} is no longer in a group", role_id.mention()).to_string(),
+            Ok(false) => "Given role is not in a group".to_string(),
+            Err(_) => "Failed to remove the role from its group in the database".to_string(),
+        }
+    }
+
+    async fn list(guild_id: GuildId, data: &mut AppData) -> String {
+        let groups = data.get_role_groups(&guild_id);
+        if groups.is_empty() {
+            return "No role groups are set for this server".to_string();
+        }
+
+        return groups.iter().map(|group| group.describe()).collect::<Vec<_>>().join("\n");
+    }
+}
+
+#[async_trait]
+impl DiscordCommand for RoleGroupCommands {
+    async fn run(&self, ctx: &Context, command: &CommandInteraction, data: &mut AppData) -> CommandResponse {
+        let Some(guild_id) = command.guild_id else {
+            return "No server ID found".to_string().into();
+        };
+        let Some(subcommand) = command.data.options.first() else {
+            return "No subcommand given".to_string().into();
+        };
+        let CommandDataOptionValue::SubCommand(options) = &subcommand.value else {
+            return "Invalid command data".to_string().into();
+        };
+
+        let content = match subcommand.name.as_str() {
+            "add" => RoleGroupCommands::add(ctx, guild_id, options, data).await,
+            "remove" => RoleGroupCommands::remove(guild_id, options, data).await,
+            "list" => RoleGroupCommands::list(guild_id, data).await,
+            _ => "Unknown subcommand".to_string(),
+        };
+
+        return content.into();
+    }
+
+    fn register(&self) -> CreateCommand {
+        CreateCommand::new("rolegroup")
+            .description("Manage groups of roles members may only hold one of")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::SubCommand, "add", "Add a role to a group, creating the group if needed")
+                    .add_sub_option(CreateCommandOption::new(CommandOptionType::String, "group", "Name of the group").required(true).max_length(100))
+                    .add_sub_option(CreateCommandOption::new(CommandOptionType::Role, "role", "Role to add").required(true)),
+            )
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::SubCommand, "remove", "Take a role out of its group")
+                    .add_sub_option(CreateCommandOption::new(CommandOptionType::Role, "role", "Role to take out").required(true)),
+            )
+            .add_option(CreateCommandOption::new(CommandOptionType::SubCommand, "list", "List the role groups"))
+            .add_context(InteractionContext::Guild)
+    }
+}
@@ -0,0 +1,148 @@
+use serenity::all::*;
+
+use crate::{
+    commands::commands::{get_option, parse_role_list, CommandResponse, DiscordCommand},
+    data::AppData,
+    policy::GuildPolicy,
+    rules::Rule,
+};
+
+pub struct RulesCommands;
+
+impl RulesCommands {
+    /// Build the rule described by the options of an add subcommand
+    ///
+    /// @param name Name of the subcommand
+    /// @param options Options given to the subcommand
+    ///
+    /// @return Rule to add, or a message describing the invalid option
+    fn parse(name: &str, options: &Vec<CommandDataOption>) -> Result<Rule, String> {
+        let role = get_option("role", options).and_then(|option| option.value.as_role_id());
+        let role_list = |name: &str| -> Result<Vec<RoleId>, String> {
+            let value = get_option(name, options).and_then(|option| option.value.as_str().map(str::to_string)).unwrap_or_default();
+            return parse_role_list(&value).ok_or("Roles must be role mentions or IDs separated by spaces or commas".to_string());
+        };
+
+        match name {
+            "requires" => {
+                let Some(required) = get_option("required", options).and_then(|option| option.value.as_role_id()) else {
+                    return Err("No valid required role given".to_string());
+                };
+                if role == Some(required) {
+                    return Err("A role cannot require itself".to_string());
+                }
+                Ok(Rule::Requires { role, required })
+            }
+            "requires_any" => {
+                let any_of = role_list("any_of")?;
+                if role.is_some_and(|role| any_of.contains(&role)) {
+                    return Err("A role cannot require itself".to_string());
+                }
+                Ok(Rule::RequiresAnyOf { role, any_of })
+            }
+            "exclusive" => {
+                let mut roles: Vec<RoleId> = Vec::new();
+                for role in role_list("roles")? {
+                    if !roles.contains(&role) {
+                        roles.push(role);
+                    }
+                }
+                if roles.len() < 2 {
+                    return Err("At least two different roles are needed".to_string());
+                }
+                Ok(Rule::MutuallyExclusive { roles })
+            }
+            _ => Err("Unknown subcommand".to_string()),
+        }
+    }
+
+    async fn add(guild_id: GuildId, name: &str, options: &Vec<CommandDataOption>, data: &mut AppData) -> String {
+        let rule = match RulesCommands::parse(name, options) {
+            Ok(rule) => rule,
+            Err(message) => return message,
+        };
+
+        let Ok(rule_id) = data.add_rule(&guild_id, &rule) else {
+            return "Failed to add the rule to the database".to_string();
+        };
+
+        return format!("Added rule {}: {}", rule_id, rule.describe()).to_string();
+    }
+
+    async fn remove(guild_id: GuildId, options: &Vec<CommandDataOption>, data: &mut AppData) -> String {
+        let Some(rule_id) = get_option("rule_id", options).and_then(|option| option.value.as_i64()) else {
+            return "No rule ID given".to_string();
+        };
+
+        match data.remove_rule(&guild_id, rule_id) {
+            Ok(true) => format!("Removed rule {}", rule_id).to_string(),
+            Ok(false) => format!("There is no rule {} in this server", rule_id).to_string(),
+            Err(_) => "Failed to remove the rule from the database".to_string(),
+        }
+    }
+
+    async fn list(guild_id: GuildId, data: &mut AppData) -> String {
+        let mut lines = Vec::new();
+        if let Some(policy) = GuildPolicy::load(data, &guild_id) {
+            lines.extend(policy.primary_rules().iter().map(|rule| format!("Primary role: {}", rule.describe())));
+        }
+        lines.extend(data.get_rules(&guild_id).iter().map(|(rule_id, rule)| format!("{}: {}", rule_id, rule.describe())));
+
+        if lines.is_empty() {
+            return "No rules are set for this server".to_string();
+        }
+
+        return lines.join("\n");
+    }
+}
+
+#[async_trait]
+impl DiscordCommand for RulesCommands {
+    async fn run(&self, _ctx: &Context, command: &CommandInteraction, data: &mut AppData) -> CommandResponse {
+        let Some(guild_id) = command.guild_id else {
+            return "No server ID found".to_string().into();
+        };
+        let Some(subcommand) = command.data.options.first() else {
+            return "No subcommand given".to_string().into();
+        };
+        let CommandDataOptionValue::SubCommand(options) = &subcommand.value else {
+            return "Invalid command data".to_string().into();
+        };
+
+        let content = match subcommand.name.as_str() {
+            "list" => RulesCommands::list(guild_id, data).await,
+            "remove" => RulesCommands::remove(guild_id, options, data).await,
+            name => RulesCommands::add(guild_id, name, options, data).await,
+        };
+
+        return content.into();
+    }
+
+    fn register(&self) -> CreateCommand {
+        let role_option = || CreateCommandOption::new(CommandOptionType::Role, "role", "Role the rule applies to, every role that is not exempt if left out");
+
+        CreateCommand::new("rules")
+            .description("Manage the rules members' roles must follow")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .add_option(CreateCommandOption::new(CommandOptionType::SubCommand, "list", "List the rules of this server"))
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::SubCommand, "requires", "Only allow a role alongside another role")
+                    .add_sub_option(CreateCommandOption::new(CommandOptionType::Role, "required", "Role that must be held").required(true))
+                    .add_sub_option(role_option()),
+            )
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::SubCommand, "requires_any", "Only allow a role alongside at least one of several roles")
+                    .add_sub_option(CreateCommandOption::new(CommandOptionType::String, "any_of", "Role mentions or IDs separated by spaces").required(true))
+                    .add_sub_option(role_option()),
+            )
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::SubCommand, "exclusive", "Only allow one of several roles, earlier roles win")
+                    .add_sub_option(CreateCommandOption::new(CommandOptionType::String, "roles", "Role mentions or IDs separated by spaces").required(true)),
+            )
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::SubCommand, "remove", "Remove a rule")
+                    .add_sub_option(CreateCommandOption::new(CommandOptionType::Integer, "rule_id", "Rule to remove, as shown by the list").required(true).min_int_value(1)),
+            )
+            .add_context(InteractionContext::Guild)
+    }
+}
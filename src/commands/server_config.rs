@@ -0,0 +1,109 @@
+use log::info;
+use serenity::all::*;
+
+use crate::{
+    commands::commands::{get_option, CommandResponse, DiscordCommand},
+    data::AppData,
+    export::{ConfigDocument, ImportMode},
+};
+
+pub struct ConfigCommands;
+
+/// Largest configuration document that will be downloaded for an import
+const MAX_IMPORT_SIZE: u32 = 1024 * 1024;
+
+impl ConfigCommands {
+    async fn export(guild_id: GuildId, data: &mut AppData) -> CommandResponse {
+        let document = ConfigDocument::export(data, &[guild_id]);
+        if document.guilds.is_empty() {
+            return "This server has no saved configuration".to_string().into();
+        }
+
+        let json = match document.to_json() {
+            Ok(json) => json,
+            Err(message) => return message.into(),
+        };
+
+        let file = CreateAttachment::bytes(json, format!("primary-role-{}.json", guild_id.get()));
+        return CommandResponse::File("Configuration for this server".to_string(), file);
+    }
+
+    async fn import(command: &CommandInteraction, guild_id: GuildId, options: &Vec<CommandDataOption>, data: &mut AppData) -> String {
+        let Some(attachment) = get_option("file", options).and_then(|option| option.value.as_attachment_id()).and_then(|id| command.data.resolved.attachments.get(&id)) else {
+            return "No configuration file given".to_string();
+        };
+        let mode = match get_option("mode", options).and_then(|option| option.value.as_str().map(str::to_string)) {
+            Some(name) => {
+                let Some(mode) = ImportMode::from_name(&name) else {
+                    return format!("Unknown import mode {}", name).to_string();
+                };
+                mode
+            }
+            None => ImportMode::default(),
+        };
+
+        if attachment.size > MAX_IMPORT_SIZE {
+            return "Configuration file is too large".to_string();
+        }
+
+        let Ok(contents) = attachment.download().await else {
+            return "Failed to download the configuration file".to_string();
+        };
+        let Ok(contents) = String::from_utf8(contents) else {
+            return "Configuration file is not valid text".to_string();
+        };
+
+        let document = match ConfigDocument::parse(&contents) {
+            Ok(document) => document,
+            Err(message) => return message,
+        };
+
+        // Only this server's entry is applied, role IDs from other servers would be meaningless here
+        match document.import(data, mode, Some(guild_id)) {
+            Ok(0) => "The file has no configuration for this server".to_string(),
+            Ok(_) => {
+                info!("Imported configuration for server {} using {:?} mode", guild_id.get(), mode);
+                format!("Imported the configuration for this server ({:?} mode)", mode).to_string()
+            }
+            Err(message) => message,
+        }
+    }
+}
+
+#[async_trait]
+impl DiscordCommand for ConfigCommands {
+    async fn run(&self, _ctx: &Context, command: &CommandInteraction, data: &mut AppData) -> CommandResponse {
+        let Some(guild_id) = command.guild_id else {
+            return "No server ID found".to_string().into();
+        };
+        let Some(subcommand) = command.data.options.first() else {
+            return "No subcommand given".to_string().into();
+        };
+        let CommandDataOptionValue::SubCommand(options) = &subcommand.value else {
+            return "Invalid command data".to_string().into();
+        };
+
+        match subcommand.name.as_str() {
+            "export" => ConfigCommands::export(guild_id, data).await,
+            "import" => ConfigCommands::import(command, guild_id, options, data).await.into(),
+            _ => "Unknown subcommand".to_string().into(),
+        }
+    }
+
+    fn register(&self) -> CreateCommand {
+        CreateCommand::new("config")
+            .description("Export or import the bot configuration for this server")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .add_option(CreateCommandOption::new(CommandOptionType::SubCommand, "export", "Download the configuration for this server"))
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::SubCommand, "import", "Load a configuration file for this server")
+                    .add_sub_option(CreateCommandOption::new(CommandOptionType::Attachment, "file", "Configuration file from an export").required(true))
+                    .add_sub_option(
+                        CreateCommandOption::new(CommandOptionType::String, "mode", "How to combine the file with the current configuration")
+                            .add_string_choice("Merge", "merge")
+                            .add_string_choice("Replace", "replace"),
+                    ),
+            )
+            .add_context(InteractionContext::Guild)
+    }
+}
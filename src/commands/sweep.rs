@@ -1,121 +1,604 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
 use log::{debug, error, info};
 use serenity::all::*;
+use tokio::sync::Mutex;
 
-use crate::{commands::commands::DiscordCommand, data::AppData};
+use crate::{
+    actions::{self, GuildActions},
+    commands::commands::{get_option, CommandResponse, DiscordCommand},
+    config::{ConfigKey, SweepSettings},
+    data::{AppData, AppDataKey},
+    metrics,
+    policy::{self, Decision, GuildPolicy, MemberSnapshot, PolicyAction, Trigger},
+    shutdown::{self, ShutdownKey},
+    status::BotStatusKey,
+};
 
 pub struct SweepCommand;
 
-const DISCORD_BATCH_SIZE: u64 = 1000;
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// How long a sweep keeps its claim on a server without renewing it, so a crashed process does not block it forever
+const SWEEP_LEASE: Duration = Duration::from_secs(120);
+
+/// Claim of this process on a running sweep or undo, stored in the shared database
+struct SweepLease {
+    app_data: Option<Arc<Mutex<AppData>>>,
+    sweep_id: i64,
+    owner: String,
+    renewed_at: Instant,
+}
+
+impl SweepLease {
+    /// Get the end of a lease taken out now
+    fn expiry(now: i64) -> i64 {
+        now + SWEEP_LEASE.as_secs() as i64
+    }
+
+    /// Extend the lease once a quarter of it has passed
+    ///
+    /// @return False if another process has taken over the server, and the sweep must stop
+    async fn renew(&mut self) -> bool {
+        let Some(app_data) = &self.app_data else {
+            return true;
+        };
+        if self.renewed_at.elapsed() < SWEEP_LEASE / 4 {
+            return true;
+        }
+
+        self.renewed_at = Instant::now();
+        let lease_until = SweepLease::expiry(Timestamp::now().unix_timestamp());
+        match app_data.lock().await.renew_sweep_lease(self.sweep_id, &self.owner, lease_until) {
+            Ok(renewed) => renewed,
+            Err(error) => {
+                // Keep going, the lease is still valid for a while and the next renewal may succeed
+                error!("Failed to renew the lease of sweep {}: {}", self.sweep_id, error);
+                true
+            }
+        }
+    }
+
+    /// Give up the claim so the server can be swept again
+    async fn release(self) {
+        if let Some(app_data) = &self.app_data {
+            app_data.lock().await.release_sweep(self.sweep_id, &self.owner).unwrap_or_else(|error| {
+                error!("Failed to release sweep {}: {}", self.sweep_id, error);
+            });
+        }
+    }
+
+    /// Mark the undone sweep as such and give up the claim
+    async fn finish_undo(self) {
+        if let Some(app_data) = &self.app_data {
+            app_data.lock().await.finish_sweep_undo(self.sweep_id, &self.owner).unwrap_or_else(|error| {
+                error!("Failed to mark sweep {} as undone: {}", self.sweep_id, error);
+            });
+        }
+    }
+}
+
+/// Restrictions on which members a sweep visits and which of their roles it removes
+#[derive(Clone, Debug, Default)]
+struct SweepFilter {
+    /// Only sweep members holding this role
+    has_role: Option<RoleId>,
+    /// Only remove this role, leaving any others in place
+    only_remove: Option<RoleId>,
+    /// Only sweep members who joined the server before this time
+    joined_before: Option<Timestamp>,
+    /// Only sweep members who joined the server after this time
+    joined_after: Option<Timestamp>,
+    /// Only sweep members whose account is at least this many days old
+    min_account_age: Option<i64>,
+    /// Accounts created after this time are too young to be swept, derived from min_account_age
+    created_before: Option<Timestamp>,
+}
+
+impl SweepFilter {
+    /// Build the filter from the options given to the sweep command
+    ///
+    /// @param options Options passed to the command
+    /// @param now Time the sweep was requested, used to evaluate the account age
+    ///
+    /// @return Filter to apply, or a message describing the invalid option
+    fn from_options(options: &Vec<CommandDataOption>, now: Timestamp) -> Result<Self, String> {
+        let date_option = |name: &str| -> Result<Option<Timestamp>, String> {
+            let Some(value) = get_option(name, options).and_then(|option| option.value.as_str().map(str::to_string)) else {
+                return Ok(None);
+            };
+
+            return parse_date(&value).map(Some).ok_or(format!("Invalid date for {}, expected YYYY-MM-DD", name));
+        };
+
+        let min_account_age = get_option("min_account_age", options).and_then(|option| option.value.as_i64());
+        let created_before = match min_account_age {
+            Some(days) => {
+                let created_before = days.checked_mul(SECONDS_PER_DAY).and_then(|age| now.unix_timestamp().checked_sub(age));
+                Some(created_before.and_then(|created_before| Timestamp::from_unix_timestamp(created_before).ok()).ok_or("Invalid minimum account age".to_string())?)
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            has_role: get_option("has_role", options).and_then(|option| option.value.as_role_id()),
+            only_remove: get_option("only_remove", options).and_then(|option| option.value.as_role_id()),
+            joined_before: date_option("joined_before")?,
+            joined_after: date_option("joined_after")?,
+            min_account_age,
+            created_before,
+        })
+    }
+
+    /// Check if a member should be visited by the sweep
+    fn includes(&self, member: &Member) -> bool {
+        if !self.has_role.is_none_or(|role| member.roles.contains(&role)) {
+            return false;
+        }
+
+        if self.joined_before.is_some() || self.joined_after.is_some() {
+            // Without a join date there is no way to tell if the member is in range, leave them alone
+            let Some(joined_at) = member.joined_at else {
+                return false;
+            };
+
+            if self.joined_before.is_some_and(|before| joined_at >= before) || self.joined_after.is_some_and(|after| joined_at <= after) {
+                return false;
+            }
+        }
+
+        return self.created_before.is_none_or(|before| *member.user.created_at() <= *before);
+    }
+
+    /// Narrow what the policy decided down to what this sweep is allowed to do
+    ///
+    /// @return The decision with only the allowed roles left to remove, Compliant if the sweep may not act on it
+    fn limit(&self, decision: Decision) -> Decision {
+        let Some(only) = self.only_remove else {
+            return decision;
+        };
+
+        // Restricting the sweep to one role only makes sense for removals, leave members alone under any other action
+        let Decision::RemoveRoles(roles) = decision else {
+            return Decision::Compliant;
+        };
+        let roles: Vec<RoleId> = roles.into_iter().filter(|role| only == *role).collect();
+        if roles.is_empty() {
+            return Decision::Compliant;
+        }
+
+        return Decision::RemoveRoles(roles);
+    }
+
+    /// Describe the applied filters for the sweep report
+    ///
+    /// @return Human readable list of filters, or None if the whole server is swept
+    fn describe(&self) -> Option<String> {
+        let mut filters = Vec::new();
+        if let Some(role) = self.has_role {
+            filters.push(format!("members with {}", role.mention()));
+        }
+        if let Some(role) = self.only_remove {
+            filters.push(format!("only removing {}", role.mention()));
+        }
+        if let Some(before) = self.joined_before {
+            filters.push(format!("joined before {}", before.format("%Y-%m-%d")));
+        }
+        if let Some(after) = self.joined_after {
+            filters.push(format!("joined after {}", after.format("%Y-%m-%d")));
+        }
+        if let Some(days) = self.min_account_age {
+            filters.push(format!("accounts at least {} days old", days));
+        }
+
+        if filters.is_empty() {
+            return None;
+        }
+
+        return Some(filters.join(", "));
+    }
+}
+
+/// Explain why a sweep or undo could not start
+fn busy_message(app_data: &AppData, guild_id: &GuildId) -> String {
+    match app_data.running_sweep(guild_id, Timestamp::now().unix_timestamp()) {
+        Ok(Some((sweep_id, owner))) => format!("Sweep {} is still running in this server on {}, wait for it to finish", sweep_id, owner),
+        _ => "Another sweep is still running in this server, wait for it to finish".to_string(),
+    }
+}
+
+/// Describe what a sweep did to members, for its report
+///
+/// @param members_changed Members who lost roles, whether to the primary role, role rules or temporary roles
+/// @param punished_members Members the policy action was applied to
+/// @param action Policy action of the server
+///
+/// @return Outcome such as "removed roles from 2 members and kicked 1 members"
+fn describe_outcome(members_changed: u64, punished_members: u64, action: &PolicyAction) -> String {
+    let mut outcome = format!("removed roles from {} members", members_changed);
+    if punished_members > 0 {
+        outcome.push_str(&format!(" and {} {} members", action.past_tense(), punished_members));
+    }
+
+    return outcome;
+}
+
+/// Parse a date given to a sweep option
+///
+/// @param value Date in YYYY-MM-DD form, or a full RFC 3339 timestamp
+///
+/// @return Parsed timestamp, or None if the value is not a valid date
+fn parse_date(value: &str) -> Option<Timestamp> {
+    let value = value.trim();
+
+    return Timestamp::parse(value).or_else(|_| Timestamp::parse(&format!("{}T00:00:00Z", value))).ok();
+}
 
 impl SweepCommand {
-    async fn sweep(ctx: Context, command: CommandInteraction, members: Vec<Member>, primary_role: RoleId) {
+    /// Get the configured sweep throttling, falling back to the defaults
+    async fn settings(ctx: &Context) -> SweepSettings {
+        return ctx.data.read().await.get::<ConfigKey>().map(|config| config.sweep.clone()).unwrap_or_default();
+    }
+
+    /// Get the name this process uses to claim sweeps
+    async fn instance_id(ctx: &Context) -> String {
+        return ctx.data.read().await.get::<ConfigKey>().map(|config| config.instance_id.clone()).unwrap_or_default();
+    }
+
+    /// Take over the lease of a sweep this process just claimed
+    async fn lease(ctx: &Context, sweep_id: i64) -> SweepLease {
+        SweepLease {
+            app_data: ctx.data.read().await.get::<AppDataKey>().cloned(),
+            sweep_id,
+            owner: SweepCommand::instance_id(ctx).await,
+            renewed_at: Instant::now(),
+        }
+    }
+
+    async fn sweep(ctx: Context, command: CommandInteraction, sweep_id: i64, members: Vec<Member>, policy: GuildPolicy, filter: SweepFilter) {
+        let _running = ctx.data.read().await.get::<BotStatusKey>().map(|status| status.start_sweep());
+        let app_data = ctx.data.read().await.get::<AppDataKey>().cloned();
+        let settings = SweepCommand::settings(&ctx).await;
+        let shutdown = ctx.data.read().await.get::<ShutdownKey>().cloned();
+        let mut lease = SweepCommand::lease(&ctx, sweep_id).await;
+        let _timer = metrics::SWEEP_DURATION.start_timer();
         let member_count = members.len();
-        let mut removed_roles: u64 = 0;
+        let mut processed_members: usize = 0;
+        let mut matched_members: u64 = 0;
+        let mut punished_members: u64 = 0;
+        let mut members_changed: u64 = 0;
+        let now = Timestamp::now();
+        let mut interruption = None;
         for member in members {
+            // Every removal so far is recorded, so stopping here leaves an undoable checkpoint
+            if shutdown.as_ref().is_some_and(|shutdown| shutdown.is_shutting_down()) {
+                interruption = Some("a bot restart");
+                break;
+            }
+            if !lease.renew().await {
+                interruption = Some("another instance of the bot taking over this server");
+                break;
+            }
+
+            processed_members += 1;
             debug!("Processing member {}", member.user.id);
+            metrics::SWEEP_MEMBERS_PROCESSED.inc();
+
+            if !filter.includes(&member) {
+                continue; // Member is outside the scope of this sweep
+            }
+
+            matched_members += 1;
+
+            let decision = match policy::decide(&MemberSnapshot::from(&member), &policy, Trigger::Sweep, now) {
+                Decision::SkipBot => {
+                    debug!("Skipping bot user {}", member.user.id);
+                    continue;
+                }
+                decision => filter.limit(decision),
+            };
+            if !decision.is_action() {
+                debug!("Skipping compliant user {}", member.user.id);
+                continue;
+            }
+
+            match &decision {
+                Decision::RemoveRoles(roles) => {
+                    // Roles are removed one at a time so those removed before a failure can still be undone
+                    let mut removed = Vec::new();
+                    for role in roles {
+                        if actions::carry_out(&*ctx.http, member.guild_id, member.user.id, &Decision::RemoveRoles(vec![*role]), Trigger::Sweep).await.is_ok() {
+                            removed.push(*role);
+                        }
+                    }
+
+                    // Keep track of what was removed so the sweep can be undone
+                    if !removed.is_empty() {
+                        members_changed += 1;
+                        if let Some(app_data) = &app_data {
+                            app_data.lock().await.record_sweep_removal(sweep_id, &member.user.id, &removed).unwrap_or_else(|error| {
+                                error!("Failed to record removed roles for {} in sweep {}: {}", member.user.id, sweep_id, error);
+                            });
+                        }
+                    }
+                }
+                _ => {
+                    if actions::carry_out(&*ctx.http, member.guild_id, member.user.id, &decision, Trigger::Sweep).await.is_ok() {
+                        punished_members += 1;
+                    }
+                }
+            }
+
+            tokio::time::sleep(settings.member_delay).await; // Avoid hitting rate limits
+        }
+
+        lease.release().await;
+
+        let outcome = describe_outcome(members_changed, punished_members, &policy.action);
+        let mut report = if let Some(reason) = interruption {
+            format!("Sweep {} was interrupted by {} after {} of {} members, {}. Run `/sweep run` again to finish it", sweep_id, reason, processed_members, member_count, outcome)
+        } else {
+            format!("Sweep {} completed sweeping through {} members, {}", sweep_id, member_count, outcome)
+        };
+        if let Some(filters) = filter.describe() {
+            report.push_str(&format!("\n{} members matched the filters: {}", matched_members, filters));
+        }
+        if members_changed > 0 {
+            report.push_str(&format!("\nUse `/sweep undo sweep_id:{}` to restore the removed roles", sweep_id));
+        }
+
+        ctx.http.send_dm(command.user.id, report).await.ok();
+        info!("Sweep {} went through {} members ({} matched), {}", sweep_id, member_count, matched_members, outcome);
+    }
+
+    /// Give back the roles removed by a previous sweep
+    ///
+    /// @param ctx Context object for the command being processed
+    /// @param command Command being processed
+    /// @param guild_id ID of the server that was swept
+    /// @param sweep_id ID of the sweep being undone
+    /// @param removals Members and the roles the sweep removed from them
+    async fn restore(ctx: Context, command: CommandInteraction, guild_id: GuildId, sweep_id: i64, removals: Vec<(UserId, Vec<RoleId>)>) {
+        let _running = ctx.data.read().await.get::<BotStatusKey>().map(|status| status.start_sweep());
+        let settings = SweepCommand::settings(&ctx).await;
+        let shutdown = ctx.data.read().await.get::<ShutdownKey>().cloned();
+        let mut lease = SweepCommand::lease(&ctx, sweep_id).await;
+        // Without the server's roles every member would be skipped as if their roles had been deleted
+        let existing_roles = match guild_id.roles(&ctx).await {
+            Ok(roles) => roles,
+            Err(error) => {
+                error!("Failed to get the roles of server {}: {}", guild_id.get(), error);
+                metrics::DISCORD_ERRORS.with_label_values(&["get_roles"]).inc();
+                lease.release().await;
+                ctx.http.send_dm(command.user.id, format!("Failed to undo sweep {}, could not get the roles of the server, nothing was restored", sweep_id)).await.ok();
+                return;
+            }
+        };
+        let member_count = removals.len();
+        let mut restored_members: u64 = 0;
+        let mut skipped_members: u64 = 0;
+        let mut interruption = None;
 
-            if member.roles.contains(&primary_role) {
-                continue; // User has the primary role, skip them
+        for (user_id, roles) in removals {
+            if shutdown.as_ref().is_some_and(|shutdown| shutdown.is_shutting_down()) {
+                interruption = Some("a bot restart");
+                break;
+            }
+            if !lease.renew().await {
+                interruption = Some("another instance of the bot taking over this server");
+                break;
             }
 
-            if member.user.bot {
-                debug!("Skipping bot user {}", member.user.id);
-                continue; // Do not touch any bots, bots aren't auto granted the primary role
+            let roles: Vec<RoleId> = roles.into_iter().filter(|role| existing_roles.contains_key(role)).collect();
+            if roles.is_empty() {
+                debug!("Skipping user {}, none of their removed roles exist anymore", user_id);
+                skipped_members += 1;
+                continue;
             }
 
-            if member.roles.is_empty() {
-                debug!("Skipping user {} with no roles", member.user.id);
+            if ctx.http.member(guild_id, user_id).await.is_err() {
+                debug!("Skipping user {}, they are no longer in the server", user_id);
+                skipped_members += 1;
                 continue;
             }
 
-            match member.remove_roles(&ctx, &member.roles).await {
+            match ctx.http.add_roles(guild_id, user_id, &roles).await {
                 Ok(_) => {
-                    info!("Removed roles from {}", member.user.id);
-                    removed_roles += 1;
+                    info!("Restored roles to {}", user_id);
+                    restored_members += 1;
                 }
                 Err(error) => {
-                    error!("Failed to remove roles from {}: {}", member.user.id, error);
+                    error!("Failed to restore roles to {}: {}", user_id, error);
+                    metrics::DISCORD_ERRORS.with_label_values(&["add_roles"]).inc();
+                    skipped_members += 1;
                     continue;
                 }
             }
 
-            tokio::time::sleep(std::time::Duration::from_millis(25)).await; // Avoid hitting rate limits
+            tokio::time::sleep(settings.member_delay).await; // Avoid hitting rate limits
         }
 
-        let created_message = CreateMessage::new().content(format!("Completed sweeping through {} members, removed roles from {} members", member_count, removed_roles));
+        // An interrupted undo can be run again, giving back roles that were already restored does nothing
+        if interruption.is_some() {
+            lease.release().await;
+        } else {
+            lease.finish_undo().await;
+        }
+
+        let report = if let Some(reason) = interruption {
+            format!(
+                "Undoing sweep {} was interrupted by {}, restored roles to {} of {} members, skipped {} members, run the undo again to finish it",
+                sweep_id, reason, restored_members, member_count, skipped_members
+            )
+        } else {
+            format!(
+                "Completed undoing sweep {}, restored roles to {} of {} members, skipped {} members",
+                sweep_id, restored_members, member_count, skipped_members
+            )
+        };
 
-        command.user.dm(&ctx, created_message).await.ok();
-        info!("Swept through {} members, removed roles from {} members", member_count, removed_roles);
+        ctx.http.send_dm(command.user.id, report).await.ok();
+        info!("Undid sweep {}, restored roles to {} of {} members", sweep_id, restored_members, member_count);
     }
-}
 
-#[async_trait]
-impl DiscordCommand for SweepCommand {
     /// Sweep through all members of a given server, purging roles from anyone without the configured primary role.
     ///
     /// @param ctx Context object for the command being processed
     /// @param command Command being processed
+    /// @param options Options given to the run subcommand
     /// @param app_data Database of primary roles
     ///
     /// @return Result message to display to the user
-    async fn run(&self, ctx: &Context, command: &CommandInteraction, app_data: &mut AppData) -> String {
+    async fn start(ctx: &Context, command: &CommandInteraction, options: &Vec<CommandDataOption>, app_data: &mut AppData) -> String {
         let Some(guild_id) = command.guild_id else {
             return "No server ID was given".to_string();
         };
 
-        let Some(member_count) = ctx.http.get_guild_with_counts(guild_id).await.map_or(None, |guild| guild.approximate_member_count) else {
+        let filter = match SweepFilter::from_options(options, Timestamp::now()) {
+            Ok(filter) => filter,
+            Err(message) => return message,
+        };
+
+        let Some(member_count) = ctx.http.get_guild_with_counts(guild_id).await.ok().and_then(|guild| guild.approximate_member_count) else {
             return "Failed to get the member count for this server".to_string();
         };
 
         info!("Member count for server {} is {}", guild_id.get(), member_count);
 
-        let Some(primary_role) = app_data.get_primary_role(&guild_id) else {
-            return "Failed to determine the primary role for this server".to_string();
+        let Some(policy) = GuildPolicy::load(app_data, &guild_id) else {
+            return "No primary role or role rules are set for this server".to_string();
         };
 
-        let Ok(member_list) = (match member_count {
-            1..DISCORD_BATCH_SIZE => guild_id.members(&ctx, None, None).await,
-            DISCORD_BATCH_SIZE.. => {
-                let mut members: Vec<Member> = Vec::new();
-                let mut last_id: Option<UserId> = None;
+        if member_count == 0 {
+            return "No members found in this server".to_string();
+        }
 
-                loop {
-                    let batch = guild_id.members(&ctx, Some(DISCORD_BATCH_SIZE), last_id).await.unwrap_or_default();
-                    let batch_size = batch.len();
+        let settings = SweepCommand::settings(ctx).await;
+        let Ok(member_list) = (if member_count < settings.batch_size {
+            ctx.http.members(guild_id, None, None).await
+        } else {
+            let mut members: Vec<Member> = Vec::new();
+            let mut last_id: Option<UserId> = None;
 
-                    if batch_size == 0 {
-                        break;
-                    }
+            loop {
+                let batch = ctx.http.members(guild_id, Some(settings.batch_size), last_id).await.unwrap_or_default();
+                let batch_size = batch.len();
 
-                    last_id = batch.last().map_or(None, |member| Some(member.user.id));
+                if batch_size == 0 {
+                    break;
+                }
 
-                    debug!("Offset is now {:?}", last_id);
-                    members.extend(batch);
+                last_id = batch.last().map(|member| member.user.id);
 
-                    if batch_size < DISCORD_BATCH_SIZE as usize {
-                        break;
-                    }
+                debug!("Offset is now {:?}", last_id);
+                members.extend(batch);
 
-                    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
-                    // Avoid hitting rate limits
+                if batch_size < settings.batch_size as usize {
+                    break;
                 }
 
-                Ok(members)
-            }
-            0 => {
-                return "No members found in this server".to_string();
+                tokio::time::sleep(settings.batch_delay).await;
+                // Avoid hitting rate limits
             }
+
+            Ok(members)
         }) else {
+            metrics::DISCORD_ERRORS.with_label_values(&["get_members"]).inc();
             return "Failed to retrieve the list of members from the server".to_string();
         };
 
         let member_count = member_list.len();
 
-        info!("Starting a sweep of {} members in server {}", member_count, guild_id.get());
+        let now = Timestamp::now().unix_timestamp();
+        let sweep_id = match app_data.start_sweep(&guild_id, &SweepCommand::instance_id(ctx).await, now, SweepLease::expiry(now)) {
+            Ok(Some(sweep_id)) => sweep_id,
+            Ok(None) => return busy_message(app_data, &guild_id),
+            Err(_) => return "Failed to record the sweep in the database".to_string(),
+        };
 
-        tokio::spawn(SweepCommand::sweep(ctx.clone(), command.clone(), member_list, primary_role));
+        info!("Starting sweep {} of {} members in server {}", sweep_id, member_count, guild_id.get());
 
-        return format!("Sweeping through {} members", member_count).to_string();
+        shutdown::spawn(ctx, SweepCommand::sweep(ctx.clone(), command.clone(), sweep_id, member_list, policy, filter.clone())).await;
+
+        if let Some(filters) = filter.describe() {
+            return format!("Sweep {} is going through {} members ({})", sweep_id, member_count, filters).to_string();
+        }
+
+        return format!("Sweep {} is going through {} members", sweep_id, member_count).to_string();
+    }
+
+    /// Restore the roles removed by a sweep, defaulting to the most recent one
+    ///
+    /// @param ctx Context object for the command being processed
+    /// @param command Command being processed
+    /// @param options Options given to the undo subcommand
+    /// @param app_data Database of primary roles
+    ///
+    /// @return Result message to display to the user
+    async fn undo(ctx: &Context, command: &CommandInteraction, options: &Vec<CommandDataOption>, app_data: &mut AppData) -> String {
+        let Some(guild_id) = command.guild_id else {
+            return "No server ID was given".to_string();
+        };
+
+        let sweep_id = match get_option("sweep_id", options).and_then(|option| option.value.as_i64()) {
+            Some(sweep_id) => sweep_id,
+            None => {
+                let Some(sweep_id) = app_data.get_last_sweep(&guild_id) else {
+                    return "There are no sweeps to undo in this server".to_string();
+                };
+                sweep_id
+            }
+        };
+
+        if !app_data.is_sweep_undoable(&guild_id, sweep_id) {
+            return format!("Sweep {} does not exist in this server or has already been undone", sweep_id).to_string();
+        }
+
+        let now = Timestamp::now().unix_timestamp();
+        match app_data.claim_sweep_undo(&guild_id, sweep_id, &SweepCommand::instance_id(ctx).await, now, SweepLease::expiry(now)) {
+            Ok(true) => {}
+            Ok(false) => return busy_message(app_data, &guild_id),
+            Err(_) => return "Failed to update the sweep in the database".to_string(),
+        }
+
+        let removals = app_data.get_sweep_removals(sweep_id);
+
+        if removals.is_empty() {
+            app_data.finish_sweep_undo(sweep_id, &SweepCommand::instance_id(ctx).await).unwrap_or_else(|error| {
+                error!("Failed to mark sweep {} as undone: {}", sweep_id, error);
+            });
+            return format!("Sweep {} did not remove any roles, nothing to undo", sweep_id).to_string();
+        }
+
+        info!("Undoing sweep {} for {} members in server {}", sweep_id, removals.len(), guild_id.get());
+
+        let member_count = removals.len();
+        shutdown::spawn(ctx, SweepCommand::restore(ctx.clone(), command.clone(), guild_id, sweep_id, removals)).await;
+
+        return format!("Restoring roles removed by sweep {} to {} members", sweep_id, member_count).to_string();
+    }
+}
+
+#[async_trait]
+impl DiscordCommand for SweepCommand {
+    async fn run(&self, ctx: &Context, command: &CommandInteraction, app_data: &mut AppData) -> CommandResponse {
+        let Some(subcommand) = command.data.options.first() else {
+            return "No subcommand given".to_string().into();
+        };
+        let CommandDataOptionValue::SubCommand(options) = &subcommand.value else {
+            return "Invalid command data".to_string().into();
+        };
+
+        let content = match subcommand.name.as_str() {
+            "run" => SweepCommand::start(ctx, command, options, app_data).await,
+            "undo" => SweepCommand::undo(ctx, command, options, app_data).await,
+            _ => "Unknown subcommand".to_string(),
+        };
+
+        return content.into();
     }
 
     /// Create the command to register with Discord
@@ -123,6 +606,94 @@ impl DiscordCommand for SweepCommand {
         CreateCommand::new("sweep")
             .description("Sweep the current server and remove roles from members without the mandatory role.")
             .default_member_permissions(Permissions::ADMINISTRATOR)
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::SubCommand, "run", "Remove roles from members without the mandatory role")
+                    .add_sub_option(CreateCommandOption::new(CommandOptionType::Role, "has_role", "Only sweep members who hold this role"))
+                    .add_sub_option(CreateCommandOption::new(CommandOptionType::Role, "only_remove", "Only remove this role, leaving any others in place"))
+                    .add_sub_option(CreateCommandOption::new(CommandOptionType::String, "joined_before", "Only sweep members who joined before this date (YYYY-MM-DD)"))
+                    .add_sub_option(CreateCommandOption::new(CommandOptionType::String, "joined_after", "Only sweep members who joined after this date (YYYY-MM-DD)"))
+                    .add_sub_option(
+                        CreateCommandOption::new(CommandOptionType::Integer, "min_account_age", "Only sweep members whose account is at least this many days old").min_int_value(0),
+                    ),
+            )
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::SubCommand, "undo", "Give back the roles removed by a sweep")
+                    .add_sub_option(CreateCommandOption::new(CommandOptionType::Integer, "sweep_id", "Sweep to undo, defaults to the most recent one").min_int_value(1)),
+            )
             .add_context(InteractionContext::Guild)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn member(user_id: u64, joined_at: Option<&str>, roles: &[u64]) -> Member {
+        let mut member = Member::default();
+        member.user.id = UserId::new(user_id);
+        member.joined_at = joined_at.and_then(parse_date);
+        member.roles = roles.iter().map(|role| RoleId::new(*role)).collect();
+
+        member
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(Some(1704067200), parse_date("2024-01-01").map(|date| date.unix_timestamp()));
+        assert_eq!(Some(1704067200), parse_date(" 2024-01-01T00:00:00Z ").map(|date| date.unix_timestamp()));
+        assert!(parse_date("yesterday").is_none());
+    }
+
+    #[test]
+    fn test_filter_includes() {
+        let filter = SweepFilter {
+            has_role: Some(RoleId::new(5)),
+            joined_before: parse_date("2024-06-01"),
+            joined_after: parse_date("2024-01-01"),
+            ..Default::default()
+        };
+
+        assert!(filter.includes(&member(1, Some("2024-03-01"), &[5])));
+        assert!(!filter.includes(&member(1, Some("2024-03-01"), &[6])));
+        assert!(!filter.includes(&member(1, Some("2024-07-01"), &[5])));
+        assert!(!filter.includes(&member(1, Some("2023-07-01"), &[5])));
+        assert!(!filter.includes(&member(1, None, &[5])));
+        assert!(SweepFilter::default().includes(&member(1, None, &[])));
+    }
+
+    #[test]
+    fn test_filter_limit() {
+        let roles = Decision::RemoveRoles(vec![RoleId::new(5), RoleId::new(6)]);
+        assert_eq!(roles, SweepFilter::default().limit(roles.clone()));
+        assert_eq!(Decision::Kick, SweepFilter::default().limit(Decision::Kick));
+
+        let filter = SweepFilter { only_remove: Some(RoleId::new(6)), ..Default::default() };
+        assert_eq!(Decision::RemoveRoles(vec![RoleId::new(6)]), filter.limit(roles));
+        assert_eq!(Decision::Compliant, filter.limit(Decision::RemoveRoles(vec![RoleId::new(5)])));
+        assert_eq!(Decision::Compliant, filter.limit(Decision::Kick));
+    }
+
+    #[test]
+    fn test_describe_outcome() {
+        let kick = PolicyAction::Kick { after_days: 7 };
+        assert_eq!("removed roles from 2 members", describe_outcome(2, 0, &PolicyAction::RemoveAll));
+        assert_eq!("removed roles from 2 members", describe_outcome(2, 0, &kick));
+        assert_eq!("removed roles from 0 members and kicked 1 members", describe_outcome(0, 1, &kick));
+    }
+
+    #[test]
+    fn test_filter_account_age() {
+        // Snowflake 0 was created at the Discord epoch, 2015-01-01
+        let now = parse_date("2015-01-11").unwrap();
+
+        let filter = SweepFilter::from_options(&Vec::new(), now).unwrap();
+        assert!(filter.created_before.is_none());
+        let age = |days: i64| vec![serde_json::from_value::<CommandDataOption>(serde_json::json!({ "name": "min_account_age", "type": 4, "value": days })).unwrap()];
+        assert_eq!(Some(now.unix_timestamp() - 5 * SECONDS_PER_DAY), SweepFilter::from_options(&age(5), now).unwrap().created_before.map(|time| time.unix_timestamp()));
+        assert_eq!("Invalid minimum account age", SweepFilter::from_options(&age(i64::MAX), now).err().unwrap());
+
+        let filter = SweepFilter { created_before: Timestamp::from_unix_timestamp(now.unix_timestamp() - 5 * SECONDS_PER_DAY).ok(), ..Default::default() };
+        assert!(filter.includes(&member(1, None, &[])));
+        assert!(!filter.includes(&member(1 << 52, None, &[])));
+    }
+}
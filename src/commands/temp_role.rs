@@ -0,0 +1,148 @@
+use log::info;
+use serenity::all::*;
+
+use crate::{
+    actions::{self, GuildActions},
+    commands::commands::{get_option, CommandResponse, DiscordCommand},
+    data::AppData,
+    policy::{Decision, Trigger},
+    temp_roles,
+};
+
+pub struct TempRoleCommands;
+
+impl TempRoleCommands {
+    /// Give a member a role that is taken away again once the duration runs out
+    ///
+    /// @param discord Connection to Discord
+    /// @param guild_id ID of the server
+    /// @param options Options given to the subcommand
+    /// @param data Database to record the expiry in
+    ///
+    /// @return Result message to display to the user
+    async fn give(discord: &dyn GuildActions, guild_id: GuildId, options: &Vec<CommandDataOption>, data: &mut AppData) -> String {
+        let Some(user_id) = get_option("user", options).and_then(|option| option.value.as_user_id()) else {
+            return "No valid member given".to_string();
+        };
+        let Some(role_id) = get_option("role", options).and_then(|option| option.value.as_role_id()) else {
+            return "No valid role given".to_string();
+        };
+        let Some(duration) = get_option("duration", options).and_then(|option| option.value.as_str().and_then(temp_roles::parse_duration)) else {
+            return "Durations are numbers followed by m, h, d or w, such as 90m or 1d12h, and at most a year".to_string();
+        };
+
+        let primary_role = data.get_primary_role(&guild_id);
+        if primary_role == Some(role_id) {
+            return "The primary role cannot be given temporarily".to_string();
+        }
+
+        let Ok(member) = discord.member(guild_id, user_id).await else {
+            return format!("{} is not a member of this server", user_id.mention()).to_string();
+        };
+        if primary_role.is_some_and(|primary_role| !member.roles.contains(&primary_role)) {
+            return format!("{} does not hold the primary role, so would lose {} straight away", user_id.mention(), role_id.mention()).to_string();
+        }
+        // A role held for good must not gain an expiry, or it would be taken away with it
+        let held_temporarily = data.get_temp_roles(&guild_id).iter().any(|temp_role| temp_role.user_id == user_id && temp_role.role_id == role_id);
+        if member.roles.contains(&role_id) && !held_temporarily {
+            return format!("{} already holds {} permanently", user_id.mention(), role_id.mention()).to_string();
+        }
+
+        // Record the expiry first, so a role given just before a restart is still taken away
+        let expires_at = Timestamp::now().unix_timestamp() + duration;
+        if data.add_temp_role(&guild_id, &user_id, &role_id, expires_at).is_err() {
+            return "Failed to record the temporary role in the database".to_string();
+        }
+
+        if discord.add_roles(guild_id, user_id, &[role_id]).await.is_err() {
+            data.remove_temp_role(&guild_id, &user_id, &role_id).ok();
+            return format!("Failed to give {} to {}", role_id.mention(), user_id.mention()).to_string();
+        }
+
+        info!("Gave {} role {} until {}", user_id, role_id, expires_at);
+        return format!("Gave {} to {} until <t:{}:f>", role_id.mention(), user_id.mention(), expires_at).to_string();
+    }
+
+    /// Take a temporary role away before it runs out
+    ///
+    /// @param discord Connection to Discord
+    /// @param guild_id ID of the server
+    /// @param options Options given to the subcommand
+    /// @param data Database holding the expiry
+    ///
+    /// @return Result message to display to the user
+    async fn revoke(discord: &dyn GuildActions, guild_id: GuildId, options: &Vec<CommandDataOption>, data: &mut AppData) -> String {
+        let Some(user_id) = get_option("user", options).and_then(|option| option.value.as_user_id()) else {
+            return "No valid member given".to_string();
+        };
+        let Some(role_id) = get_option("role", options).and_then(|option| option.value.as_role_id()) else {
+            return "No valid role given".to_string();
+        };
+
+        if !data.get_temp_roles(&guild_id).iter().any(|temp_role| temp_role.user_id == user_id && temp_role.role_id == role_id) {
+            return format!("{} does not have {} temporarily", user_id.mention(), role_id.mention()).to_string();
+        }
+
+        if actions::carry_out(discord, guild_id, user_id, &Decision::RemoveRoles(vec![role_id]), Trigger::TempRole).await.is_err() {
+            return format!("Failed to take {} from {}", role_id.mention(), user_id.mention()).to_string();
+        }
+
+        if data.remove_temp_role(&guild_id, &user_id, &role_id).is_err() {
+            return "Took the role away, but failed to forget it in the database".to_string();
+        }
+
+        return format!("Took {} from {}", role_id.mention(), user_id.mention()).to_string();
+    }
+
+    async fn list(guild_id: GuildId, data: &mut AppData) -> String {
+        let temp_roles = data.get_temp_roles(&guild_id);
+        if temp_roles.is_empty() {
+            return "No temporary roles are handed out in this server".to_string();
+        }
+
+        return temp_roles.iter().map(|temp_role| temp_role.describe()).collect::<Vec<_>>().join("\n");
+    }
+}
+
+#[async_trait]
+impl DiscordCommand for TempRoleCommands {
+    async fn run(&self, ctx: &Context, command: &CommandInteraction, data: &mut AppData) -> CommandResponse {
+        let Some(guild_id) = command.guild_id else {
+            return "No server ID found".to_string().into();
+        };
+        let Some(subcommand) = command.data.options.first() else {
+            return "No subcommand given".to_string().into();
+        };
+        let CommandDataOptionValue::SubCommand(options) = &subcommand.value else {
+            return "Invalid command data".to_string().into();
+        };
+
+        let content = match subcommand.name.as_str() {
+            "give" => TempRoleCommands::give(&*ctx.http, guild_id, options, data).await,
+            "revoke" => TempRoleCommands::revoke(&*ctx.http, guild_id, options, data).await,
+            "list" => TempRoleCommands::list(guild_id, data).await,
+            _ => "Unknown subcommand".to_string(),
+        };
+
+        return content.into();
+    }
+
+    fn register(&self) -> CreateCommand {
+        CreateCommand::new("temprole")
+            .description("Give members roles that are taken away after a while")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::SubCommand, "give", "Give a member a role for a limited time")
+                    .add_sub_option(CreateCommandOption::new(CommandOptionType::User, "user", "Member to give the role to").required(true))
+                    .add_sub_option(CreateCommandOption::new(CommandOptionType::Role, "role", "Role to give").required(true))
+                    .add_sub_option(CreateCommandOption::new(CommandOptionType::String, "duration", "How long to give the role for, such as 90m, 12h or 7d").required(true)),
+            )
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::SubCommand, "revoke", "Take a temporary role away early")
+                    .add_sub_option(CreateCommandOption::new(CommandOptionType::User, "user", "Member to take the role from").required(true))
+                    .add_sub_option(CreateCommandOption::new(CommandOptionType::Role, "role", "Role to take").required(true)),
+            )
+            .add_option(CreateCommandOption::new(CommandOptionType::SubCommand, "list", "List the temporary roles and when they run out"))
+            .add_context(InteractionContext::Guild)
+    }
+}
@@ -0,0 +1,538 @@
+use std::time::{Duration, Instant};
+
+use log::{error, info};
+use serenity::all::*;
+
+use crate::{
+    actions::GuildActions,
+    commands::{
+        commands::{get_option, CommandResponse, DiscordCommand},
+        components::{modal_value, DiscordComponent, CUSTOM_ID_SEPARATOR},
+    },
+    data::AppData,
+    metrics,
+    verification::{Application, CooldownsKey, VerificationSettings, DEFAULT_COOLDOWN_SECS, MAX_FORM_INPUTS, MAX_QUESTION_LENGTH},
+};
+
+/// Prefix of the custom IDs of the verify button and its forms, as registered in the component map
+pub const VERIFY_PREFIX: &str = "verify";
+
+/// Prefix of the custom IDs of the review buttons, as registered in the component map
+pub const REVIEW_PREFIX: &str = "review";
+
+/// Custom ID arguments of the form members accept the rules and answer the questions in
+const FORM_MODAL: &str = "form";
+const RULES_INPUT: &str = "rules";
+const AGREE_INPUT: &str = "agree";
+const QUESTION_INPUT: &str = "q";
+
+/// Custom ID arguments of the review buttons, followed by the application ID
+const APPROVE: &str = "approve";
+const DENY: &str = "deny";
+
+/// Longest answer to a question, so every answer fits in one review message
+const MAX_ANSWER_LENGTH: u16 = 300;
+
+/// What members type to accept the rules, compared ignoring case
+const AGREEMENT: &str = "I agree";
+
+const DEFAULT_PANEL_MESSAGE: &str = "Click the button below to get access to the rest of the server.";
+
+pub struct VerificationCommands;
+
+impl VerificationCommands {
+    async fn panel(ctx: &Context, guild_id: GuildId, options: &Vec<CommandDataOption>, data: &mut AppData) -> String {
+        let Some(channel) = get_option("channel", options).and_then(|option| option.value.as_channel_id()) else {
+            return "No valid channel given".to_string();
+        };
+        let Some(primary_role) = data.get_primary_role(&guild_id) else {
+            return "Set a primary role with /primaryrole set before posting a verification panel".to_string();
+        };
+
+        let settings = VerificationSettings {
+            channel,
+            log_channel: get_option("log_channel", options).and_then(|option| option.value.as_channel_id()),
+            rules: get_option("rules", options).and_then(|option| option.value.as_str().map(|rules| rules.trim().to_string())).filter(|rules| !rules.is_empty()),
+            cooldown_secs: get_option("cooldown_seconds", options).and_then(|option| option.value.as_i64()).and_then(|secs| u32::try_from(secs).ok()).unwrap_or(DEFAULT_COOLDOWN_SECS),
+            questions: get_option("questions", options)
+                .and_then(|option| option.value.as_str().map(|questions| questions.split('|').map(|question| question.trim().to_string()).filter(|question| !question.is_empty()).collect()))
+                .unwrap_or_default(),
+            review_channel: get_option("review_channel", options).and_then(|option| option.value.as_channel_id()),
+        };
+        if let Some(problem) = VerificationCommands::check_questions(&settings) {
+            return problem;
+        }
+        let message = get_option("message", options).and_then(|option| option.value.as_str().map(str::to_string)).unwrap_or(DEFAULT_PANEL_MESSAGE.to_string());
+
+        let button = CreateButton::new(VERIFY_PREFIX).label("Verify").style(ButtonStyle::Success);
+        let panel = CreateMessage::new().content(message).components(vec![CreateActionRow::Buttons(vec![button])]);
+        if let Err(error) = channel.send_message(&ctx.http, panel).await {
+            error!("Failed to post the verification panel in {}: {}", channel, error);
+            metrics::DISCORD_ERRORS.with_label_values(&["send_message"]).inc();
+            return format!("Failed to post in {}, check the bot may send messages there", channel.mention()).to_string();
+        }
+
+        if data.set_verification(&guild_id, &settings).is_err() {
+            return "Posted the panel, but failed to save the verification settings in the database".to_string();
+        }
+
+        if let (Some(review_channel), false) = (settings.review_channel, settings.questions.is_empty()) {
+            return format!("Posted the verification panel in {}, members get {} once their answers are approved in {}", channel.mention(), primary_role.mention(), review_channel.mention())
+                .to_string();
+        }
+
+        return format!("Posted the verification panel in {}, members clicking it get {}", channel.mention(), primary_role.mention()).to_string();
+    }
+
+    /// Check the questions of new verification settings fit in a form and have somewhere to be reviewed
+    ///
+    /// @param settings Verification settings about to be saved
+    ///
+    /// @return Problem to display to the admin, or None if the questions are fine
+    fn check_questions(settings: &VerificationSettings) -> Option<String> {
+        if settings.questions.is_empty() {
+            return None;
+        }
+
+        let max_questions = if settings.rules.is_some() { MAX_FORM_INPUTS - 2 } else { MAX_FORM_INPUTS };
+        if settings.questions.len() > max_questions {
+            return Some(format!("A form fits at most {} questions{}", max_questions, if settings.rules.is_some() { " next to the rules" } else { "" }).to_string());
+        }
+        if let Some(question) = settings.questions.iter().find(|question| question.chars().count() > MAX_QUESTION_LENGTH) {
+            return Some(format!("Questions can be at most {} characters long, \"{}\" is too long", MAX_QUESTION_LENGTH, question).to_string());
+        }
+        if settings.review_channel.is_none() {
+            return Some("Give a review_channel for moderators to review the answers in".to_string());
+        }
+
+        return None;
+    }
+
+    async fn show(guild_id: GuildId, data: &mut AppData) -> String {
+        match data.get_verification(&guild_id) {
+            Some(settings) => settings.describe(),
+            None => "Verification is not set up in this server".to_string(),
+        }
+    }
+
+    async fn pending(guild_id: GuildId, data: &mut AppData) -> String {
+        let applications = data.get_applications(&guild_id);
+        if applications.is_empty() {
+            return "No applications are waiting for review".to_string();
+        }
+
+        let lines = applications
+            .iter()
+            .map(|application| format!("Application {} from {}, sent <t:{}:R>", application.application_id, application.user_id.mention(), application.submitted_at))
+            .collect::<Vec<_>>();
+
+        return format!("Applications waiting for review:\n{}", lines.join("\n")).to_string();
+    }
+
+    async fn disable(guild_id: GuildId, data: &mut AppData) -> String {
+        match data.remove_verification(&guild_id) {
+            Ok(true) => "Verification is turned off, posted verify buttons no longer work".to_string(),
+            Ok(false) => "Verification is not set up in this server".to_string(),
+            Err(_) => "Failed to turn off verification in the database".to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl DiscordCommand for VerificationCommands {
+    async fn run(&self, ctx: &Context, command: &CommandInteraction, data: &mut AppData) -> CommandResponse {
+        let Some(guild_id) = command.guild_id else {
+            return "No server ID found".to_string().into();
+        };
+        let Some(subcommand) = command.data.options.first() else {
+            return "No subcommand given".to_string().into();
+        };
+        let CommandDataOptionValue::SubCommand(options) = &subcommand.value else {
+            return "Invalid command data".to_string().into();
+        };
+
+        let content = match subcommand.name.as_str() {
+            "panel" => VerificationCommands::panel(ctx, guild_id, options, data).await,
+            "show" => VerificationCommands::show(guild_id, data).await,
+            "pending" => VerificationCommands::pending(guild_id, data).await,
+            "disable" => VerificationCommands::disable(guild_id, data).await,
+            _ => "Unknown subcommand".to_string(),
+        };
+
+        return content.into();
+    }
+
+    fn register(&self) -> CreateCommand {
+        CreateCommand::new("verification")
+            .description("Let members give themselves the primary role")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::SubCommand, "panel", "Post a verify button, replacing the verification settings")
+                    .add_sub_option(
+                        CreateCommandOption::new(CommandOptionType::Channel, "channel", "Channel to post the button in").channel_types(vec![ChannelType::Text]).required(true),
+                    )
+                    .add_sub_option(CreateCommandOption::new(CommandOptionType::String, "message", "Text shown above the button").max_length(2000))
+                    .add_sub_option(CreateCommandOption::new(CommandOptionType::String, "rules", "Rules members must accept first").max_length(4000))
+                    .add_sub_option(CreateCommandOption::new(CommandOptionType::Channel, "log_channel", "Channel to log verifications to").channel_types(vec![ChannelType::Text]))
+                    .add_sub_option(
+                        CreateCommandOption::new(CommandOptionType::Integer, "cooldown_seconds", "Time members must wait between clicks, 60 if left out").min_int_value(0).max_int_value(86400),
+                    )
+                    .add_sub_option(CreateCommandOption::new(CommandOptionType::String, "questions", "Questions separated by |, whose answers moderators review").max_length(500))
+                    .add_sub_option(
+                        CreateCommandOption::new(CommandOptionType::Channel, "review_channel", "Channel moderators review answers in").channel_types(vec![ChannelType::Text]),
+                    ),
+            )
+            .add_option(CreateCommandOption::new(CommandOptionType::SubCommand, "show", "Show the verification settings"))
+            .add_option(CreateCommandOption::new(CommandOptionType::SubCommand, "pending", "List the applications waiting for review"))
+            .add_option(CreateCommandOption::new(CommandOptionType::SubCommand, "disable", "Turn off verification"))
+            .add_context(InteractionContext::Guild)
+    }
+}
+
+/// Verify button posted by `/verification panel`, and the form it opens
+pub struct VerifyButton;
+
+impl VerifyButton {
+    /// Build the form members accept the rules and answer the questions in
+    fn form_modal(settings: &VerificationSettings) -> CreateModal {
+        let mut inputs = Vec::new();
+        if let Some(rules) = &settings.rules {
+            let rules = CreateInputText::new(InputTextStyle::Paragraph, "Rules", RULES_INPUT).value(rules).required(false);
+            let agree = CreateInputText::new(InputTextStyle::Short, format!("Type \"{}\" to accept the rules", AGREEMENT), AGREE_INPUT).placeholder(AGREEMENT).max_length(20);
+            inputs.extend([CreateActionRow::InputText(rules), CreateActionRow::InputText(agree)]);
+        }
+        for (index, question) in settings.questions.iter().enumerate() {
+            let answer = CreateInputText::new(InputTextStyle::Paragraph, question, format!("{}{}", QUESTION_INPUT, index)).max_length(MAX_ANSWER_LENGTH);
+            inputs.push(CreateActionRow::InputText(answer));
+        }
+
+        let title = if settings.questions.is_empty() { "Server rules" } else { "Verification" };
+        CreateModal::new(format!("{}{}{}", VERIFY_PREFIX, CUSTOM_ID_SEPARATOR, FORM_MODAL), title).components(inputs)
+    }
+
+    /// Check a member may verify, returning the reason if they may not
+    fn refuse(guild_id: GuildId, member: &Member, data: &AppData) -> Option<String> {
+        let Some(primary_role) = data.get_primary_role(&guild_id) else {
+            return Some("This server has no role to give, ask an admin for help".to_string());
+        };
+        if member.roles.contains(&primary_role) {
+            return Some("You are already verified".to_string());
+        }
+        if data.get_applications(&guild_id).iter().any(|application| application.user_id == member.user.id) {
+            return Some("Your answers are waiting for a moderator to review them".to_string());
+        }
+
+        return None;
+    }
+
+    /// Give a member who passed verification the primary role, logging it
+    ///
+    /// @param discord Connection to Discord
+    /// @param guild_id ID of the member's server
+    /// @param user_id ID of the member
+    /// @param reviewer ID of the moderator who approved the member's answers, None if the member verified themselves
+    /// @param log_channel Channel to log the verification to
+    /// @param data Database of primary roles
+    ///
+    /// @return Primary role given, or why it could not be given
+    pub async fn grant(discord: &dyn GuildActions, guild_id: GuildId, user_id: UserId, reviewer: Option<UserId>, log_channel: Option<ChannelId>, data: &mut AppData) -> Result<RoleId, String> {
+        let Some(primary_role) = data.get_primary_role(&guild_id) else {
+            return Err("the server has no primary role".to_string());
+        };
+
+        if let Err(error) = discord.add_roles(guild_id, user_id, &[primary_role]).await {
+            error!("Failed to give {} the primary role of {}: {}", user_id, guild_id, error);
+            metrics::DISCORD_ERRORS.with_label_values(&["add_roles"]).inc();
+            return Err("Discord refused to add the role".to_string());
+        }
+
+        info!("Verified {} in {}", user_id, guild_id);
+        metrics::MEMBERS_VERIFIED.inc();
+        if let Some(log_channel) = log_channel {
+            let entry = match reviewer {
+                Some(reviewer) => format!("{} was approved by {} and got {}", user_id.mention(), reviewer.mention(), primary_role.mention()),
+                None => format!("{} verified and got {}", user_id.mention(), primary_role.mention()),
+            };
+            log_to(discord, log_channel, entry).await;
+        }
+
+        return Ok(primary_role);
+    }
+
+    /// Give a member the primary role straight away, describing the outcome to them
+    async fn verify(discord: &dyn GuildActions, guild_id: GuildId, user_id: UserId, settings: &VerificationSettings, data: &mut AppData) -> String {
+        match VerifyButton::grant(discord, guild_id, user_id, None, settings.log_channel, data).await {
+            Ok(primary_role) => format!("You are verified and now have {}", primary_role.mention()).to_string(),
+            Err(reason) => format!("Could not verify you as {}, ask an admin for help", reason).to_string(),
+        }
+    }
+
+    /// Save a member's answers and post them to the review channel with buttons to approve or deny them
+    ///
+    /// @param ctx Context object for the interaction being processed
+    /// @param guild_id ID of the member's server
+    /// @param user_id ID of the member
+    /// @param settings Verification settings of the server
+    /// @param answers Each question with the member's answer
+    /// @param data Database of applications
+    ///
+    /// @return Result message to display to the member
+    async fn apply(ctx: &Context, guild_id: GuildId, user_id: UserId, settings: &VerificationSettings, answers: Vec<(String, String)>, data: &mut AppData) -> String {
+        let Some(review_channel) = settings.review_channel else {
+            return "This server has nowhere to review answers, ask an admin for help".to_string();
+        };
+
+        let submitted_at = Timestamp::now().unix_timestamp();
+        let application_id = match data.add_application(&guild_id, &user_id, &answers, submitted_at) {
+            Ok(Some(application_id)) => application_id,
+            Ok(None) => return "Your answers are waiting for a moderator to review them".to_string(),
+            Err(_) => return "Failed to save your answers, try again later".to_string(),
+        };
+        let application = Application { application_id, guild_id, user_id, answers, submitted_at };
+
+        let button = |decision: &str, label: &str, style: ButtonStyle| {
+            CreateButton::new(format!("{}{}{}{}{}", REVIEW_PREFIX, CUSTOM_ID_SEPARATOR, decision, CUSTOM_ID_SEPARATOR, application_id)).label(label).style(style)
+        };
+        let buttons = vec![button(APPROVE, "Approve", ButtonStyle::Success), button(DENY, "Deny", ButtonStyle::Danger)];
+        // Answers are typed by the member, so nothing in them may ping anyone
+        let review = CreateMessage::new().content(application.describe()).allowed_mentions(CreateAllowedMentions::new()).components(vec![CreateActionRow::Buttons(buttons)]);
+        if let Err(error) = review_channel.send_message(&ctx.http, review).await {
+            error!("Failed to post application {} in {}: {}", application_id, review_channel, error);
+            metrics::DISCORD_ERRORS.with_label_values(&["send_message"]).inc();
+            data.remove_application(application_id).ok();
+            return "Failed to send your answers to the moderators, ask an admin for help".to_string();
+        }
+
+        info!("{} sent application {} in {}", user_id, application_id, guild_id);
+        return "Thanks, your answers were sent to the moderators and you get access once they approve them".to_string();
+    }
+}
+
+/// Post a message to a log channel, logging any failure instead of returning it
+async fn log_to(discord: &dyn GuildActions, log_channel: ChannelId, entry: String) {
+    if let Err(error) = discord.send_message(log_channel, entry).await {
+        error!("Failed to log to {}: {}", log_channel, error);
+        metrics::DISCORD_ERRORS.with_label_values(&["send_message"]).inc();
+    }
+}
+
+#[async_trait]
+impl DiscordComponent for VerifyButton {
+    async fn run_component(&self, ctx: &Context, interaction: &ComponentInteraction, _args: &str, data: &mut AppData) -> CommandResponse {
+        let (Some(guild_id), Some(member)) = (interaction.guild_id, &interaction.member) else {
+            return "Verification only works in a server".to_string().into();
+        };
+        let Some(settings) = data.get_verification(&guild_id) else {
+            return "Verification is turned off in this server".to_string().into();
+        };
+        if let Some(reason) = VerifyButton::refuse(guild_id, member, data) {
+            return reason.into();
+        }
+
+        let cooldowns = ctx.data.read().await.get::<CooldownsKey>().cloned();
+        let wait = cooldowns.and_then(|cooldowns| cooldowns.try_attempt(guild_id, member.user.id, Duration::from_secs(settings.cooldown_secs.into()), Instant::now()));
+        if let Some(wait) = wait {
+            return format!("Please wait {} seconds before trying again", wait.as_secs().max(1)).to_string().into();
+        }
+
+        if settings.rules.is_some() || !settings.questions.is_empty() {
+            return CommandResponse::Modal(VerifyButton::form_modal(&settings));
+        }
+
+        return VerifyButton::verify(&*ctx.http, guild_id, member.user.id, &settings, data).await.into();
+    }
+
+    async fn run_modal(&self, ctx: &Context, interaction: &ModalInteraction, args: &str, data: &mut AppData) -> CommandResponse {
+        let (Some(guild_id), Some(member)) = (interaction.guild_id, &interaction.member) else {
+            return "Verification only works in a server".to_string().into();
+        };
+        let Some(settings) = data.get_verification(&guild_id) else {
+            return "Verification is turned off in this server".to_string().into();
+        };
+        if let Some(reason) = VerifyButton::refuse(guild_id, member, data) {
+            return reason.into();
+        }
+
+        if args != FORM_MODAL {
+            return "This form is not supported".to_string().into();
+        }
+        if settings.rules.is_some() && !modal_value(interaction, AGREE_INPUT).is_some_and(|agreement| agreement.trim().eq_ignore_ascii_case(AGREEMENT)) {
+            return format!("You need to type \"{}\" to accept the rules, click Verify to try again", AGREEMENT).to_string().into();
+        }
+
+        if settings.questions.is_empty() {
+            return VerifyButton::verify(&*ctx.http, guild_id, member.user.id, &settings, data).await.into();
+        }
+
+        let answers = settings
+            .questions
+            .iter()
+            .enumerate()
+            .map(|(index, question)| (question.clone(), modal_value(interaction, &format!("{}{}", QUESTION_INPUT, index)).unwrap_or_default().trim().to_string()))
+            .collect();
+
+        return VerifyButton::apply(ctx, guild_id, member.user.id, &settings, answers, data).await.into();
+    }
+}
+
+/// Approve and Deny buttons under each application posted to the review channel
+pub struct ReviewButtons;
+
+impl ReviewButtons {
+    /// Carry out a moderator's decision on an application
+    ///
+    /// @param discord Connection to Discord
+    /// @param application Application being reviewed, already removed from the database
+    /// @param approved Whether the moderator approved the application
+    /// @param reviewer ID of the moderator
+    /// @param server_name Name of the server, for the message sent to the member
+    /// @param data Database of primary roles and verification settings
+    ///
+    /// @return Outcome to add to the review message
+    pub async fn review(discord: &dyn GuildActions, application: &Application, approved: bool, reviewer: UserId, server_name: &str, data: &mut AppData) -> String {
+        let (guild_id, user_id) = (application.guild_id, application.user_id);
+        let log_channel = data.get_verification(&guild_id).and_then(|settings| settings.log_channel);
+
+        let (outcome, notice) = if approved {
+            if let Err(reason) = VerifyButton::grant(discord, guild_id, user_id, Some(reviewer), log_channel, data).await {
+                return format!("Approved by {}, but could not verify {} as {}", reviewer.mention(), user_id.mention(), reason).to_string();
+            }
+            (format!("Approved by {}", reviewer.mention()), format!("Your application in {} was approved, welcome!", server_name))
+        } else {
+            info!("{} denied application {} in {}", reviewer, application.application_id, guild_id);
+            if let Some(log_channel) = log_channel {
+                log_to(discord, log_channel, format!("{} was denied by {}", user_id.mention(), reviewer.mention())).await;
+            }
+            (format!("Denied by {}", reviewer.mention()), format!("Your application in {} was denied", server_name))
+        };
+
+        // Members may not accept messages from the bot, which should not hold up the review
+        if let Err(error) = discord.send_dm(user_id, notice).await {
+            info!("Could not tell {} about the review of application {}: {}", user_id, application.application_id, error);
+        }
+
+        return outcome;
+    }
+}
+
+#[async_trait]
+impl DiscordComponent for ReviewButtons {
+    async fn run_component(&self, ctx: &Context, interaction: &ComponentInteraction, args: &str, data: &mut AppData) -> CommandResponse {
+        let (Some(guild_id), Some(reviewer)) = (interaction.guild_id, &interaction.member) else {
+            return "Reviews only work in a server".to_string().into();
+        };
+        if !reviewer.permissions.is_some_and(|permissions| permissions.administrator() || permissions.manage_roles()) {
+            return "You need the Manage Roles permission to review applications".to_string().into();
+        }
+
+        let Some((decision, application_id)) = args.split_once(CUSTOM_ID_SEPARATOR).and_then(|(decision, id)| Some((decision, id.parse::<i64>().ok()?))) else {
+            return "Invalid review button".to_string().into();
+        };
+        let approved = match decision {
+            APPROVE => true,
+            DENY => false,
+            _ => return "Invalid review button".to_string().into(),
+        };
+
+        // Removing the application first means two moderators clicking at once cannot both act on it
+        let application = data.get_application(application_id).filter(|application| application.guild_id == guild_id);
+        let claimed = match &application {
+            Some(_) => data.remove_application(application_id),
+            None => Ok(false),
+        };
+        let application = match (application, claimed) {
+            (Some(application), Ok(true)) => application,
+            (_, Err(_)) => return "Failed to update the application in the database".to_string().into(),
+            _ => return "This application was already reviewed".to_string().into(),
+        };
+
+        let server_name = guild_id.name(&ctx.cache).unwrap_or("the server".to_string());
+        let outcome = ReviewButtons::review(&*ctx.http, &application, approved, reviewer.user.id, &server_name, data).await;
+
+        return CommandResponse::UpdateMessage(format!("{}\n\n{}", interaction.message.content, outcome));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::actions::{Action, FakeActions};
+
+    fn settings(rules: Option<&str>, questions: &[&str], review_channel: Option<u64>) -> VerificationSettings {
+        VerificationSettings {
+            channel: ChannelId::new(2),
+            log_channel: Some(ChannelId::new(3)),
+            rules: rules.map(str::to_string),
+            cooldown_secs: 0,
+            questions: questions.iter().map(|question| question.to_string()).collect(),
+            review_channel: review_channel.map(ChannelId::new),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_grant() {
+        let (guild, user) = (GuildId::new(1), UserId::new(5));
+        let discord = FakeActions::with_members(&[(5, &[])]);
+        let mut app_data = AppData::new(":memory:");
+        let log_channel = Some(ChannelId::new(3));
+
+        assert_eq!(Err("the server has no primary role".to_string()), VerifyButton::grant(&discord, guild, user, None, log_channel, &mut app_data).await);
+
+        app_data.update_server_primary_role(&guild, &RoleId::new(10)).unwrap();
+        assert_eq!(Ok(RoleId::new(10)), VerifyButton::grant(&discord, guild, user, None, log_channel, &mut app_data).await);
+        assert_eq!(
+            vec![Action::AddRoles(user, vec![RoleId::new(10)]), Action::SendMessage(ChannelId::new(3), "<@5> verified and got <@&10>".to_string())],
+            discord.actions()
+        );
+
+        // Nothing is logged when giving the role fails
+        assert_eq!(Err("Discord refused to add the role".to_string()), VerifyButton::grant(&discord, guild, UserId::new(6), None, log_channel, &mut app_data).await);
+        assert_eq!(2, discord.actions().len());
+    }
+
+    #[test]
+    fn test_check_questions() {
+        assert_eq!(None, VerificationCommands::check_questions(&settings(Some("Be nice"), &[], None)));
+        assert_eq!(None, VerificationCommands::check_questions(&settings(Some("Be nice"), &["Why join?", "Who invited you?", "Age?"], Some(4))));
+        assert_eq!(None, VerificationCommands::check_questions(&settings(None, &["1", "2", "3", "4", "5"], Some(4))));
+
+        assert_eq!(Some("A form fits at most 3 questions next to the rules".to_string()), VerificationCommands::check_questions(&settings(Some("Be nice"), &["1", "2", "3", "4"], Some(4))));
+        assert_eq!(Some("A form fits at most 5 questions".to_string()), VerificationCommands::check_questions(&settings(None, &["1", "2", "3", "4", "5", "6"], Some(4))));
+        assert!(VerificationCommands::check_questions(&settings(None, &[&"?".repeat(46)], Some(4))).is_some_and(|problem| problem.starts_with("Questions can be at most 45")));
+        assert_eq!(Some("Give a review_channel for moderators to review the answers in".to_string()), VerificationCommands::check_questions(&settings(None, &["Why join?"], None)));
+    }
+
+    #[tokio::test]
+    async fn test_review() {
+        let guild = GuildId::new(1);
+        let (moderator, log_channel) = (UserId::new(7), ChannelId::new(3));
+        let discord = FakeActions::with_members(&[(5, &[])]);
+        let mut app_data = AppData::new(":memory:");
+        app_data.update_server_primary_role(&guild, &RoleId::new(10)).unwrap();
+        app_data.set_verification(&guild, &settings(None, &["Why join?"], Some(4))).unwrap();
+        let application = |user: u64| Application { application_id: 1, guild_id: guild, user_id: UserId::new(user), answers: Vec::new(), submitted_at: 0 };
+
+        assert_eq!("Approved by <@7>", ReviewButtons::review(&discord, &application(5), true, moderator, "Test", &mut app_data).await);
+        assert_eq!(
+            vec![
+                Action::AddRoles(UserId::new(5), vec![RoleId::new(10)]),
+                Action::SendMessage(log_channel, "<@5> was approved by <@7> and got <@&10>".to_string()),
+                Action::SendDm(UserId::new(5), "Your application in Test was approved, welcome!".to_string()),
+            ],
+            discord.actions()
+        );
+
+        assert_eq!("Denied by <@7>", ReviewButtons::review(&discord, &application(6), false, moderator, "Test", &mut app_data).await);
+        assert_eq!(
+            vec![Action::SendMessage(log_channel, "<@6> was denied by <@7>".to_string()), Action::SendDm(UserId::new(6), "Your application in Test was denied".to_string())],
+            discord.actions()[3..]
+        );
+
+        // Members who left cannot be given the role, and are not told they were approved
+        assert_eq!(
+            "Approved by <@7>, but could not verify <@6> as Discord refused to add the role",
+            ReviewButtons::review(&discord, &application(6), true, moderator, "Test", &mut app_data).await
+        );
+        assert_eq!(5, discord.actions().len());
+    }
+}
@@ -0,0 +1,574 @@
+use std::{
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use clap::{parser::ValueSource, Args, CommandFactory, FromArgMatches, Parser};
+use serde::Deserialize;
+use serenity::{all::GatewayIntents, prelude::TypeMapKey};
+
+use crate::admin::AdminCommand;
+
+const DEFAULT_TOKEN_FILE: &str = "/run/secrets/DISCORD_TOKEN";
+const DEFAULT_DATABASE_FILE: &str = "/app/data/config.sqlite";
+const DEFAULT_INTENTS: [&str; 2] = ["GUILD_MESSAGES", "GUILD_MEMBERS"];
+const DEFAULT_LOG_LEVEL: &str = "warn,discord_bot=info";
+
+/// Largest page of members Discord will return in one request
+pub const DISCORD_BATCH_SIZE: u64 = 1000;
+
+/// Command line arguments, every setting can also be given through the listed environment variable
+#[derive(Parser, Debug)]
+#[command(version, about = "Discord bot that removes roles from members without the server's primary role")]
+pub struct Cli {
+    /// TOML file to load settings from, environment variables and flags take priority over it
+    #[arg(long, env = "CONFIG_FILE")]
+    pub config: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub overrides: ConfigLayer,
+
+    /// Restore the database from a backup file before starting, or "latest" for the newest backup in the backup directory
+    #[arg(long, value_name = "FILE|latest")]
+    pub restore_backup: Option<String>,
+
+    /// Admin command to run instead of starting the bot
+    #[command(subcommand)]
+    pub command: Option<AdminCommand>,
+}
+
+/// Settings from a single source, anything left out falls through to the next source
+#[derive(Args, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigLayer {
+    /// Discord API token, takes priority over a token file given the same way
+    #[arg(long, env = "DISCORD_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+
+    /// File containing the Discord API token
+    #[arg(long, env = "DISCORD_TOKEN_FILE")]
+    token_file: Option<PathBuf>,
+
+    /// Whether the token came from the environment rather than the command line, both of which clap merges into one layer
+    #[arg(skip)]
+    #[serde(skip)]
+    token_from_env: bool,
+
+    /// Whether the token file came from the environment rather than the command line
+    #[arg(skip)]
+    #[serde(skip)]
+    token_file_from_env: bool,
+
+    /// SQLite database holding the server configuration
+    #[arg(long, env = "DATABASE_FILE")]
+    database: Option<PathBuf>,
+
+    /// PostgreSQL connection string, used instead of the SQLite database when set. Needs the postgres feature
+    #[arg(long, env = "DATABASE_URL", hide_env_values = true)]
+    database_url: Option<String>,
+
+    /// Gateway intents to request, as a comma separated list of names
+    #[arg(long, env = "GATEWAY_INTENTS", value_delimiter = ',')]
+    intents: Option<Vec<String>>,
+
+    /// Log filter, using the env_logger syntax
+    #[arg(long, env = "RUST_LOG")]
+    log_level: Option<String>,
+
+    /// Name of this process, used to tell replicas apart when they share a database. Defaults to the host name and process ID
+    #[arg(long, env = "INSTANCE_ID")]
+    instance_id: Option<String>,
+
+    /// Longest time to wait for running sweeps when shutting down, in seconds
+    #[arg(long, env = "SHUTDOWN_TIMEOUT_SECS")]
+    shutdown_timeout_secs: Option<u64>,
+
+    #[command(flatten)]
+    sweep: SweepLayer,
+
+    #[command(flatten)]
+    guild_defaults: GuildDefaultsLayer,
+
+    #[command(flatten)]
+    backup: BackupLayer,
+
+    #[command(flatten)]
+    http: HttpLayer,
+
+    #[command(flatten)]
+    sharding: ShardingLayer,
+}
+
+#[derive(Args, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SweepLayer {
+    /// Number of members to request from Discord at a time, at most 1000
+    #[arg(long = "sweep-batch-size", env = "SWEEP_BATCH_SIZE")]
+    batch_size: Option<u64>,
+
+    /// Delay between updating members during a sweep, in milliseconds
+    #[arg(long = "sweep-member-delay-ms", env = "SWEEP_MEMBER_DELAY_MS")]
+    member_delay_ms: Option<u64>,
+
+    /// Delay between requesting batches of members, in milliseconds
+    #[arg(long = "sweep-batch-delay-ms", env = "SWEEP_BATCH_DELAY_MS")]
+    batch_delay_ms: Option<u64>,
+}
+
+#[derive(Args, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct GuildDefaultsLayer {
+    /// Whether auto scanning is enabled for newly joined servers
+    #[arg(long = "default-auto-scan", env = "DEFAULT_AUTO_SCAN")]
+    auto_scan: Option<bool>,
+}
+
+#[derive(Args, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct BackupLayer {
+    /// Directory to write database backups to, backups are disabled if not set
+    #[arg(long = "backup-dir", env = "BACKUP_DIR")]
+    directory: Option<PathBuf>,
+
+    /// Time between backups, in minutes
+    #[arg(long = "backup-interval-minutes", env = "BACKUP_INTERVAL_MINUTES")]
+    interval_minutes: Option<u64>,
+
+    /// Number of backups to keep
+    #[arg(long = "backup-keep", env = "BACKUP_KEEP")]
+    keep: Option<usize>,
+}
+
+#[derive(Args, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct HttpLayer {
+    /// Address to serve the health endpoints on, the HTTP server is disabled if not set
+    #[arg(long = "http-listen", env = "HTTP_LISTEN")]
+    listen: Option<SocketAddr>,
+}
+
+#[derive(Args, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ShardingLayer {
+    /// Total number of shards, or "auto" for the number Discord recommends. A single shard is run if not set
+    #[arg(long = "shards", env = "SHARDS", value_parser = parse_shard_total)]
+    total: Option<ShardTotal>,
+
+    /// Shards run by this process, as an inclusive range such as 0-3. Every shard is run if not set
+    #[arg(long = "shard-range", env = "SHARD_RANGE")]
+    range: Option<String>,
+}
+
+/// Shard count as written in a config layer
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(untagged)]
+enum ShardTotal {
+    Count(u32),
+    Name(String),
+}
+
+fn parse_shard_total(value: &str) -> Result<ShardTotal, String> {
+    return Ok(value.parse().map_or_else(|_| ShardTotal::Name(value.to_string()), ShardTotal::Count));
+}
+
+impl ConfigLayer {
+    /// Load settings from a TOML file
+    ///
+    /// @param path Location of the file
+    ///
+    /// @return Settings in the file, or a message describing why it could not be read
+    fn from_file(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|error| format!("Could not read config file {}: {}", path.display(), error))?;
+
+        return toml::from_str(&contents).map_err(|error| format!("Could not parse config file {}: {}", path.display(), error));
+    }
+}
+
+/// Where to find the Discord API token
+#[derive(Clone, Debug, PartialEq)]
+pub enum TokenSource {
+    Value(String),
+    File(PathBuf),
+}
+
+/// Limits to keep sweeps within Discord's rate limits
+#[derive(Clone, Debug, PartialEq)]
+pub struct SweepSettings {
+    pub batch_size: u64,
+    pub member_delay: Duration,
+    pub batch_delay: Duration,
+}
+
+impl Default for SweepSettings {
+    fn default() -> Self {
+        Self {
+            batch_size: DISCORD_BATCH_SIZE,
+            member_delay: Duration::from_millis(25),
+            batch_delay: Duration::from_millis(50),
+        }
+    }
+}
+
+/// Settings applied to servers the first time the bot sees them
+#[derive(Clone, Debug, PartialEq)]
+pub struct GuildDefaults {
+    pub auto_scan: bool,
+}
+
+/// Periodic database backups
+#[derive(Clone, Debug, PartialEq)]
+pub struct BackupSettings {
+    pub directory: Option<PathBuf>,
+    pub interval: Duration,
+    pub keep: usize,
+}
+
+/// Which gateway shards this process runs
+#[derive(Clone, Debug, PartialEq)]
+pub enum Sharding {
+    /// One shard covering every server
+    Single,
+    /// As many shards as Discord recommends, all run by this process
+    Auto,
+    /// Shards `first` to `last` inclusive, out of `total`
+    Range { first: u32, last: u32, total: u32 },
+}
+
+/// Fully resolved bot configuration
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub token: TokenSource,
+    pub database: PathBuf,
+    pub database_url: Option<String>,
+    pub intents: GatewayIntents,
+    pub log_level: String,
+    pub instance_id: String,
+    pub shutdown_timeout: Duration,
+    pub sweep: SweepSettings,
+    pub guild_defaults: GuildDefaults,
+    pub backup: BackupSettings,
+    pub http_listen: Option<SocketAddr>,
+    pub sharding: Sharding,
+}
+
+/// Key to retrieve the bot configuration from the client's data
+pub struct ConfigKey;
+
+impl TypeMapKey for ConfigKey {
+    type Value = Arc<Config>;
+}
+
+impl Cli {
+    /// Parse the command line and environment, noting which of them the token settings came from
+    ///
+    /// @param args Command line arguments, starting with the program name
+    pub fn parse_with_sources<I, T>(args: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<std::ffi::OsString> + Clone,
+    {
+        let matches = Cli::command().get_matches_from(args);
+        let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|error| error.exit());
+        cli.overrides.token_from_env = matches.value_source("token") == Some(ValueSource::EnvVariable);
+        cli.overrides.token_file_from_env = matches.value_source("token_file") == Some(ValueSource::EnvVariable);
+
+        return cli;
+    }
+}
+
+impl Config {
+    /// Load the configuration from the config file, environment and command line, in increasing priority
+    ///
+    /// @param cli Parsed command line, which already includes the environment variables
+    ///
+    /// @return Resolved configuration, or every problem found with it
+    pub fn load(cli: &Cli) -> Result<Self, Vec<String>> {
+        let file = match &cli.config {
+            Some(path) => ConfigLayer::from_file(path).map_err(|error| vec![error])?,
+            None => ConfigLayer::default(),
+        };
+
+        return Config::resolve(&cli.overrides, &file);
+    }
+
+    /// Merge two layers of settings and validate the result
+    ///
+    /// @param overrides Settings that take priority
+    /// @param base Settings used when the overrides leave a value out
+    ///
+    /// @return Resolved configuration, or every problem found with it
+    fn resolve(overrides: &ConfigLayer, base: &ConfigLayer) -> Result<Self, Vec<String>> {
+        let mut errors = Vec::new();
+
+        // The command line beats the environment, which beats the config file, and within a source a token given
+        // directly beats a token file
+        let source_rank = |from_env: bool| if from_env { 1 } else { 0 };
+        let candidates = [
+            (source_rank(overrides.token_from_env), overrides.token.clone().map(TokenSource::Value)),
+            (source_rank(overrides.token_file_from_env), overrides.token_file.clone().map(TokenSource::File)),
+            (2, base.token.clone().map(TokenSource::Value)),
+            (2, base.token_file.clone().map(TokenSource::File)),
+        ];
+        let token = candidates
+            .into_iter()
+            .filter_map(|(rank, token)| Some((rank, token?)))
+            .reduce(|best, next| if next.0 < best.0 { next } else { best })
+            .map_or_else(|| TokenSource::File(PathBuf::from(DEFAULT_TOKEN_FILE)), |(_, token)| token);
+
+        let database = overrides.database.clone().or(base.database.clone()).unwrap_or_else(|| PathBuf::from(DEFAULT_DATABASE_FILE));
+        if database.as_os_str().is_empty() {
+            errors.push("Database path cannot be empty".to_string());
+        }
+
+        let database_url = overrides.database_url.clone().or(base.database_url.clone());
+        if database_url.is_some() && !cfg!(feature = "postgres") {
+            errors.push("A database URL was given, but this build does not include the postgres feature".to_string());
+        }
+
+        let intent_names = overrides.intents.clone().or(base.intents.clone()).unwrap_or_else(|| DEFAULT_INTENTS.iter().map(|name| name.to_string()).collect());
+        let mut intents = GatewayIntents::empty();
+        for name in intent_names.iter().map(|name| name.trim().to_uppercase()) {
+            match GatewayIntents::from_name(&name) {
+                Some(intent) => intents |= intent,
+                None => errors.push(format!("Unknown gateway intent {}", name)),
+            }
+        }
+        if !intents.contains(GatewayIntents::GUILD_MEMBERS) {
+            errors.push("The GUILD_MEMBERS intent is required to receive member updates".to_string());
+        }
+
+        let log_level = overrides.log_level.clone().or(base.log_level.clone()).unwrap_or_else(|| DEFAULT_LOG_LEVEL.to_string());
+        if log_level.trim().is_empty() {
+            errors.push("Log level cannot be empty".to_string());
+        }
+
+        let instance_id = overrides.instance_id.clone().or(base.instance_id.clone()).unwrap_or_else(default_instance_id);
+        if instance_id.trim().is_empty() {
+            errors.push("Instance ID cannot be empty".to_string());
+        }
+
+        let shutdown_timeout = Duration::from_secs(overrides.shutdown_timeout_secs.or(base.shutdown_timeout_secs).unwrap_or(25));
+
+        let defaults = SweepSettings::default();
+        let sweep = SweepSettings {
+            batch_size: overrides.sweep.batch_size.or(base.sweep.batch_size).unwrap_or(defaults.batch_size),
+            member_delay: overrides.sweep.member_delay_ms.or(base.sweep.member_delay_ms).map_or(defaults.member_delay, Duration::from_millis),
+            batch_delay: overrides.sweep.batch_delay_ms.or(base.sweep.batch_delay_ms).map_or(defaults.batch_delay, Duration::from_millis),
+        };
+        if !(1..=DISCORD_BATCH_SIZE).contains(&sweep.batch_size) {
+            errors.push(format!("Sweep batch size must be between 1 and {}, got {}", DISCORD_BATCH_SIZE, sweep.batch_size));
+        }
+
+        let guild_defaults = GuildDefaults {
+            auto_scan: overrides.guild_defaults.auto_scan.or(base.guild_defaults.auto_scan).unwrap_or(true),
+        };
+
+        let interval_minutes = overrides.backup.interval_minutes.or(base.backup.interval_minutes).unwrap_or(6 * 60);
+        let backup = BackupSettings {
+            directory: overrides.backup.directory.clone().or(base.backup.directory.clone()),
+            interval: interval_minutes.checked_mul(60).map_or_else(
+                || {
+                    errors.push(format!("Backup interval must be at most {} minutes, got {}", u64::MAX / 60, interval_minutes));
+                    Duration::MAX
+                },
+                Duration::from_secs,
+            ),
+            keep: overrides.backup.keep.or(base.backup.keep).unwrap_or(7),
+        };
+        if backup.interval.is_zero() {
+            errors.push("Backup interval must be at least one minute".to_string());
+        }
+        if backup.keep == 0 {
+            errors.push("At least one backup must be kept".to_string());
+        }
+        if backup.directory.is_some() && database_url.is_some() {
+            errors.push("Backups only cover the SQLite database, use pg_dump to back up PostgreSQL".to_string());
+        }
+
+        let sharding = resolve_sharding(overrides.sharding.total.as_ref().or(base.sharding.total.as_ref()), overrides.sharding.range.as_ref().or(base.sharding.range.as_ref()))
+            .unwrap_or_else(|error| {
+                errors.push(error);
+                Sharding::Single
+            });
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        return Ok(Self {
+            token,
+            database,
+            database_url,
+            intents,
+            log_level,
+            instance_id,
+            shutdown_timeout,
+            sweep,
+            guild_defaults,
+            backup,
+            http_listen: overrides.http.listen.or(base.http.listen),
+            sharding,
+        });
+    }
+
+    /// Read the Discord API token from its configured source
+    ///
+    /// @return Token, or a message describing why it is unavailable
+    pub fn read_token(&self) -> Result<String, String> {
+        let token = match &self.token {
+            TokenSource::Value(token) => token.clone(),
+            TokenSource::File(path) => fs::read_to_string(path).map_err(|error| format!("Could not read token file {}: {}", path.display(), error))?,
+        };
+
+        let token = token.trim().to_string();
+        if token.is_empty() {
+            return Err("Discord token is empty".to_string());
+        }
+
+        return Ok(token);
+    }
+}
+
+/// Name this process after its host, which is the pod name on Kubernetes
+fn default_instance_id() -> String {
+    let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "localhost".to_string());
+
+    format!("{}:{}", host, std::process::id())
+}
+
+/// Work out which shards to run from the shard settings
+///
+/// @param total Total number of shards, if set
+/// @param range Inclusive range of shards to run, if set
+///
+/// @return Shards to run, or a message describing the problem
+fn resolve_sharding(total: Option<&ShardTotal>, range: Option<&String>) -> Result<Sharding, String> {
+    let total = match total {
+        None if range.is_some() => return Err("A shard range needs the total number of shards to be set".to_string()),
+        None => return Ok(Sharding::Single),
+        Some(ShardTotal::Name(name)) if name.eq_ignore_ascii_case("auto") => {
+            if range.is_some() {
+                return Err("A shard range cannot be combined with automatic sharding".to_string());
+            }
+            return Ok(Sharding::Auto);
+        }
+        Some(ShardTotal::Name(name)) => return Err(format!("Shard count must be a number or \"auto\", got {}", name)),
+        Some(ShardTotal::Count(0)) => return Err("Shard count must be at least 1".to_string()),
+        Some(ShardTotal::Count(total)) => *total,
+    };
+
+    let (first, last) = match range {
+        None => (0, total - 1),
+        Some(range) => {
+            let bounds = range.split_once('-').and_then(|(first, last)| Some((first.trim().parse::<u32>().ok()?, last.trim().parse::<u32>().ok()?)));
+            let Some((first, last)) = bounds else {
+                return Err(format!("Shard range must look like 0-3, got {}", range));
+            };
+            (first, last)
+        }
+    };
+
+    if first > last || last >= total {
+        return Err(format!("Shard range {}-{} does not fit within {} shards", first, last, total));
+    }
+
+    return Ok(Sharding::Range { first, last, total });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> ConfigLayer {
+        Cli::parse_from(std::iter::once("discord_bot").chain(args.iter().copied())).overrides
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = Config::resolve(&ConfigLayer::default(), &ConfigLayer::default()).unwrap();
+
+        assert_eq!(TokenSource::File(PathBuf::from(DEFAULT_TOKEN_FILE)), config.token);
+        assert_eq!(PathBuf::from(DEFAULT_DATABASE_FILE), config.database);
+        assert_eq!(GatewayIntents::GUILD_MESSAGES | GatewayIntents::GUILD_MEMBERS, config.intents);
+        assert_eq!(SweepSettings::default(), config.sweep);
+        assert!(config.guild_defaults.auto_scan);
+        assert_eq!(Duration::from_secs(25), config.shutdown_timeout);
+        assert_eq!(Sharding::Single, config.sharding);
+    }
+
+    #[test]
+    fn test_layer_priority() {
+        let file: ConfigLayer = toml::from_str(
+            r#"
+            token_file = "/file/token"
+            database = "/file/db.sqlite"
+
+            [sweep]
+            batch_size = 200
+            member_delay_ms = 100
+
+            [guild_defaults]
+            auto_scan = false
+
+            [http]
+            listen = "0.0.0.0:8080"
+            "#,
+        )
+        .unwrap();
+        let overrides = parse(&["--database", "/cli/db.sqlite", "--sweep-batch-size", "500", "--token", "abc"]);
+
+        let config = Config::resolve(&overrides, &file).unwrap();
+
+        assert_eq!(TokenSource::Value("abc".to_string()), config.token);
+        assert_eq!(PathBuf::from("/cli/db.sqlite"), config.database);
+        assert_eq!(500, config.sweep.batch_size);
+        assert_eq!(Duration::from_millis(100), config.sweep.member_delay);
+        assert!(!config.guild_defaults.auto_scan);
+        assert_eq!(Some("0.0.0.0:8080".parse().unwrap()), config.http_listen);
+
+        let file: ConfigLayer = toml::from_str(r#"token = "from-file""#).unwrap();
+        let config = Config::resolve(&parse(&["--token-file", "/cli/token"]), &file).unwrap();
+        assert_eq!(TokenSource::File(PathBuf::from("/cli/token")), config.token);
+
+        // A token file on the command line beats a token from the environment, which beats one from the config file
+        let mut overrides = parse(&["--token-file", "/cli/token", "--token", "from-env"]);
+        overrides.token_from_env = true;
+        assert_eq!(TokenSource::File(PathBuf::from("/cli/token")), Config::resolve(&overrides, &file).unwrap().token);
+        overrides.token_file = None;
+        assert_eq!(TokenSource::Value("from-env".to_string()), Config::resolve(&overrides, &file).unwrap().token);
+        overrides.token_file_from_env = true;
+        overrides.token_file = Some(PathBuf::from("/env/token"));
+        assert_eq!(TokenSource::Value("from-env".to_string()), Config::resolve(&overrides, &file).unwrap().token);
+    }
+
+    #[test]
+    fn test_validation() {
+        let overrides = parse(&["--intents", "guild_messages,NOT_AN_INTENT", "--sweep-batch-size", "0"]);
+        let errors = Config::resolve(&overrides, &ConfigLayer::default()).unwrap_err();
+
+        assert_eq!(3, errors.len());
+
+        let overrides = parse(&["--backup-keep", "0", "--backup-interval-minutes", "0"]);
+        assert_eq!(2, Config::resolve(&overrides, &ConfigLayer::default()).unwrap_err().len());
+        let overrides = parse(&["--backup-interval-minutes", &u64::MAX.to_string()]);
+        assert_eq!(1, Config::resolve(&overrides, &ConfigLayer::default()).unwrap_err().len());
+        assert!(toml::from_str::<ConfigLayer>("unknown_key = 1").is_err());
+    }
+
+    #[test]
+    fn test_sharding() {
+        let file: ConfigLayer = toml::from_str("[sharding]\ntotal = 8").unwrap();
+        let config = Config::resolve(&parse(&["--shard-range", "4-7"]), &file).unwrap();
+        assert_eq!(Sharding::Range { first: 4, last: 7, total: 8 }, config.sharding);
+
+        let config = Config::resolve(&parse(&["--shards", "auto"]), &ConfigLayer::default()).unwrap();
+        assert_eq!(Sharding::Auto, config.sharding);
+
+        for args in [&["--shards", "4", "--shard-range", "2-4"][..], &["--shard-range", "0-1"], &["--shards", "auto", "--shard-range", "0-1"], &["--shards", "lots"]] {
+            assert_eq!(1, Config::resolve(&parse(args), &ConfigLayer::default()).unwrap_err().len(), "{:?}", args);
+        }
+    }
+}
@@ -1,48 +1,411 @@
+use std::{path::Path, sync::Arc};
+
 use log::error;
-use serenity::all::{GuildId, RoleId};
-use sqlite;
-use sqlite::State;
+use serde::{Deserialize, Serialize};
+use serenity::{
+    all::{GuildId, RoleId, UserId},
+    prelude::TypeMapKey,
+};
+use tokio::sync::Mutex;
+
+use crate::{
+    config::Config,
+    database::{Backend, Connection, DbError, Row},
+    groups::RoleGroup,
+    metrics,
+    policy::PolicyAction,
+    rules::Rule,
+    temp_roles::TempRole,
+    verification::{Application, VerificationSettings},
+};
 
 pub struct AppData {
-    db: sqlite::Connection,
+    db: Connection,
+    default_auto_scan: bool,
 }
 
-type SQLResult = Result<(), sqlite::Error>;
+/// Key to retrieve the shared database from the client's data, for tasks that outlive a single interaction
+pub struct AppDataKey;
 
-impl AppData {
-    pub fn new(db_location: &str) -> Self {
-        let new_db = Self {
-            db: sqlite::Connection::open(db_location).expect("Expected the database to initialize"),
-        };
+impl TypeMapKey for AppDataKey {
+    type Value = Arc<Mutex<AppData>>;
+}
 
-        new_db
-            .db
-            .execute(
-                "CREATE TABLE IF NOT EXISTS roles (
+type SQLResult = Result<(), DbError>;
+
+/// One schema change, written for each backend
+struct Migration {
+    sqlite: &'static str,
+    #[cfg_attr(not(feature = "postgres"), allow(dead_code))]
+    postgres: &'static str,
+}
+
+/// Schema changes, applied in order. The index + 1 of each entry is stored as the database's schema version once applied
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        sqlite: "CREATE TABLE IF NOT EXISTS roles (
           guild_id INTEGER PRIMARY KEY,
           role_id INTEGER,
           auto_scan BOOLEAN DEFAULT(TRUE)
         );",
-            )
-            .expect("Table was unable to be created.");
+        postgres: "CREATE TABLE IF NOT EXISTS roles (
+          guild_id BIGINT PRIMARY KEY,
+          role_id BIGINT,
+          auto_scan BOOLEAN DEFAULT(TRUE)
+        );",
+    },
+    Migration {
+        sqlite: "CREATE TABLE IF NOT EXISTS exempt_roles (
+          guild_id INTEGER NOT NULL,
+          role_id INTEGER NOT NULL,
+          PRIMARY KEY (guild_id, role_id)
+        );",
+        postgres: "CREATE TABLE IF NOT EXISTS exempt_roles (
+          guild_id BIGINT NOT NULL,
+          role_id BIGINT NOT NULL,
+          PRIMARY KEY (guild_id, role_id)
+        );",
+    },
+    Migration {
+        sqlite: "CREATE TABLE IF NOT EXISTS sweeps (
+          sweep_id INTEGER PRIMARY KEY AUTOINCREMENT,
+          guild_id INTEGER NOT NULL,
+          started_at INTEGER NOT NULL,
+          undone BOOLEAN DEFAULT(FALSE)
+        );
+        CREATE TABLE IF NOT EXISTS sweep_removals (
+          sweep_id INTEGER NOT NULL REFERENCES sweeps(sweep_id),
+          user_id INTEGER NOT NULL,
+          role_id INTEGER NOT NULL,
+          PRIMARY KEY (sweep_id, user_id, role_id)
+        );",
+        postgres: "CREATE TABLE IF NOT EXISTS sweeps (
+          sweep_id BIGSERIAL PRIMARY KEY,
+          guild_id BIGINT NOT NULL,
+          started_at BIGINT NOT NULL,
+          undone BOOLEAN DEFAULT(FALSE)
+        );
+        CREATE TABLE IF NOT EXISTS sweep_removals (
+          sweep_id BIGINT NOT NULL REFERENCES sweeps(sweep_id),
+          user_id BIGINT NOT NULL,
+          role_id BIGINT NOT NULL,
+          PRIMARY KEY (sweep_id, user_id, role_id)
+        );",
+    },
+    Migration {
+        sqlite: "ALTER TABLE sweeps ADD COLUMN owner TEXT;
+        ALTER TABLE sweeps ADD COLUMN lease_until INTEGER NOT NULL DEFAULT(0);",
+        postgres: "ALTER TABLE sweeps ADD COLUMN owner TEXT;
+        ALTER TABLE sweeps ADD COLUMN lease_until BIGINT NOT NULL DEFAULT(0);",
+    },
+    Migration {
+        sqlite: "ALTER TABLE roles ADD COLUMN policy_action TEXT;",
+        postgres: "ALTER TABLE roles ADD COLUMN policy_action TEXT;",
+    },
+    Migration {
+        sqlite: "CREATE TABLE IF NOT EXISTS rules (
+          rule_id INTEGER PRIMARY KEY AUTOINCREMENT,
+          guild_id INTEGER NOT NULL,
+          rule TEXT NOT NULL
+        );",
+        postgres: "CREATE TABLE IF NOT EXISTS rules (
+          rule_id BIGSERIAL PRIMARY KEY,
+          guild_id BIGINT NOT NULL,
+          rule TEXT NOT NULL
+        );",
+    },
+    Migration {
+        sqlite: "CREATE TABLE IF NOT EXISTS role_groups (
+          guild_id INTEGER NOT NULL,
+          role_id INTEGER NOT NULL,
+          name TEXT NOT NULL,
+          PRIMARY KEY (guild_id, role_id)
+        );",
+        postgres: "CREATE TABLE IF NOT EXISTS role_groups (
+          guild_id BIGINT NOT NULL,
+          role_id BIGINT NOT NULL,
+          name TEXT NOT NULL,
+          PRIMARY KEY (guild_id, role_id)
+        );",
+    },
+    Migration {
+        sqlite: "CREATE TABLE IF NOT EXISTS temp_roles (
+          guild_id INTEGER NOT NULL,
+          user_id INTEGER NOT NULL,
+          role_id INTEGER NOT NULL,
+          expires_at INTEGER NOT NULL,
+          PRIMARY KEY (guild_id, user_id, role_id)
+        );",
+        postgres: "CREATE TABLE IF NOT EXISTS temp_roles (
+          guild_id BIGINT NOT NULL,
+          user_id BIGINT NOT NULL,
+          role_id BIGINT NOT NULL,
+          expires_at BIGINT NOT NULL,
+          PRIMARY KEY (guild_id, user_id, role_id)
+        );",
+    },
+    Migration {
+        sqlite: "CREATE TABLE IF NOT EXISTS verification (
+          guild_id INTEGER PRIMARY KEY,
+          settings TEXT NOT NULL
+        );",
+        postgres: "CREATE TABLE IF NOT EXISTS verification (
+          guild_id BIGINT PRIMARY KEY,
+          settings TEXT NOT NULL
+        );",
+    },
+    Migration {
+        sqlite: "CREATE TABLE IF NOT EXISTS applications (
+          application_id INTEGER PRIMARY KEY AUTOINCREMENT,
+          guild_id INTEGER NOT NULL,
+          user_id INTEGER NOT NULL,
+          answers TEXT NOT NULL,
+          submitted_at INTEGER NOT NULL,
+          UNIQUE (guild_id, user_id)
+        );",
+        postgres: "CREATE TABLE IF NOT EXISTS applications (
+          application_id BIGSERIAL PRIMARY KEY,
+          guild_id BIGINT NOT NULL,
+          user_id BIGINT NOT NULL,
+          answers TEXT NOT NULL,
+          submitted_at BIGINT NOT NULL,
+          UNIQUE (guild_id, user_id)
+        );",
+    },
+];
+
+/// Everything configured for a single server
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GuildConfig {
+    pub guild_id: GuildId,
+    pub primary_role: Option<RoleId>,
+    pub auto_scan: bool,
+    #[serde(default)]
+    pub exempt_roles: Vec<RoleId>,
+    /// Left out of documents written before policy actions existed, in which case importing keeps the stored action
+    #[serde(default)]
+    pub policy_action: Option<PolicyAction>,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    #[serde(default)]
+    pub role_groups: Vec<RoleGroup>,
+    #[serde(default)]
+    pub verification: Option<VerificationSettings>,
+}
+
+/// Read a row of the applications table, skipping rows with missing IDs or unreadable answers
+fn application_from_row(row: &Row) -> Option<Application> {
+    let application_id = row.get_i64("application_id")?;
+    let answers = match serde_json::from_str(&row.get_text("answers")?) {
+        Ok(answers) => answers,
+        Err(error) => {
+            error!("Invalid answers stored for application {}: {}", application_id, error);
+            return None;
+        }
+    };
+
+    Some(Application {
+        application_id,
+        guild_id: GuildId::new(row.get_id("guild_id")?),
+        user_id: UserId::new(row.get_id("user_id")?),
+        answers,
+        submitted_at: row.get_i64("submitted_at")?,
+    })
+}
+
+/// Read a row of the temp_roles table, skipping rows with missing IDs
+fn temp_role_from_row(row: &Row) -> Option<TempRole> {
+    Some(TempRole {
+        guild_id: GuildId::new(row.get_id("guild_id")?),
+        user_id: UserId::new(row.get_id("user_id")?),
+        role_id: RoleId::new(row.get_id("role_id")?),
+        expires_at: row.get_i64("expires_at")?,
+    })
+}
+
+impl AppData {
+    /// Open the database and bring its schema up to date
+    ///
+    /// @param db_location Path to the database file, or ":memory:"
+    #[cfg(test)]
+    pub fn new(db_location: impl AsRef<Path>) -> Self {
+        let mut new_db = Self::open(db_location).expect("Expected the database to initialize");
+        new_db.migrate().expect("Table was unable to be created.");
 
         new_db
     }
 
+    /// Open a SQLite database without touching its schema
+    ///
+    /// @param db_location Path to the database file, or ":memory:"
+    pub fn open(db_location: impl AsRef<Path>) -> Result<Self, DbError> {
+        Ok(Self {
+            db: Connection::open_sqlite(db_location)?,
+            default_auto_scan: true,
+        })
+    }
+
+    /// Connect to a PostgreSQL database without touching its schema
+    ///
+    /// @param url Connection string of the database
+    #[cfg(feature = "postgres")]
+    pub fn connect(url: &str) -> Result<Self, DbError> {
+        Ok(Self {
+            db: Connection::connect_postgres(url)?,
+            default_auto_scan: true,
+        })
+    }
+
+    /// Open the database the configuration points at without touching its schema, PostgreSQL if a URL is set and SQLite otherwise
+    ///
+    /// @param config Resolved bot configuration
+    pub fn open_configured(config: &Config) -> Result<Self, DbError> {
+        #[cfg(feature = "postgres")]
+        if let Some(url) = &config.database_url {
+            return Self::connect(url);
+        }
+
+        Self::open(&config.database)
+    }
+
+    /// Get the schema version of the database
+    ///
+    /// @return Number of migrations applied to the database
+    pub fn schema_version(&self) -> Result<usize, DbError> {
+        let rows = match self.db.backend() {
+            Backend::Sqlite => self.db.query("PRAGMA user_version;", &[])?,
+            #[cfg(feature = "postgres")]
+            Backend::Postgres => {
+                self.db.execute("CREATE TABLE IF NOT EXISTS schema_version (version BIGINT NOT NULL);")?;
+                self.db.query("SELECT MAX(version) AS user_version FROM schema_version;", &[])?
+            }
+        };
+
+        Ok(rows.first().and_then(|row| row.get_i64("user_version")).and_then(|version| version.try_into().ok()).unwrap_or(0))
+    }
+
+    /// Get the number of migrations not yet applied to the database
+    pub fn pending_migrations(&self) -> Result<usize, DbError> {
+        Ok(MIGRATIONS.len().saturating_sub(self.schema_version()?))
+    }
+
+    /// Apply every migration the database has not seen yet
+    ///
+    /// @return Number of migrations applied
+    pub fn migrate(&mut self) -> Result<usize, DbError> {
+        let current = self.schema_version()?;
+
+        for (version, migration) in MIGRATIONS.iter().enumerate().skip(current) {
+            let version = version + 1;
+            let statements = match self.db.backend() {
+                Backend::Sqlite => format!("{}\nPRAGMA user_version = {};", migration.sqlite, version),
+                #[cfg(feature = "postgres")]
+                Backend::Postgres => format!("{}\nDELETE FROM schema_version;\nINSERT INTO schema_version (version) VALUES({});", migration.postgres, version),
+            };
+
+            self.transaction("BEGIN;", |db| db.execute(&statements))?;
+        }
+
+        Ok(MIGRATIONS.len().saturating_sub(current))
+    }
+
+    /// Run statements in a transaction, rolling it back if any of them fail
+    ///
+    /// @param begin Statement starting the transaction
+    /// @param body Work to do inside the transaction
+    fn transaction<T>(&self, begin: &str, body: impl FnOnce(&Connection) -> Result<T, DbError>) -> Result<T, DbError> {
+        self.db.execute(begin)?;
+
+        match body(&self.db) {
+            Ok(result) => self.db.execute("COMMIT;").map(|_| result),
+            Err(error) => {
+                self.db.execute("ROLLBACK;")?;
+                Err(error)
+            }
+        }
+    }
+
+    /// Start a transaction that no other process can change the sweeps table during
+    fn begin_sweeps_exclusive(&self) -> &'static str {
+        match self.db.backend() {
+            Backend::Sqlite => "BEGIN IMMEDIATE;",
+            #[cfg(feature = "postgres")]
+            Backend::Postgres => "BEGIN; LOCK TABLE sweeps IN SHARE ROW EXCLUSIVE MODE;",
+        }
+    }
+
+    /// Write a consistent copy of the database to a new file, safe to run while the bot is using it
+    ///
+    /// @param destination File to create, must not already exist
+    pub fn backup_to(&self, destination: &Path) -> SQLResult {
+        if self.db.backend() != Backend::Sqlite {
+            return Err(DbError::Unsupported("Backing up to a file"));
+        }
+
+        let destination = destination.to_string_lossy().replace('\'', "''");
+
+        self.db.execute(format!("VACUUM INTO '{}';", destination))
+    }
+
+    /// Check that the database still answers queries
+    pub fn ping(&self) -> SQLResult {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["ping"]).start_timer();
+        self.db.execute("SELECT 1;")
+    }
+
+    /// Check the database file for corruption
+    ///
+    /// @return True if SQLite found no problems
+    pub fn integrity_check(&self) -> Result<bool, DbError> {
+        if self.db.backend() != Backend::Sqlite {
+            return Err(DbError::Unsupported("Checking the integrity of the database file"));
+        }
+
+        let rows = self.db.query("PRAGMA integrity_check;", &[])?;
+
+        Ok(rows.first().and_then(|row| row.get_text("integrity_check")).is_some_and(|result| result == "ok"))
+    }
+
+    /// Set whether auto scanning starts enabled for servers registered from now on
+    ///
+    /// @param enabled Initial auto scan state for new servers
+    pub fn set_default_auto_scan(&mut self, enabled: bool) {
+        self.default_auto_scan = enabled;
+    }
+
+    /// Run a query, logging and swallowing any failure for methods that have no way to report it
+    fn query_or_log(&self, statement: String) -> Vec<Row> {
+        self.db.query(&statement, &[]).unwrap_or_else(|error| {
+            error!("Failed to run query {}: {}", statement, error);
+            Vec::new()
+        })
+    }
+
     /// Register a new server with the database
     ///
     /// @param server_id ID of the new server
     pub fn new_server(&mut self, server_id: &GuildId) -> SQLResult {
-        let statement = format!("INSERT OR IGNORE INTO roles (guild_id, role_id) VALUES({}, NULL);", server_id.get());
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["new_server"]).start_timer();
+        let statement = format!(
+            "INSERT INTO roles (guild_id, role_id, auto_scan) VALUES({}, NULL, {}) ON CONFLICT DO NOTHING;",
+            server_id.get(),
+            if self.default_auto_scan { "TRUE" } else { "FALSE" }
+        );
         self.db.execute(statement)
     }
 
-    /// Update the primary role for the given server
+    /// Update the primary role for the given server, registering it if needed
     ///
     /// @param server_id ID for the server to update
     /// @param role_id ID to become the new primary role
     pub fn update_server_primary_role(&mut self, server_id: &GuildId, role_id: &RoleId) -> SQLResult {
-        let statement = format!("UPDATE OR IGNORE roles SET role_id = {} WHERE guild_id = {};", role_id.get(), server_id.get());
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["update_server_primary_role"]).start_timer();
+        let statement = format!(
+            "INSERT INTO roles (guild_id, role_id, auto_scan) VALUES({}, {}, {}) ON CONFLICT (guild_id) DO UPDATE SET role_id = excluded.role_id;",
+            server_id.get(),
+            role_id.get(),
+            if self.default_auto_scan { "TRUE" } else { "FALSE" }
+        );
 
         self.db.execute(statement)
     }
@@ -51,34 +414,34 @@ impl AppData {
     ///
     /// @param server_id ID of the server to check
     pub fn is_auto_scan_enabled(&self, server_id: &GuildId) -> bool {
-        let statement = format!("SELECT auto_scan FROM roles WHERE guild_id = {};", server_id.get());
-        let statement = self.db.prepare(statement);
-        let Ok(mut statement) = statement else {
-            error!("Failed to prepare statement");
-            return false;
-        };
-
-        while let Ok(State::Row) = statement.next() {
-            return statement.read::<i64, _>("auto_scan").map_or(false, |val| val != 0);
-        }
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["is_auto_scan_enabled"]).start_timer();
+        let rows = self.query_or_log(format!("SELECT auto_scan FROM roles WHERE guild_id = {};", server_id.get()));
 
-        return false;
+        return rows.first().and_then(|row| row.get_bool("auto_scan")).unwrap_or(false);
     }
 
-    /// Disable auto scan on a given server
+    /// Disable auto scan on a given server, registering it if needed
     ///
     /// @param server_id ID of the server to disable auto scanning on
     pub fn disable_auto_scan(&self, server_id: &GuildId) -> SQLResult {
-        let statement = format!("UPDATE OR IGNORE roles SET auto_scan = FALSE WHERE guild_id = {}", server_id.get());
-
-        self.db.execute(statement)
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["disable_auto_scan"]).start_timer();
+        self.set_auto_scan(server_id, false)
     }
 
-    /// Enable auto scan on a given server
+    /// Enable auto scan on a given server, registering it if needed
     ///
     /// @param server_id ID of the server to enable auto scanning on
     pub fn enable_auto_scan(&self, server_id: &GuildId) -> SQLResult {
-        let statement = format!("UPDATE OR IGNORE roles SET auto_scan = TRUE WHERE guild_id = {}", server_id.get());
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["enable_auto_scan"]).start_timer();
+        self.set_auto_scan(server_id, true)
+    }
+
+    fn set_auto_scan(&self, server_id: &GuildId, enabled: bool) -> SQLResult {
+        let statement = format!(
+            "INSERT INTO roles (guild_id, role_id, auto_scan) VALUES({}, NULL, {}) ON CONFLICT (guild_id) DO UPDATE SET auto_scan = excluded.auto_scan;",
+            server_id.get(),
+            if enabled { "TRUE" } else { "FALSE" }
+        );
 
         self.db.execute(statement)
     }
@@ -89,49 +452,977 @@ impl AppData {
     ///
     /// @return Role ID of the primary role, or None if not saved
     pub fn get_primary_role(&self, server_id: &GuildId) -> Option<RoleId> {
-        let statement = format!("SELECT role_id FROM roles where guild_id = {};", server_id.get());
-        let mut statement = self.db.prepare(statement).ok()?;
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["get_primary_role"]).start_timer();
+        let rows = self.query_or_log(format!("SELECT role_id FROM roles where guild_id = {};", server_id.get()));
+
+        return rows.first().and_then(|row| row.get_id("role_id")).map(RoleId::new);
+    }
+
+    /// Exempt a role from being removed by the bot
+    ///
+    /// @param server_id ID of the server the role belongs to
+    /// @param role_id ID of the role to exempt
+    pub fn add_exempt_role(&mut self, server_id: &GuildId, role_id: &RoleId) -> SQLResult {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["add_exempt_role"]).start_timer();
+        let statement = format!("INSERT INTO exempt_roles (guild_id, role_id) VALUES({}, {}) ON CONFLICT DO NOTHING;", server_id.get(), role_id.get());
+
+        self.db.execute(statement)
+    }
+
+    /// Stop exempting a role from being removed by the bot
+    ///
+    /// @param server_id ID of the server the role belongs to
+    /// @param role_id ID of the role to no longer exempt
+    pub fn remove_exempt_role(&mut self, server_id: &GuildId, role_id: &RoleId) -> SQLResult {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["remove_exempt_role"]).start_timer();
+        let statement = format!("DELETE FROM exempt_roles WHERE guild_id = {} AND role_id = {};", server_id.get(), role_id.get());
+
+        self.db.execute(statement)
+    }
+
+    /// Get the roles the bot will never remove in a given server
+    ///
+    /// @param server_id ID of the server to get the exempt roles of
+    ///
+    /// @return List of exempt role IDs, empty if none are saved
+    pub fn get_exempt_roles(&self, server_id: &GuildId) -> Vec<RoleId> {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["get_exempt_roles"]).start_timer();
+        let rows = self.query_or_log(format!("SELECT role_id FROM exempt_roles WHERE guild_id = {} ORDER BY role_id;", server_id.get()));
+
+        return rows.iter().filter_map(|row| row.get_id("role_id")).map(RoleId::new).collect();
+    }
+
+    /// Set what the bot does to members who break the policy in a server, registering it if needed
+    ///
+    /// @param server_id ID of the server
+    /// @param action Action to take
+    pub fn set_policy_action(&mut self, server_id: &GuildId, action: &PolicyAction) -> SQLResult {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["set_policy_action"]).start_timer();
+        let action = serde_json::to_string(action).map_err(|_| DbError::Unsupported("Storing this policy action"))?;
+
+        // Actions are JSON, so bind them instead of formatting them into the query
+        let statement = format!(
+            "INSERT INTO roles (guild_id, role_id, auto_scan, policy_action) VALUES(?, NULL, {}, ?)
+              ON CONFLICT (guild_id) DO UPDATE SET policy_action = excluded.policy_action;",
+            if self.default_auto_scan { "TRUE" } else { "FALSE" }
+        );
+        self.db.update(&statement, &[(server_id.get() as i64).into(), action.as_str().into()])?;
+
+        Ok(())
+    }
+
+    /// Get what the bot does to members who break the policy in a server
+    ///
+    /// @param server_id ID of the server
+    ///
+    /// @return Configured action, removing every non-exempt role if none is saved
+    pub fn get_policy_action(&self, server_id: &GuildId) -> PolicyAction {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["get_policy_action"]).start_timer();
+        let rows = self.query_or_log(format!("SELECT policy_action FROM roles WHERE guild_id = {};", server_id.get()));
+
+        let Some(action) = rows.first().and_then(|row| row.get_text("policy_action")) else {
+            return PolicyAction::default();
+        };
+
+        return serde_json::from_str(&action).unwrap_or_else(|error| {
+            error!("Invalid policy action stored for {}: {}", server_id, error);
+            PolicyAction::default()
+        });
+    }
+
+    /// Add a role rule to a server
+    ///
+    /// @param server_id ID of the server
+    /// @param rule Rule to add
+    ///
+    /// @return ID of the new rule
+    pub fn add_rule(&mut self, server_id: &GuildId, rule: &Rule) -> Result<i64, DbError> {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["add_rule"]).start_timer();
+        let rule = serde_json::to_string(rule).map_err(|_| DbError::Unsupported("Storing this rule"))?;
+
+        let rows = self.db.query("INSERT INTO rules (guild_id, rule) VALUES(?, ?) RETURNING rule_id;", &[(server_id.get() as i64).into(), rule.as_str().into()])?;
+
+        rows.first().and_then(|row| row.get_i64("rule_id")).ok_or(DbError::Unsupported("Returning the ID of a new rule"))
+    }
+
+    /// Delete a role rule from a server
+    ///
+    /// @param server_id ID of the server the rule belongs to
+    /// @param rule_id ID of the rule
+    ///
+    /// @return False if the server has no rule with this ID
+    pub fn remove_rule(&mut self, server_id: &GuildId, rule_id: i64) -> Result<bool, DbError> {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["remove_rule"]).start_timer();
+        let changed = self.db.update("DELETE FROM rules WHERE guild_id = ? AND rule_id = ?;", &[(server_id.get() as i64).into(), rule_id.into()])?;
+
+        Ok(changed > 0)
+    }
+
+    /// Get the role rules of a server
+    ///
+    /// @param server_id ID of the server
+    ///
+    /// @return ID and rule of every rule in the order they were added, skipping any that cannot be read
+    pub fn get_rules(&self, server_id: &GuildId) -> Vec<(i64, Rule)> {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["get_rules"]).start_timer();
+        let rows = self.query_or_log(format!("SELECT rule_id, rule FROM rules WHERE guild_id = {} ORDER BY rule_id;", server_id.get()));
+
+        return rows
+            .iter()
+            .filter_map(|row| {
+                let (rule_id, rule) = (row.get_i64("rule_id")?, row.get_text("rule")?);
+                match serde_json::from_str(&rule) {
+                    Ok(rule) => Some((rule_id, rule)),
+                    Err(error) => {
+                        error!("Invalid rule {} stored for {}: {}", rule_id, server_id, error);
+                        None
+                    }
+                }
+            })
+            .collect();
+    }
+
+    /// Put a role in a role group, moving it out of any other group
+    ///
+    /// @param server_id ID of the server
+    /// @param group Name of the group
+    /// @param role_id ID of the role
+    pub fn add_group_role(&mut self, server_id: &GuildId, group: &str, role_id: &RoleId) -> SQLResult {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["add_group_role"]).start_timer();
+
+        // Group names are typed by users, so bind them instead of formatting them into the query
+        self.db.update(
+            "INSERT INTO role_groups (guild_id, role_id, name) VALUES(?, ?, ?) ON CONFLICT (guild_id, role_id) DO UPDATE SET name = excluded.name;",
+            &[(server_id.get() as i64).into(), (role_id.get() as i64).into(), group.into()],
+        )?;
+
+        Ok(())
+    }
+
+    /// Take a role out of its role group
+    ///
+    /// @param server_id ID of the server
+    /// @param role_id ID of the role
+    ///
+    /// @return False if the role was not in a group
+    pub fn remove_group_role(&mut self, server_id: &GuildId, role_id: &RoleId) -> Result<bool, DbError> {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["remove_group_role"]).start_timer();
+        let changed = self.db.update("DELETE FROM role_groups WHERE guild_id = ? AND role_id = ?;", &[(server_id.get() as i64).into(), (role_id.get() as i64).into()])?;
+
+        Ok(changed > 0)
+    }
+
+    /// Get the role groups of a server
+    ///
+    /// @param server_id ID of the server
+    ///
+    /// @return Groups ordered by name, each with its roles ordered by ID so the oldest role comes first
+    pub fn get_role_groups(&self, server_id: &GuildId) -> Vec<RoleGroup> {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["get_role_groups"]).start_timer();
+        let rows = self.query_or_log(format!("SELECT name, role_id FROM role_groups WHERE guild_id = {} ORDER BY name, role_id;", server_id.get()));
+
+        let mut groups: Vec<RoleGroup> = Vec::new();
+        for row in rows {
+            let (Some(name), Some(role_id)) = (row.get_text("name"), row.get_id("role_id")) else {
+                continue;
+            };
+
+            match groups.last_mut() {
+                Some(group) if group.name == name => group.roles.push(RoleId::new(role_id)),
+                _ => groups.push(RoleGroup { name, roles: vec![RoleId::new(role_id)] }),
+            }
+        }
+
+        return groups;
+    }
+
+    /// Record a role given to a member until a set time, replacing the expiry of an earlier grant of the same role
+    ///
+    /// @param server_id ID of the server
+    /// @param user_id ID of the member
+    /// @param role_id ID of the role
+    /// @param expires_at Unix timestamp the role is taken away at
+    pub fn add_temp_role(&mut self, server_id: &GuildId, user_id: &UserId, role_id: &RoleId, expires_at: i64) -> SQLResult {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["add_temp_role"]).start_timer();
+        self.db.update(
+            "INSERT INTO temp_roles (guild_id, user_id, role_id, expires_at) VALUES(?, ?, ?, ?) ON CONFLICT (guild_id, user_id, role_id) DO UPDATE SET expires_at = excluded.expires_at;",
+            &[(server_id.get() as i64).into(), (user_id.get() as i64).into(), (role_id.get() as i64).into(), expires_at.into()],
+        )?;
+
+        Ok(())
+    }
+
+    /// Forget a temporary role, once it was taken away or should stay
+    ///
+    /// @param server_id ID of the server
+    /// @param user_id ID of the member
+    /// @param role_id ID of the role
+    ///
+    /// @return False if the member did not have the role temporarily
+    pub fn remove_temp_role(&mut self, server_id: &GuildId, user_id: &UserId, role_id: &RoleId) -> Result<bool, DbError> {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["remove_temp_role"]).start_timer();
+        let changed = self.db.update(
+            "DELETE FROM temp_roles WHERE guild_id = ? AND user_id = ? AND role_id = ?;",
+            &[(server_id.get() as i64).into(), (user_id.get() as i64).into(), (role_id.get() as i64).into()],
+        )?;
+
+        Ok(changed > 0)
+    }
+
+    /// Get the temporary roles handed out in a server
+    ///
+    /// @param server_id ID of the server
+    ///
+    /// @return Temporary roles, the first to expire first
+    pub fn get_temp_roles(&self, server_id: &GuildId) -> Vec<TempRole> {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["get_temp_roles"]).start_timer();
+        let rows = self.query_or_log(format!("SELECT guild_id, user_id, role_id, expires_at FROM temp_roles WHERE guild_id = {} ORDER BY expires_at, user_id, role_id;", server_id.get()));
+
+        return rows.iter().filter_map(temp_role_from_row).collect();
+    }
+
+    /// Get the temporary roles of every server that are due to be taken away
+    ///
+    /// @param now Current Unix timestamp, roles expiring at or before it are due
+    ///
+    /// @return Expired temporary roles, the first to expire first
+    pub fn get_expired_temp_roles(&self, now: i64) -> Vec<TempRole> {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["get_expired_temp_roles"]).start_timer();
+        let rows = self.query_or_log(format!("SELECT guild_id, user_id, role_id, expires_at FROM temp_roles WHERE expires_at <= {} ORDER BY expires_at, guild_id, user_id, role_id;", now));
+
+        return rows.iter().filter_map(temp_role_from_row).collect();
+    }
+
+    /// Set how members of a server verify themselves
+    ///
+    /// @param server_id ID of the server
+    /// @param settings Verification settings
+    pub fn set_verification(&mut self, server_id: &GuildId, settings: &VerificationSettings) -> SQLResult {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["set_verification"]).start_timer();
+        let settings = serde_json::to_string(settings).map_err(|_| DbError::Unsupported("Storing these verification settings"))?;
+
+        self.db.update(
+            "INSERT INTO verification (guild_id, settings) VALUES(?, ?) ON CONFLICT (guild_id) DO UPDATE SET settings = excluded.settings;",
+            &[(server_id.get() as i64).into(), settings.as_str().into()],
+        )?;
+
+        Ok(())
+    }
+
+    /// Turn off verification in a server
+    ///
+    /// @param server_id ID of the server
+    ///
+    /// @return False if verification was not set up
+    pub fn remove_verification(&mut self, server_id: &GuildId) -> Result<bool, DbError> {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["remove_verification"]).start_timer();
+        let changed = self.db.update("DELETE FROM verification WHERE guild_id = ?;", &[(server_id.get() as i64).into()])?;
+
+        Ok(changed > 0)
+    }
+
+    /// Get how members of a server verify themselves
+    ///
+    /// @param server_id ID of the server
+    ///
+    /// @return Verification settings, None if verification is not set up
+    pub fn get_verification(&self, server_id: &GuildId) -> Option<VerificationSettings> {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["get_verification"]).start_timer();
+        let rows = self.query_or_log(format!("SELECT settings FROM verification WHERE guild_id = {};", server_id.get()));
+        let settings = rows.first()?.get_text("settings")?;
+
+        match serde_json::from_str(&settings) {
+            Ok(settings) => Some(settings),
+            Err(error) => {
+                error!("Invalid verification settings stored for {}: {}", server_id, error);
+                None
+            }
+        }
+    }
+
+    /// Save a member's answers to the verification questions for review, unless they are already waiting for one
+    ///
+    /// @param server_id ID of the server
+    /// @param user_id ID of the member
+    /// @param answers Each question with the member's answer
+    /// @param submitted_at Unix timestamp the answers were sent at
+    ///
+    /// @return ID of the new application, or None if the member already has one waiting
+    pub fn add_application(&mut self, server_id: &GuildId, user_id: &UserId, answers: &[(String, String)], submitted_at: i64) -> Result<Option<i64>, DbError> {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["add_application"]).start_timer();
+        let answers = serde_json::to_string(answers).map_err(|_| DbError::Unsupported("Storing these answers"))?;
+
+        // Look before inserting instead of relying on the unique constraint, as PostgreSQL uses up an ID on every conflicting insert
+        self.transaction("BEGIN;", |db| {
+            let pending = db.query(format!("SELECT application_id FROM applications WHERE guild_id = {} AND user_id = {};", server_id.get(), user_id.get()), &[])?;
+            if !pending.is_empty() {
+                return Ok(None);
+            }
+
+            let rows = db.query(
+                "INSERT INTO applications (guild_id, user_id, answers, submitted_at) VALUES(?, ?, ?, ?) RETURNING application_id;",
+                &[(server_id.get() as i64).into(), (user_id.get() as i64).into(), answers.as_str().into(), submitted_at.into()],
+            )?;
+
+            Ok(rows.first().and_then(|row| row.get_i64("application_id")))
+        })
+    }
+
+    /// Forget an application once it was reviewed
+    ///
+    /// @param application_id ID of the application
+    ///
+    /// @return False if there is no application with this ID, for example because it was already reviewed
+    pub fn remove_application(&mut self, application_id: i64) -> Result<bool, DbError> {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["remove_application"]).start_timer();
+        let changed = self.db.update("DELETE FROM applications WHERE application_id = ?;", &[application_id.into()])?;
+
+        Ok(changed > 0)
+    }
+
+    /// Get an application waiting for review
+    ///
+    /// @param application_id ID of the application
+    ///
+    /// @return Application, None if there is no application with this ID
+    pub fn get_application(&self, application_id: i64) -> Option<Application> {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["get_application"]).start_timer();
+        let rows = self.query_or_log(format!("SELECT application_id, guild_id, user_id, answers, submitted_at FROM applications WHERE application_id = {};", application_id));
+
+        return rows.first().and_then(application_from_row);
+    }
+
+    /// Get the applications waiting for review in a server
+    ///
+    /// @param server_id ID of the server
+    ///
+    /// @return Applications, the oldest first
+    pub fn get_applications(&self, server_id: &GuildId) -> Vec<Application> {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["get_applications"]).start_timer();
+        let rows = self.query_or_log(format!(
+            "SELECT application_id, guild_id, user_id, answers, submitted_at FROM applications WHERE guild_id = {} ORDER BY application_id;",
+            server_id.get()
+        ));
+
+        return rows.iter().filter_map(application_from_row).collect();
+    }
+
+    /// Record the start of a new sweep, unless another sweep or undo of the server is still running
+    ///
+    /// @param server_id ID of the server being swept
+    /// @param owner Instance ID of the process running the sweep
+    /// @param started_at Unix timestamp the sweep started at
+    /// @param lease_until Unix timestamp after which other processes may assume the sweep died
+    ///
+    /// @return ID of the new sweep, or None if the server is already being swept
+    pub fn start_sweep(&mut self, server_id: &GuildId, owner: &str, started_at: i64, lease_until: i64) -> Result<Option<i64>, DbError> {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["start_sweep"]).start_timer();
+
+        // Take the write lock up front, so two processes cannot both see the server as idle
+        self.transaction(self.begin_sweeps_exclusive(), |db| {
+            if self.running_sweep(server_id, started_at)?.is_some() {
+                return Ok(None);
+            }
+
+            // Owner names come from the configuration, so bind them instead of formatting them into the query
+            let rows = db.query(
+                "INSERT INTO sweeps (guild_id, started_at, owner, lease_until) VALUES(?, ?, ?, ?) RETURNING sweep_id;",
+                &[(server_id.get() as i64).into(), started_at.into(), owner.into(), lease_until.into()],
+            )?;
+
+            Ok(rows.first().and_then(|row| row.get_i64("sweep_id")))
+        })
+    }
+
+    /// Take ownership of undoing a sweep, unless the server is busy or it was already undone
+    ///
+    /// The sweep is only marked undone by finish_sweep_undo, so an undo that stops early can be run again
+    ///
+    /// @param server_id ID of the server the sweep belongs to
+    /// @param sweep_id ID of the sweep to undo
+    /// @param owner Instance ID of the process restoring the roles
+    /// @param now Current Unix timestamp
+    /// @param lease_until Unix timestamp after which other processes may assume the undo died
+    ///
+    /// @return True if this process now owns the undo
+    pub fn claim_sweep_undo(&mut self, server_id: &GuildId, sweep_id: i64, owner: &str, now: i64, lease_until: i64) -> Result<bool, DbError> {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["claim_sweep_undo"]).start_timer();
+
+        self.transaction(self.begin_sweeps_exclusive(), |db| {
+            if self.running_sweep(server_id, now)?.is_some() || !self.is_sweep_undoable(server_id, sweep_id) {
+                return Ok(false);
+            }
+
+            db.update("UPDATE sweeps SET owner = ?, lease_until = ? WHERE sweep_id = ?;", &[owner.into(), lease_until.into(), sweep_id.into()])?;
+            Ok(true)
+        })
+    }
+
+    /// Mark a sweep as undone once all its roles have been restored, releasing the server
+    ///
+    /// @param sweep_id ID of the sweep
+    /// @param owner Instance ID of the process that undid it
+    pub fn finish_sweep_undo(&mut self, sweep_id: i64, owner: &str) -> SQLResult {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["finish_sweep_undo"]).start_timer();
+        self.db.update("UPDATE sweeps SET undone = TRUE, lease_until = 0 WHERE sweep_id = ? AND owner = ?;", &[sweep_id.into(), owner.into()])?;
+
+        Ok(())
+    }
+
+    /// Find the sweep or undo currently running in a server, in any process
+    ///
+    /// @param server_id ID of the server
+    /// @param now Current Unix timestamp, sweeps whose lease ran out before this are considered dead
+    ///
+    /// @return ID of the sweep and the instance running it, or None if the server is idle
+    pub fn running_sweep(&self, server_id: &GuildId, now: i64) -> Result<Option<(i64, String)>, DbError> {
+        let statement = format!(
+            "SELECT sweep_id, owner FROM sweeps WHERE guild_id = {} AND lease_until > {} ORDER BY sweep_id DESC LIMIT 1;",
+            server_id.get(),
+            now
+        );
+        let rows = self.db.query(statement, &[])?;
+
+        Ok(rows.first().and_then(|row| Some((row.get_i64("sweep_id")?, row.get_text("owner").unwrap_or_default()))))
+    }
+
+    /// Extend the lease of a running sweep or undo
+    ///
+    /// @param sweep_id ID of the sweep
+    /// @param owner Instance ID of the process running it
+    /// @param lease_until New end of the lease
+    ///
+    /// @return False if another process has taken the sweep over, in which case this process must stop
+    pub fn renew_sweep_lease(&mut self, sweep_id: i64, owner: &str, lease_until: i64) -> Result<bool, DbError> {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["renew_sweep_lease"]).start_timer();
+        let changed = self.db.update("UPDATE sweeps SET lease_until = ? WHERE sweep_id = ? AND owner = ?;", &[lease_until.into(), sweep_id.into(), owner.into()])?;
+
+        Ok(changed > 0)
+    }
+
+    /// Let other processes sweep the server again once a sweep or undo has stopped
+    ///
+    /// @param sweep_id ID of the sweep
+    /// @param owner Instance ID of the process that ran it
+    pub fn release_sweep(&mut self, sweep_id: i64, owner: &str) -> SQLResult {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["release_sweep"]).start_timer();
+        self.db.update("UPDATE sweeps SET lease_until = 0 WHERE sweep_id = ? AND owner = ?;", &[sweep_id.into(), owner.into()])?;
+
+        Ok(())
+    }
+
+    /// Record the roles a sweep removed from a member
+    ///
+    /// @param sweep_id ID of the sweep that removed the roles
+    /// @param user_id ID of the member the roles were removed from
+    /// @param roles Roles that were removed
+    pub fn record_sweep_removal(&mut self, sweep_id: i64, user_id: &UserId, roles: &[RoleId]) -> SQLResult {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["record_sweep_removal"]).start_timer();
+        for role in roles {
+            let statement = format!(
+                "INSERT INTO sweep_removals (sweep_id, user_id, role_id) VALUES({}, {}, {}) ON CONFLICT DO NOTHING;",
+                sweep_id,
+                user_id.get(),
+                role.get()
+            );
+            self.db.execute(statement)?;
+        }
+
+        Ok(())
+    }
+
+    /// Get the most recent sweep of a server that has not been undone
+    ///
+    /// @param server_id ID of the server to check
+    ///
+    /// @return ID of the sweep, or None if there is nothing to undo
+    pub fn get_last_sweep(&self, server_id: &GuildId) -> Option<i64> {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["get_last_sweep"]).start_timer();
+        let rows = self.query_or_log(format!("SELECT sweep_id FROM sweeps WHERE guild_id = {} AND undone = FALSE ORDER BY sweep_id DESC LIMIT 1;", server_id.get()));
 
-        while let Ok(State::Row) = statement.next() {
-            let id = statement.read::<i64, _>("role_id").ok()?;
-            let id: u64 = id.try_into().ok()?;
+        return rows.first().and_then(|row| row.get_i64("sweep_id"));
+    }
+
+    /// Check if a sweep belongs to a server and has not been undone yet
+    ///
+    /// @param server_id ID of the server the sweep should belong to
+    /// @param sweep_id ID of the sweep to check
+    pub fn is_sweep_undoable(&self, server_id: &GuildId, sweep_id: i64) -> bool {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["is_sweep_undoable"]).start_timer();
+        let rows = self.query_or_log(format!("SELECT undone FROM sweeps WHERE guild_id = {} AND sweep_id = {};", server_id.get(), sweep_id));
+
+        return rows.first().and_then(|row| row.get_bool("undone")).is_some_and(|undone| !undone);
+    }
+
+    /// Get the roles a sweep removed, grouped by member
+    ///
+    /// @param sweep_id ID of the sweep
+    ///
+    /// @return List of members and the roles removed from each of them
+    pub fn get_sweep_removals(&self, sweep_id: i64) -> Vec<(UserId, Vec<RoleId>)> {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["get_sweep_removals"]).start_timer();
+        let rows = self.query_or_log(format!("SELECT user_id, role_id FROM sweep_removals WHERE sweep_id = {} ORDER BY user_id, role_id;", sweep_id));
 
-            if id == 0 {
-                return None;
+        let mut removals: Vec<(UserId, Vec<RoleId>)> = Vec::new();
+        for row in rows {
+            let (Some(user_id), Some(role_id)) = (row.get_id("user_id"), row.get_id("role_id")) else {
+                continue;
+            };
+
+            let user_id = UserId::new(user_id);
+            match removals.last_mut() {
+                Some((last_user, roles)) if *last_user == user_id => roles.push(RoleId::new(role_id)),
+                _ => removals.push((user_id, vec![RoleId::new(role_id)])),
             }
+        }
+
+        return removals;
+    }
 
-            return Some(RoleId::new(id));
+    /// Get every server registered with the database
+    pub fn get_guild_ids(&self) -> Vec<GuildId> {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["get_guild_ids"]).start_timer();
+        let rows = self.query_or_log("SELECT guild_id FROM roles ORDER BY guild_id;".to_string());
+
+        return rows.iter().filter_map(|row| row.get_id("guild_id")).map(GuildId::new).collect();
+    }
+
+    /// Get everything configured for a server
+    ///
+    /// @param server_id ID of the server
+    ///
+    /// @return Configuration of the server, or None if it is not registered
+    pub fn get_guild_config(&self, server_id: &GuildId) -> Option<GuildConfig> {
+        if !self.get_guild_ids().contains(server_id) {
+            return None;
         }
 
-        return None;
+        return Some(GuildConfig {
+            guild_id: *server_id,
+            primary_role: self.get_primary_role(server_id),
+            auto_scan: self.is_auto_scan_enabled(server_id),
+            exempt_roles: self.get_exempt_roles(server_id),
+            policy_action: Some(self.get_policy_action(server_id)),
+            rules: self.get_rules(server_id).into_iter().map(|(_, rule)| rule).collect(),
+            role_groups: self.get_role_groups(server_id),
+            verification: self.get_verification(server_id),
+        });
+    }
+
+    /// Overwrite everything configured for a server, registering it if needed
+    ///
+    /// @param config New configuration of the server
+    pub fn set_guild_config(&mut self, config: &GuildConfig) -> SQLResult {
+        let _timer = metrics::DB_QUERY_DURATION.with_label_values(&["set_guild_config"]).start_timer();
+        let guild_id = config.guild_id.get();
+        let statement = format!(
+            "INSERT INTO roles (guild_id, role_id, auto_scan) VALUES({}, {}, {})
+              ON CONFLICT (guild_id) DO UPDATE SET role_id = excluded.role_id, auto_scan = excluded.auto_scan;
+            DELETE FROM exempt_roles WHERE guild_id = {};
+            DELETE FROM rules WHERE guild_id = {};
+            DELETE FROM role_groups WHERE guild_id = {};
+            DELETE FROM verification WHERE guild_id = {};",
+            guild_id,
+            config.primary_role.map_or("NULL".to_string(), |role| role.get().to_string()),
+            if config.auto_scan { "TRUE" } else { "FALSE" },
+            guild_id,
+            guild_id,
+            guild_id,
+            guild_id
+        );
+        let policy_action = serde_json::to_string(&config.policy_action.clone().unwrap_or_default()).map_err(|_| DbError::Unsupported("Storing this policy action"))?;
+        let rules = config.rules.iter().map(serde_json::to_string).collect::<Result<Vec<_>, _>>().map_err(|_| DbError::Unsupported("Storing this rule"))?;
+        let verification = config.verification.as_ref().map(serde_json::to_string).transpose().map_err(|_| DbError::Unsupported("Storing these verification settings"))?;
+
+        self.transaction("BEGIN;", |db| {
+            db.execute(statement)?;
+            db.update("UPDATE roles SET policy_action = ? WHERE guild_id = ?;", &[policy_action.as_str().into(), (guild_id as i64).into()])?;
+            for role in &config.exempt_roles {
+                db.execute(format!("INSERT INTO exempt_roles (guild_id, role_id) VALUES({}, {}) ON CONFLICT DO NOTHING;", guild_id, role.get()))?;
+            }
+            for rule in &rules {
+                db.update("INSERT INTO rules (guild_id, rule) VALUES(?, ?);", &[(guild_id as i64).into(), rule.as_str().into()])?;
+            }
+            for group in &config.role_groups {
+                for role in &group.roles {
+                    db.update(
+                        "INSERT INTO role_groups (guild_id, role_id, name) VALUES(?, ?, ?) ON CONFLICT (guild_id, role_id) DO UPDATE SET name = excluded.name;",
+                        &[(guild_id as i64).into(), (role.get() as i64).into(), group.name.as_str().into()],
+                    )?;
+                }
+            }
+            if let Some(verification) = &verification {
+                db.update("INSERT INTO verification (guild_id, settings) VALUES(?, ?);", &[(guild_id as i64).into(), verification.as_str().into()])?;
+            }
+            Ok(())
+        })
     }
 }
 
 #[cfg(test)]
 mod test {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use serenity::all::ChannelId;
+
     use super::*;
+    use crate::policy::MAX_TIMEOUT_MINUTES;
     use proptest::prelude::*;
     use proptest::test_runner::Config;
     use proptest_state_machine::{self, prop_state_machine, ReferenceStateMachine, StateMachineTest};
 
-    // Normal tests
+    // Normal tests, run against every backend
+
+    /// Database handed to a test, dropping its PostgreSQL schema once the test is done with it
+    struct TestDatabase {
+        app_data: AppData,
+        schema: Option<String>,
+    }
+
+    impl std::ops::Deref for TestDatabase {
+        type Target = AppData;
+
+        fn deref(&self) -> &AppData {
+            &self.app_data
+        }
+    }
+
+    impl std::ops::DerefMut for TestDatabase {
+        fn deref_mut(&mut self) -> &mut AppData {
+            &mut self.app_data
+        }
+    }
+
+    impl Drop for TestDatabase {
+        fn drop(&mut self) {
+            if let Some(schema) = &self.schema {
+                self.app_data.db.execute(format!("DROP SCHEMA IF EXISTS {schema} CASCADE;")).unwrap_or_else(|error| {
+                    eprintln!("Failed to drop test schema {}: {}", schema, error);
+                });
+            }
+        }
+    }
+
+    /// Fresh databases of every backend this build supports, without any migrations applied
+    fn empty_databases() -> Vec<TestDatabase> {
+        #[allow(unused_mut)]
+        let mut databases = vec![TestDatabase { app_data: AppData::open(":memory:").unwrap(), schema: None }];
+
+        #[cfg(feature = "postgres")]
+        databases.push(empty_postgres_database());
+
+        databases
+    }
+
+    /// Fresh databases of every backend this build supports, ready to use
+    fn test_databases() -> Vec<TestDatabase> {
+        empty_databases()
+            .into_iter()
+            .map(|mut database| {
+                database.migrate().unwrap();
+                database
+            })
+            .collect()
+    }
+
+    /// Connect to the PostgreSQL server given in TEST_DATABASE_URL, isolating each test in a schema of its own
+    #[cfg(feature = "postgres")]
+    fn empty_postgres_database() -> TestDatabase {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static NEXT_SCHEMA: AtomicUsize = AtomicUsize::new(0);
+
+        let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must point at a PostgreSQL database to test the postgres feature");
+        let database = AppData::connect(&url).unwrap();
+        let schema = format!("test_{}_{}", std::process::id(), NEXT_SCHEMA.fetch_add(1, Ordering::SeqCst));
+        database.db.execute(format!("DROP SCHEMA IF EXISTS {schema} CASCADE; CREATE SCHEMA {schema}; SET search_path TO {schema};")).unwrap();
+
+        TestDatabase { app_data: database, schema: Some(schema) }
+    }
 
     #[test]
     fn test_auto_scan() {
-        let mut test_subject = AppData::new(":memory:");
-        let guild1 = GuildId::new(1);
-        let guild2 = GuildId::new(2);
+        for mut test_subject in test_databases() {
+            let guild1 = GuildId::new(1);
+            let guild2 = GuildId::new(2);
+
+            test_subject.new_server(&guild1).unwrap();
+            test_subject.new_server(&guild2).unwrap();
+            test_subject.disable_auto_scan(&guild2).unwrap();
+
+            assert!(test_subject.is_auto_scan_enabled(&guild1));
+            assert!(!test_subject.is_auto_scan_enabled(&guild2));
+            assert!(!test_subject.is_auto_scan_enabled(&GuildId::new(37)));
+
+            test_subject.set_default_auto_scan(false);
+            test_subject.new_server(&GuildId::new(3)).unwrap();
+            test_subject.new_server(&guild1).unwrap();
+
+            assert!(!test_subject.is_auto_scan_enabled(&GuildId::new(3)));
+            assert!(test_subject.is_auto_scan_enabled(&guild1));
+
+            // Configuring a server registers it, even if the bot has not seen it yet
+            test_subject.update_server_primary_role(&GuildId::new(4), &RoleId::new(9)).unwrap();
+            test_subject.enable_auto_scan(&GuildId::new(5)).unwrap();
+            assert_eq!(Some(RoleId::new(9)), test_subject.get_primary_role(&GuildId::new(4)));
+            assert!(!test_subject.is_auto_scan_enabled(&GuildId::new(4)));
+            assert!(test_subject.is_auto_scan_enabled(&GuildId::new(5)));
+        }
+    }
+
+    #[test]
+    fn test_exempt_roles() {
+        for mut test_subject in test_databases() {
+            let guild1 = GuildId::new(1);
+            let guild2 = GuildId::new(2);
+
+            test_subject.add_exempt_role(&guild1, &RoleId::new(20)).unwrap();
+            test_subject.add_exempt_role(&guild1, &RoleId::new(10)).unwrap();
+            test_subject.add_exempt_role(&guild1, &RoleId::new(10)).unwrap();
+            test_subject.add_exempt_role(&guild2, &RoleId::new(30)).unwrap();
+            test_subject.remove_exempt_role(&guild2, &RoleId::new(30)).unwrap();
+
+            assert_eq!(vec![RoleId::new(10), RoleId::new(20)], test_subject.get_exempt_roles(&guild1));
+            assert!(test_subject.get_exempt_roles(&guild2).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_migrations() {
+        for mut test_subject in empty_databases() {
+            assert_eq!(MIGRATIONS.len(), test_subject.pending_migrations().unwrap());
+
+            assert_eq!(MIGRATIONS.len(), test_subject.migrate().unwrap());
+            assert_eq!(0, test_subject.pending_migrations().unwrap());
+            assert_eq!(0, test_subject.migrate().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_guild_config() {
+        for mut test_subject in test_databases() {
+            let guild1 = GuildId::new(1);
+            let config = GuildConfig {
+                guild_id: GuildId::new(2),
+                primary_role: Some(RoleId::new(5)),
+                auto_scan: false,
+                exempt_roles: vec![RoleId::new(6), RoleId::new(7)],
+                policy_action: Some(PolicyAction::Quarantine { role: RoleId::new(9) }),
+                rules: vec![Rule::MutuallyExclusive { roles: vec![RoleId::new(6), RoleId::new(10)] }],
+                role_groups: vec![RoleGroup { name: "colour".to_string(), roles: vec![RoleId::new(11), RoleId::new(12)] }],
+                verification: Some(VerificationSettings {
+                    channel: ChannelId::new(13),
+                    log_channel: None,
+                    rules: Some("Be nice".to_string()),
+                    cooldown_secs: 5,
+                    questions: vec!["Why join?".to_string()],
+                    review_channel: Some(ChannelId::new(14)),
+                }),
+            };
+
+            test_subject.new_server(&guild1).unwrap();
+            test_subject.add_exempt_role(&config.guild_id, &RoleId::new(8)).unwrap();
+            test_subject.set_guild_config(&config).unwrap();
+
+            assert_eq!(vec![guild1, config.guild_id], test_subject.get_guild_ids());
+            assert_eq!(Some(config.clone()), test_subject.get_guild_config(&config.guild_id));
+            assert_eq!(None, test_subject.get_guild_config(&GuildId::new(3)));
+            assert_eq!(None, test_subject.get_guild_config(&guild1).unwrap().primary_role);
+        }
+    }
+
+    #[test]
+    fn test_rules() {
+        for mut test_subject in test_databases() {
+            let guild = GuildId::new(1);
+            let requires = Rule::Requires { role: Some(RoleId::new(3)), required: RoleId::new(4) };
+            let exclusive = Rule::MutuallyExclusive { roles: vec![RoleId::new(5), RoleId::new(6)] };
+
+            let first = test_subject.add_rule(&guild, &requires).unwrap();
+            let second = test_subject.add_rule(&guild, &exclusive).unwrap();
+            test_subject.add_rule(&GuildId::new(2), &requires).unwrap();
+            assert_eq!(vec![(first, requires), (second, exclusive.clone())], test_subject.get_rules(&guild));
+
+            assert!(!test_subject.remove_rule(&GuildId::new(2), first).unwrap());
+            assert!(test_subject.remove_rule(&guild, first).unwrap());
+            assert!(!test_subject.remove_rule(&guild, first).unwrap());
+            assert_eq!(vec![(second, exclusive)], test_subject.get_rules(&guild));
+        }
+    }
+
+    #[test]
+    fn test_role_groups() {
+        for mut test_subject in test_databases() {
+            let guild = GuildId::new(1);
+            test_subject.add_group_role(&guild, "region", &RoleId::new(5)).unwrap();
+            test_subject.add_group_role(&guild, "colour", &RoleId::new(4)).unwrap();
+            test_subject.add_group_role(&guild, "colour", &RoleId::new(3)).unwrap();
+            test_subject.add_group_role(&GuildId::new(2), "colour", &RoleId::new(3)).unwrap();
+
+            let colour = RoleGroup { name: "colour".to_string(), roles: vec![RoleId::new(3), RoleId::new(4)] };
+            assert_eq!(vec![colour, RoleGroup { name: "region".to_string(), roles: vec![RoleId::new(5)] }], test_subject.get_role_groups(&guild));
+
+            // A role belongs to one group at most
+            test_subject.add_group_role(&guild, "region", &RoleId::new(4)).unwrap();
+            assert!(test_subject.remove_group_role(&guild, &RoleId::new(3)).unwrap());
+            assert!(!test_subject.remove_group_role(&guild, &RoleId::new(3)).unwrap());
+            assert_eq!(vec![RoleGroup { name: "region".to_string(), roles: vec![RoleId::new(4), RoleId::new(5)] }], test_subject.get_role_groups(&guild));
+        }
+    }
+
+    #[test]
+    fn test_temp_roles() {
+        for mut test_subject in test_databases() {
+            let (guild, user) = (GuildId::new(1), UserId::new(7));
+            let temp_role = |guild: u64, role: u64, expires_at: i64| TempRole { guild_id: GuildId::new(guild), user_id: user, role_id: RoleId::new(role), expires_at };
+            test_subject.add_temp_role(&guild, &user, &RoleId::new(3), 50).unwrap();
+            test_subject.add_temp_role(&guild, &user, &RoleId::new(4), 20).unwrap();
+            test_subject.add_temp_role(&GuildId::new(2), &user, &RoleId::new(3), 10).unwrap();
+            assert_eq!(vec![temp_role(1, 4, 20), temp_role(1, 3, 50)], test_subject.get_temp_roles(&guild));
+
+            // Giving the role again moves the expiry
+            test_subject.add_temp_role(&guild, &user, &RoleId::new(3), 15).unwrap();
+            assert_eq!(vec![temp_role(2, 3, 10), temp_role(1, 3, 15)], test_subject.get_expired_temp_roles(15));
+            assert!(test_subject.get_expired_temp_roles(9).is_empty());
+
+            assert!(test_subject.remove_temp_role(&guild, &user, &RoleId::new(3)).unwrap());
+            assert!(!test_subject.remove_temp_role(&guild, &user, &RoleId::new(3)).unwrap());
+            assert_eq!(vec![temp_role(1, 4, 20)], test_subject.get_temp_roles(&guild));
+        }
+    }
+
+    #[test]
+    fn test_verification() {
+        for mut test_subject in test_databases() {
+            let guild = GuildId::new(1);
+            let settings = VerificationSettings {
+                channel: ChannelId::new(2),
+                log_channel: Some(ChannelId::new(3)),
+                rules: Some("No 'spam'".to_string()),
+                cooldown_secs: 30,
+                questions: Vec::new(),
+                review_channel: None,
+            };
+            assert_eq!(None, test_subject.get_verification(&guild));
+
+            test_subject.set_verification(&guild, &settings).unwrap();
+            assert_eq!(Some(settings.clone()), test_subject.get_verification(&guild));
+            assert_eq!(None, test_subject.get_verification(&GuildId::new(2)));
+
+            let settings = VerificationSettings { rules: None, questions: vec!["Who invited you?".to_string()], review_channel: Some(ChannelId::new(4)), ..settings };
+            test_subject.set_verification(&guild, &settings).unwrap();
+            assert_eq!(Some(settings), test_subject.get_verification(&guild));
+
+            assert!(test_subject.remove_verification(&guild).unwrap());
+            assert!(!test_subject.remove_verification(&guild).unwrap());
+            assert_eq!(None, test_subject.get_verification(&guild));
+        }
+    }
+
+    #[test]
+    fn test_applications() {
+        for mut test_subject in test_databases() {
+            let guild = GuildId::new(1);
+            let answers = vec![("Why join?".to_string(), "To chat, 'mostly'".to_string())];
+            let application = |application_id: i64, guild: u64, user: u64| Application {
+                application_id,
+                guild_id: GuildId::new(guild),
+                user_id: UserId::new(user),
+                answers: answers.clone(),
+                submitted_at: 10,
+            };
+
+            assert_eq!(Some(1), test_subject.add_application(&guild, &UserId::new(5), &answers, 10).unwrap());
+            assert_eq!(Some(2), test_subject.add_application(&GuildId::new(2), &UserId::new(5), &answers, 10).unwrap());
+            assert_eq!(Some(3), test_subject.add_application(&guild, &UserId::new(6), &answers, 10).unwrap());
+            // A member waits for one review at a time, and turning them away does not use up an ID
+            assert_eq!(None, test_subject.add_application(&guild, &UserId::new(5), &answers, 11).unwrap());
+
+            assert_eq!(vec![application(1, 1, 5), application(3, 1, 6)], test_subject.get_applications(&guild));
+            assert_eq!(Some(application(2, 2, 5)), test_subject.get_application(2));
+            assert_eq!(None, test_subject.get_application(4));
+
+            assert!(test_subject.remove_application(1).unwrap());
+            assert!(!test_subject.remove_application(1).unwrap());
+            assert_eq!(None, test_subject.get_application(1));
+            assert_eq!(Some(4), test_subject.add_application(&guild, &UserId::new(5), &answers, 12).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_policy_action() {
+        for mut test_subject in test_databases() {
+            let guild = GuildId::new(1);
+            let action = PolicyAction::RemoveListed { roles: vec![RoleId::new(3), RoleId::new(4)] };
+            assert_eq!(PolicyAction::RemoveAll, test_subject.get_policy_action(&guild));
+
+            test_subject.set_policy_action(&guild, &action).unwrap();
+            assert_eq!(action, test_subject.get_policy_action(&guild));
+            assert_eq!(vec![guild], test_subject.get_guild_ids());
+
+            test_subject.update_server_primary_role(&guild, &RoleId::new(2)).unwrap();
+            assert_eq!(action, test_subject.get_policy_action(&guild));
+        }
+    }
+
+    #[test]
+    fn test_sweep_history() {
+        for mut test_subject in test_databases() {
+            let guild1 = GuildId::new(1);
+            let guild2 = GuildId::new(2);
+
+            assert_eq!(None, test_subject.get_last_sweep(&guild1));
+
+            let first = test_subject.start_sweep(&guild1, "a", 100, 100).unwrap().unwrap();
+            let second = test_subject.start_sweep(&guild1, "a", 200, 200).unwrap().unwrap();
+            test_subject.record_sweep_removal(second, &UserId::new(7), &[RoleId::new(3), RoleId::new(2)]).unwrap();
+            test_subject.record_sweep_removal(second, &UserId::new(5), &[RoleId::new(4)]).unwrap();
+
+            assert_eq!(Some(second), test_subject.get_last_sweep(&guild1));
+            assert!(!test_subject.is_sweep_undoable(&guild2, second));
+            assert_eq!(
+                vec![(UserId::new(5), vec![RoleId::new(4)]), (UserId::new(7), vec![RoleId::new(2), RoleId::new(3)])],
+                test_subject.get_sweep_removals(second)
+            );
+
+            // Claiming an undo holds the server, but the sweep stays undoable until the undo finishes
+            assert!(test_subject.claim_sweep_undo(&guild1, second, "a", 300, 400).unwrap());
+            assert!(test_subject.is_sweep_undoable(&guild1, second));
+            assert!(!test_subject.claim_sweep_undo(&guild1, second, "b", 350, 400).unwrap());
+            test_subject.finish_sweep_undo(second, "b").unwrap();
+            assert!(test_subject.is_sweep_undoable(&guild1, second));
+            test_subject.finish_sweep_undo(second, "a").unwrap();
+            assert!(!test_subject.is_sweep_undoable(&guild1, second));
+            assert_eq!(None, test_subject.running_sweep(&guild1, 350).unwrap());
+            assert_eq!(Some(first), test_subject.get_last_sweep(&guild1));
+        }
+    }
+
+    #[test]
+    fn test_sweep_ownership() {
+        for mut test_subject in test_databases() {
+            let guild1 = GuildId::new(1);
+            let guild2 = GuildId::new(2);
+
+            let sweep = test_subject.start_sweep(&guild1, "a", 100, 160).unwrap().unwrap();
+            assert_eq!(None, test_subject.start_sweep(&guild1, "b", 120, 180).unwrap());
+            assert!(test_subject.start_sweep(&guild2, "b", 120, 180).unwrap().is_some());
+            assert_eq!(Some((sweep, "a".to_string())), test_subject.running_sweep(&guild1, 150).unwrap());
+            assert!(!test_subject.claim_sweep_undo(&guild1, sweep, "b", 150, 210).unwrap());
 
-        test_subject.new_server(&guild1).unwrap();
-        test_subject.new_server(&guild2).unwrap();
-        test_subject.disable_auto_scan(&guild2).unwrap();
+            // Only the owner can extend the lease, and an expired lease frees the server
+            assert!(!test_subject.renew_sweep_lease(sweep, "b", 500).unwrap());
+            assert!(test_subject.renew_sweep_lease(sweep, "a", 200).unwrap());
+            assert_eq!(None, test_subject.start_sweep(&guild1, "b", 180, 240).unwrap());
+            assert_eq!(None, test_subject.running_sweep(&guild1, 200).unwrap());
 
-        assert_eq!(true, test_subject.is_auto_scan_enabled(&guild1));
-        assert_eq!(false, test_subject.is_auto_scan_enabled(&guild2));
-        assert_eq!(false, test_subject.is_auto_scan_enabled(&GuildId::new(37)));
+            test_subject.release_sweep(sweep, "a").unwrap();
+            assert_eq!(None, test_subject.running_sweep(&guild1, 150).unwrap());
+            assert!(test_subject.claim_sweep_undo(&guild1, sweep, "b", 150, 210).unwrap());
+            assert!(!test_subject.renew_sweep_lease(sweep, "a", 300).unwrap());
+        }
     }
 
-    // State machine test
+    // State machine test, comparing the database against a model of what each method should do
     impl std::fmt::Debug for AppData {
         fn fmt(&self, _f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
             Ok(())
@@ -146,41 +1437,573 @@ mod test {
       #[test]
       fn run_state_machine(
         sequential
-        1..30
+        1..40
         =>
         AppData
       );
     }
 
-    pub struct StateMachine;
+    /// Servers most transitions pick from, so they act on the same servers often enough to interact
+    const GUILD_POOL: [u64; 3] = [1, 2, 3];
+    const OWNERS: [&str; 2] = ["a", "b"];
+
+    /// Discord ID, mostly from a small pool so transitions collide
+    ///
+    /// Snowflakes stay below 2^63 until the 2080s, so IDs the database cannot store as a signed integer are not generated
+    fn id(pool: u64) -> impl Strategy<Value = u64> {
+        prop_oneof![4 => 1..=pool, 1 => 1..=i64::MAX as u64]
+    }
+
+    fn guild_id() -> impl Strategy<Value = u64> {
+        id(GUILD_POOL.len() as u64)
+    }
+
+    fn role_id() -> impl Strategy<Value = u64> {
+        id(5)
+    }
+
+    fn owner() -> impl Strategy<Value = String> {
+        prop::sample::select(&OWNERS[..]).prop_map(str::to_string)
+    }
+
+    fn rule() -> impl Strategy<Value = Rule> {
+        let roles = || prop::collection::vec(role_id().prop_map(RoleId::new), 1..3);
+
+        prop_oneof![
+            (prop::option::of(role_id()), role_id()).prop_map(|(role, required)| Rule::Requires { role: role.map(RoleId::new), required: RoleId::new(required) }),
+            (prop::option::of(role_id()), roles()).prop_map(|(role, any_of)| Rule::RequiresAnyOf { role: role.map(RoleId::new), any_of }),
+            roles().prop_map(|roles| Rule::MutuallyExclusive { roles }),
+        ]
+    }
+
+    fn group_name() -> impl Strategy<Value = String> {
+        prop::sample::select(&["colour", "region", "o'clock"][..]).prop_map(str::to_string)
+    }
+
+    fn policy_action() -> impl Strategy<Value = PolicyAction> {
+        prop_oneof![
+            Just(PolicyAction::RemoveAll),
+            prop::collection::vec(role_id().prop_map(RoleId::new), 1..3).prop_map(|roles| PolicyAction::RemoveListed { roles }),
+            role_id().prop_map(|role| PolicyAction::Quarantine { role: RoleId::new(role) }),
+            (1..=MAX_TIMEOUT_MINUTES).prop_map(|minutes| PolicyAction::Timeout { minutes }),
+            (0..30u32).prop_map(|after_days| PolicyAction::Kick { after_days }),
+        ]
+    }
+
+    fn verification() -> impl Strategy<Value = VerificationSettings> {
+        (id(3), prop::option::of(id(3)), prop::option::of("[a-z' ]{0,8}"), 0..120u32, prop::collection::vec("[a-z?]{1,8}", 0..3), prop::option::of(id(3))).prop_map(
+            |(channel, log_channel, rules, cooldown_secs, questions, review_channel)| VerificationSettings {
+                channel: ChannelId::new(channel),
+                log_channel: log_channel.map(ChannelId::new),
+                rules,
+                cooldown_secs,
+                questions,
+                review_channel: review_channel.map(ChannelId::new),
+            },
+        )
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct GuildModel {
+        role: Option<u64>,
+        auto_scan: bool,
+        action: PolicyAction,
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct SweepModel {
+        guild: u64,
+        owner: String,
+        lease_until: i64,
+        undone: bool,
+        removals: BTreeSet<(u64, u64)>,
+    }
+
+    /// Result a transition returned, compared between the model and the database
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum Observation {
+        Done,
+        Bool(bool),
+        Role(Option<RoleId>),
+        Roles(Vec<RoleId>),
+        Guilds(Vec<GuildId>),
+        Config(Option<GuildConfig>),
+        Action(PolicyAction),
+        Rule(i64),
+        Rules(Vec<(i64, Rule)>),
+        Groups(Vec<RoleGroup>),
+        TempRoles(Vec<TempRole>),
+        Verification(Option<VerificationSettings>),
+        NewApplication(Option<i64>),
+        Application(Option<Application>),
+        Applications(Vec<Application>),
+        Sweep(Option<i64>),
+        Running(Option<(i64, String)>),
+        Removals(Vec<(UserId, Vec<RoleId>)>),
+        Count(usize),
+    }
+
+    #[derive(Clone, Debug)]
+    pub struct Model {
+        default_auto_scan: bool,
+        guilds: BTreeMap<u64, GuildModel>,
+        /// Exempt roles by server and role, kept whether or not the server is registered
+        exempt_roles: BTreeSet<(u64, u64)>,
+        /// Sweeps in order, the first has ID 1
+        sweeps: Vec<SweepModel>,
+        /// Rules by ID, with the server they belong to
+        rules: BTreeMap<i64, (u64, Rule)>,
+        /// IDs handed out to rules so far, deleted ones are not reused
+        rule_ids: i64,
+        /// Group name by server and role
+        group_roles: BTreeMap<(u64, u64), String>,
+        /// Expiry of temporary roles by server, member and role
+        temp_roles: BTreeMap<(u64, u64, u64), i64>,
+        /// Verification settings by server, kept whether or not the server is registered
+        verification: BTreeMap<u64, VerificationSettings>,
+        /// Applications waiting for review by ID
+        applications: BTreeMap<i64, Application>,
+        /// IDs handed out to applications so far, deleted ones are not reused
+        application_ids: i64,
+        /// What the last transition should have returned
+        expected: Observation,
+    }
+
+    impl Model {
+        fn register(&mut self, guild: u64) -> &mut GuildModel {
+            let auto_scan = self.default_auto_scan;
+            self.guilds.entry(guild).or_insert(GuildModel { role: None, auto_scan, action: PolicyAction::default() })
+        }
+
+        fn exempt_roles(&self, guild: u64) -> Vec<RoleId> {
+            self.exempt_roles.iter().filter(|(exempt_guild, _)| *exempt_guild == guild).map(|(_, role)| RoleId::new(*role)).collect()
+        }
+
+        fn add_rule(&mut self, guild: u64, rule: Rule) -> i64 {
+            self.rule_ids += 1;
+            self.rules.insert(self.rule_ids, (guild, rule));
+            self.rule_ids
+        }
+
+        fn rules(&self, guild: u64) -> Vec<(i64, Rule)> {
+            self.rules.iter().filter(|(_, (rule_guild, _))| *rule_guild == guild).map(|(id, (_, rule))| (*id, rule.clone())).collect()
+        }
+
+        fn role_groups(&self, guild: u64) -> Vec<RoleGroup> {
+            let mut roles: Vec<(String, u64)> = self.group_roles.iter().filter(|((group_guild, _), _)| *group_guild == guild).map(|((_, role), name)| (name.clone(), *role)).collect();
+            roles.sort();
+
+            let mut groups: Vec<RoleGroup> = Vec::new();
+            for (name, role) in roles {
+                match groups.last_mut() {
+                    Some(group) if group.name == name => group.roles.push(RoleId::new(role)),
+                    _ => groups.push(RoleGroup { name, roles: vec![RoleId::new(role)] }),
+                }
+            }
+
+            groups
+        }
+
+        /// Temporary roles matching a filter, ordered by expiry, then server, member and role
+        fn temp_roles(&self, filter: impl Fn(u64, i64) -> bool) -> Vec<TempRole> {
+            let mut temp_roles: Vec<(i64, u64, u64, u64)> =
+                self.temp_roles.iter().filter(|((guild, _, _), expires_at)| filter(*guild, **expires_at)).map(|((guild, user, role), expires_at)| (*expires_at, *guild, *user, *role)).collect();
+            temp_roles.sort();
+
+            temp_roles
+                .into_iter()
+                .map(|(expires_at, guild, user, role)| TempRole { guild_id: GuildId::new(guild), user_id: UserId::new(user), role_id: RoleId::new(role), expires_at })
+                .collect()
+        }
+
+        /// Applications of a server, oldest first
+        fn applications(&self, guild: u64) -> Vec<Application> {
+            self.applications.values().filter(|application| application.guild_id.get() == guild).cloned().collect()
+        }
+
+        fn sweep(&self, sweep_id: i64) -> Option<&SweepModel> {
+            self.sweeps.get(usize::try_from(sweep_id).ok()?.checked_sub(1)?)
+        }
+
+        fn running_sweep(&self, guild: u64, now: i64) -> Option<(i64, String)> {
+            let mut sweeps = self.sweeps.iter().enumerate().rev();
+
+            sweeps.find(|(_, sweep)| sweep.guild == guild && sweep.lease_until > now).map(|(index, sweep)| (index as i64 + 1, sweep.owner.clone()))
+        }
+
+        fn is_sweep_undoable(&self, guild: u64, sweep_id: i64) -> bool {
+            self.sweep(sweep_id).is_some_and(|sweep| sweep.guild == guild && !sweep.undone)
+        }
+
+        fn last_sweep(&self, guild: u64) -> Option<i64> {
+            self.sweeps.iter().enumerate().rev().find(|(_, sweep)| sweep.guild == guild && !sweep.undone).map(|(index, _)| index as i64 + 1)
+        }
+
+        fn sweep_removals(&self, sweep_id: i64) -> Vec<(UserId, Vec<RoleId>)> {
+            let mut removals: Vec<(UserId, Vec<RoleId>)> = Vec::new();
+            for (user, role) in self.sweep(sweep_id).map(|sweep| sweep.removals.clone()).unwrap_or_default() {
+                match removals.last_mut() {
+                    Some((last_user, roles)) if last_user.get() == user => roles.push(RoleId::new(role)),
+                    _ => removals.push((UserId::new(user), vec![RoleId::new(role)])),
+                }
+            }
+
+            removals
+        }
+
+        fn guild_config(&self, guild: u64) -> Option<GuildConfig> {
+            let model = self.guilds.get(&guild)?;
+
+            Some(GuildConfig {
+                guild_id: GuildId::new(guild),
+                primary_role: model.role.map(RoleId::new),
+                auto_scan: model.auto_scan,
+                exempt_roles: self.exempt_roles(guild),
+                policy_action: Some(model.action.clone()),
+                rules: self.rules(guild).into_iter().map(|(_, rule)| rule).collect(),
+                role_groups: self.role_groups(guild),
+                verification: self.verification.get(&guild).cloned(),
+            })
+        }
+
+        /// Servers worth comparing between the model and the database
+        fn known_guilds(&self) -> BTreeSet<u64> {
+            let mut guilds: BTreeSet<u64> = GUILD_POOL.into_iter().collect();
+            guilds.extend(self.guilds.keys());
+            guilds.extend(self.exempt_roles.iter().map(|(guild, _)| *guild));
+            guilds.extend(self.sweeps.iter().map(|sweep| sweep.guild));
+            guilds.extend(self.rules.values().map(|(guild, _)| *guild));
+            guilds.extend(self.group_roles.keys().map(|(guild, _)| *guild));
+            guilds.extend(self.temp_roles.keys().map(|(guild, _, _)| *guild));
+            guilds.extend(self.verification.keys());
+            guilds.extend(self.applications.values().map(|application| application.guild_id.get()));
+
+            guilds
+        }
+    }
 
     #[derive(Clone, Debug)]
     pub enum Transition {
         NewServer(u64),
-        UpdateRole((u64, u64)),
+        SetDefaultAutoScan(bool),
+        UpdateRole(u64, u64),
         EnableScan(u64),
         DisableScan(u64),
+        IsAutoScanEnabled(u64),
+        GetPrimaryRole(u64),
+        AddExemptRole(u64, u64),
+        RemoveExemptRole(u64, u64),
+        GetExemptRoles(u64),
+        GetGuildIds,
+        GetGuildConfig(u64),
+        SetGuildConfig(GuildConfig),
+        SetPolicyAction(u64, PolicyAction),
+        GetPolicyAction(u64),
+        AddRule(u64, Rule),
+        RemoveRule(u64, i64),
+        GetRules(u64),
+        AddGroupRole(u64, String, u64),
+        RemoveGroupRole(u64, u64),
+        GetRoleGroups(u64),
+        AddTempRole { guild: u64, user: u64, role: u64, expires_at: i64 },
+        RemoveTempRole { guild: u64, user: u64, role: u64 },
+        GetTempRoles(u64),
+        GetExpiredTempRoles(i64),
+        SetVerification(u64, VerificationSettings),
+        RemoveVerification(u64),
+        GetVerification(u64),
+        AddApplication { guild: u64, user: u64, answers: Vec<(String, String)>, now: i64 },
+        RemoveApplication(i64),
+        GetApplication(i64),
+        GetApplications(u64),
+        StartSweep { guild: u64, owner: String, now: i64, lease_until: i64 },
+        ClaimSweepUndo { guild: u64, sweep_id: i64, owner: String, now: i64, lease_until: i64 },
+        RunningSweep { guild: u64, now: i64 },
+        RenewSweepLease { sweep_id: i64, owner: String, lease_until: i64 },
+        ReleaseSweep { sweep_id: i64, owner: String },
+        FinishSweepUndo { sweep_id: i64, owner: String },
+        RecordSweepRemoval { sweep_id: i64, user: u64, roles: Vec<u64> },
+        GetLastSweep(u64),
+        IsSweepUndoable(u64, i64),
+        GetSweepRemovals(i64),
+        Ping,
+        IntegrityCheck,
+        SchemaVersion,
+        PendingMigrations,
+        Migrate,
     }
 
+    pub struct StateMachine;
+
     impl ReferenceStateMachine for StateMachine {
-        type State = Vec<i32>;
+        type State = Model;
         type Transition = Transition;
 
-        fn init_state() -> proptest::prelude::BoxedStrategy<Self::State> {
-            Just(vec![]).boxed()
+        fn init_state() -> BoxedStrategy<Self::State> {
+            Just(Model {
+                default_auto_scan: true,
+                guilds: BTreeMap::new(),
+                exempt_roles: BTreeSet::new(),
+                sweeps: Vec::new(),
+                rules: BTreeMap::new(),
+                rule_ids: 0,
+                group_roles: BTreeMap::new(),
+                temp_roles: BTreeMap::new(),
+                verification: BTreeMap::new(),
+                applications: BTreeMap::new(),
+                application_ids: 0,
+                expected: Observation::Done,
+            })
+            .boxed()
         }
 
-        fn transitions(_state: &Self::State) -> BoxedStrategy<Self::Transition> {
+        fn transitions(state: &Self::State) -> BoxedStrategy<Self::Transition> {
+            let sweep_id = 1..=state.sweeps.len() as i64 + 1;
+            let time = 0..20i64;
+            let rule_id = 1..=state.rule_ids + 1;
+            let application_id = 1..=state.application_ids + 1;
+            let config = (
+                guild_id(),
+                prop::option::of(role_id()),
+                any::<bool>(),
+                prop::collection::vec(role_id(), 0..3),
+                prop::option::of(policy_action()),
+                prop::collection::vec(rule(), 0..3),
+                prop::collection::vec((group_name(), prop::collection::vec(role_id().prop_map(RoleId::new), 1..3)), 0..3),
+                prop::option::of(verification()),
+            )
+                .prop_map(|(guild, role, auto_scan, exempt, policy_action, rules, groups, verification)| GuildConfig {
+                    guild_id: GuildId::new(guild),
+                    primary_role: role.map(RoleId::new),
+                    auto_scan,
+                    exempt_roles: exempt.into_iter().map(RoleId::new).collect(),
+                    policy_action,
+                    rules,
+                    role_groups: groups.into_iter().map(|(name, roles)| RoleGroup { name, roles }).collect(),
+                    verification,
+                });
+
             prop_oneof![
-              1 => (any::<u64>()).prop_map(Transition::NewServer),
-              2 => (any::<u64>(), any::<u64>()).prop_map(Transition::UpdateRole),
-              3 => (any::<u64>()).prop_map(Transition::EnableScan),
-              4 => (any::<u64>()).prop_map(Transition::DisableScan)
+              3 => guild_id().prop_map(Transition::NewServer),
+              1 => any::<bool>().prop_map(Transition::SetDefaultAutoScan),
+              3 => (guild_id(), role_id()).prop_map(|(guild, role)| Transition::UpdateRole(guild, role)),
+              2 => guild_id().prop_map(Transition::EnableScan),
+              2 => guild_id().prop_map(Transition::DisableScan),
+              1 => guild_id().prop_map(Transition::IsAutoScanEnabled),
+              1 => guild_id().prop_map(Transition::GetPrimaryRole),
+              2 => (guild_id(), role_id()).prop_map(|(guild, role)| Transition::AddExemptRole(guild, role)),
+              1 => (guild_id(), role_id()).prop_map(|(guild, role)| Transition::RemoveExemptRole(guild, role)),
+              1 => guild_id().prop_map(Transition::GetExemptRoles),
+              1 => Just(Transition::GetGuildIds),
+              1 => guild_id().prop_map(Transition::GetGuildConfig),
+              2 => config.prop_map(Transition::SetGuildConfig),
+              2 => (guild_id(), policy_action()).prop_map(|(guild, action)| Transition::SetPolicyAction(guild, action)),
+              1 => guild_id().prop_map(Transition::GetPolicyAction),
+              2 => (guild_id(), rule()).prop_map(|(guild, rule)| Transition::AddRule(guild, rule)),
+              1 => (guild_id(), rule_id).prop_map(|(guild, rule_id)| Transition::RemoveRule(guild, rule_id)),
+              1 => guild_id().prop_map(Transition::GetRules),
+              2 => (guild_id(), group_name(), role_id()).prop_map(|(guild, name, role)| Transition::AddGroupRole(guild, name, role)),
+              1 => (guild_id(), role_id()).prop_map(|(guild, role)| Transition::RemoveGroupRole(guild, role)),
+              1 => guild_id().prop_map(Transition::GetRoleGroups),
+              2 => (guild_id(), id(3), role_id(), time.clone()).prop_map(|(guild, user, role, expires_at)| Transition::AddTempRole { guild, user, role, expires_at }),
+              1 => (guild_id(), id(3), role_id()).prop_map(|(guild, user, role)| Transition::RemoveTempRole { guild, user, role }),
+              1 => guild_id().prop_map(Transition::GetTempRoles),
+              1 => time.clone().prop_map(Transition::GetExpiredTempRoles),
+              2 => (guild_id(), verification()).prop_map(|(guild, settings)| Transition::SetVerification(guild, settings)),
+              1 => guild_id().prop_map(Transition::RemoveVerification),
+              1 => guild_id().prop_map(Transition::GetVerification),
+              2 => (guild_id(), id(3), prop::collection::vec(("[a-z?]{1,8}", "[a-z' ]{0,8}"), 0..3), time.clone())
+                  .prop_map(|(guild, user, answers, now)| Transition::AddApplication { guild, user, answers, now }),
+              1 => application_id.clone().prop_map(Transition::RemoveApplication),
+              1 => application_id.prop_map(Transition::GetApplication),
+              1 => guild_id().prop_map(Transition::GetApplications),
+              3 => (guild_id(), owner(), time.clone(), 0..20i64).prop_map(|(guild, owner, now, lease)| Transition::StartSweep { guild, owner, now, lease_until: now + lease }),
+              2 => (guild_id(), sweep_id.clone(), owner(), time.clone(), 0..20i64)
+                  .prop_map(|(guild, sweep_id, owner, now, lease)| Transition::ClaimSweepUndo { guild, sweep_id, owner, now, lease_until: now + lease }),
+              1 => (guild_id(), time.clone()).prop_map(|(guild, now)| Transition::RunningSweep { guild, now }),
+              1 => (sweep_id.clone(), owner(), time).prop_map(|(sweep_id, owner, lease_until)| Transition::RenewSweepLease { sweep_id, owner, lease_until }),
+              1 => (sweep_id.clone(), owner()).prop_map(|(sweep_id, owner)| Transition::ReleaseSweep { sweep_id, owner }),
+              1 => (sweep_id.clone(), owner()).prop_map(|(sweep_id, owner)| Transition::FinishSweepUndo { sweep_id, owner }),
+              2 => (sweep_id.clone(), id(3), prop::collection::vec(role_id(), 0..3)).prop_map(|(sweep_id, user, roles)| Transition::RecordSweepRemoval { sweep_id, user, roles }),
+              1 => guild_id().prop_map(Transition::GetLastSweep),
+              1 => (guild_id(), sweep_id.clone()).prop_map(|(guild, sweep_id)| Transition::IsSweepUndoable(guild, sweep_id)),
+              1 => sweep_id.prop_map(Transition::GetSweepRemovals),
+              1 => prop_oneof![
+                  Just(Transition::Ping),
+                  Just(Transition::IntegrityCheck),
+                  Just(Transition::SchemaVersion),
+                  Just(Transition::PendingMigrations),
+                  Just(Transition::Migrate),
+              ],
             ]
             .boxed()
         }
 
-        fn apply(state: Self::State, _transition: &Self::Transition) -> Self::State {
+        fn preconditions(state: &Self::State, transition: &Self::Transition) -> bool {
+            match transition {
+                // Removals always belong to a sweep, which PostgreSQL enforces with a foreign key
+                Transition::RecordSweepRemoval { sweep_id, .. } => state.sweep(*sweep_id).is_some(),
+                _ => true,
+            }
+        }
+
+        fn apply(mut state: Self::State, transition: &Self::Transition) -> Self::State {
+            state.expected = match transition {
+                Transition::NewServer(guild) => {
+                    state.register(*guild);
+                    Observation::Done
+                }
+                Transition::SetDefaultAutoScan(enabled) => {
+                    state.default_auto_scan = *enabled;
+                    Observation::Done
+                }
+                // Configuring a server the bot has not seen yet registers it, rather than being silently dropped
+                Transition::UpdateRole(guild, role) => {
+                    state.register(*guild).role = Some(*role);
+                    Observation::Done
+                }
+                Transition::EnableScan(guild) => {
+                    state.register(*guild).auto_scan = true;
+                    Observation::Done
+                }
+                Transition::DisableScan(guild) => {
+                    state.register(*guild).auto_scan = false;
+                    Observation::Done
+                }
+                Transition::IsAutoScanEnabled(guild) => Observation::Bool(state.guilds.get(guild).is_some_and(|guild| guild.auto_scan)),
+                Transition::GetPrimaryRole(guild) => Observation::Role(state.guilds.get(guild).and_then(|guild| guild.role).map(RoleId::new)),
+                Transition::AddExemptRole(guild, role) => {
+                    state.exempt_roles.insert((*guild, *role));
+                    Observation::Done
+                }
+                Transition::RemoveExemptRole(guild, role) => {
+                    state.exempt_roles.remove(&(*guild, *role));
+                    Observation::Done
+                }
+                Transition::GetExemptRoles(guild) => Observation::Roles(state.exempt_roles(*guild)),
+                Transition::GetGuildIds => Observation::Guilds(state.guilds.keys().map(|guild| GuildId::new(*guild)).collect()),
+                Transition::GetGuildConfig(guild) => Observation::Config(state.guild_config(*guild)),
+                Transition::SetGuildConfig(config) => {
+                    let guild = config.guild_id.get();
+                    state.guilds.insert(
+                        guild,
+                        GuildModel { role: config.primary_role.map(|role| role.get()), auto_scan: config.auto_scan, action: config.policy_action.clone().unwrap_or_default() },
+                    );
+                    state.exempt_roles.retain(|(exempt_guild, _)| *exempt_guild != guild);
+                    state.exempt_roles.extend(config.exempt_roles.iter().map(|role| (guild, role.get())));
+                    state.rules.retain(|_, (rule_guild, _)| *rule_guild != guild);
+                    for rule in &config.rules {
+                        state.add_rule(guild, rule.clone());
+                    }
+                    state.group_roles.retain(|(group_guild, _), _| *group_guild != guild);
+                    for group in &config.role_groups {
+                        state.group_roles.extend(group.roles.iter().map(|role| ((guild, role.get()), group.name.clone())));
+                    }
+                    match &config.verification {
+                        Some(settings) => state.verification.insert(guild, settings.clone()),
+                        None => state.verification.remove(&guild),
+                    };
+                    Observation::Done
+                }
+                Transition::SetPolicyAction(guild, action) => {
+                    state.register(*guild).action = action.clone();
+                    Observation::Done
+                }
+                Transition::GetPolicyAction(guild) => Observation::Action(state.guilds.get(guild).map(|guild| guild.action.clone()).unwrap_or_default()),
+                Transition::AddRule(guild, rule) => Observation::Rule(state.add_rule(*guild, rule.clone())),
+                Transition::RemoveRule(guild, rule_id) => {
+                    let exists = state.rules.get(rule_id).is_some_and(|(rule_guild, _)| rule_guild == guild);
+                    if exists {
+                        state.rules.remove(rule_id);
+                    }
+                    Observation::Bool(exists)
+                }
+                Transition::GetRules(guild) => Observation::Rules(state.rules(*guild)),
+                Transition::AddGroupRole(guild, name, role) => {
+                    state.group_roles.insert((*guild, *role), name.clone());
+                    Observation::Done
+                }
+                Transition::RemoveGroupRole(guild, role) => Observation::Bool(state.group_roles.remove(&(*guild, *role)).is_some()),
+                Transition::GetRoleGroups(guild) => Observation::Groups(state.role_groups(*guild)),
+                Transition::AddTempRole { guild, user, role, expires_at } => {
+                    state.temp_roles.insert((*guild, *user, *role), *expires_at);
+                    Observation::Done
+                }
+                Transition::RemoveTempRole { guild, user, role } => Observation::Bool(state.temp_roles.remove(&(*guild, *user, *role)).is_some()),
+                Transition::GetTempRoles(guild) => Observation::TempRoles(state.temp_roles(|temp_guild, _| temp_guild == *guild)),
+                Transition::GetExpiredTempRoles(now) => Observation::TempRoles(state.temp_roles(|_, expires_at| expires_at <= *now)),
+                Transition::SetVerification(guild, settings) => {
+                    state.verification.insert(*guild, settings.clone());
+                    Observation::Done
+                }
+                Transition::RemoveVerification(guild) => Observation::Bool(state.verification.remove(guild).is_some()),
+                Transition::GetVerification(guild) => Observation::Verification(state.verification.get(guild).cloned()),
+                Transition::AddApplication { guild, user, answers, now } => {
+                    let (guild_id, user_id) = (GuildId::new(*guild), UserId::new(*user));
+                    if state.applications.values().any(|application| application.guild_id == guild_id && application.user_id == user_id) {
+                        Observation::NewApplication(None)
+                    } else {
+                        state.application_ids += 1;
+                        let application_id = state.application_ids;
+                        state.applications.insert(application_id, Application { application_id, guild_id, user_id, answers: answers.clone(), submitted_at: *now });
+                        Observation::NewApplication(Some(application_id))
+                    }
+                }
+                Transition::RemoveApplication(application_id) => Observation::Bool(state.applications.remove(application_id).is_some()),
+                Transition::GetApplication(application_id) => Observation::Application(state.applications.get(application_id).cloned()),
+                Transition::GetApplications(guild) => Observation::Applications(state.applications(*guild)),
+                Transition::StartSweep { guild, owner, now, lease_until } => {
+                    if state.running_sweep(*guild, *now).is_some() {
+                        Observation::Sweep(None)
+                    } else {
+                        state.sweeps.push(SweepModel { guild: *guild, owner: owner.clone(), lease_until: *lease_until, undone: false, removals: BTreeSet::new() });
+                        Observation::Sweep(Some(state.sweeps.len() as i64))
+                    }
+                }
+                Transition::ClaimSweepUndo { guild, sweep_id, owner, now, lease_until } => {
+                    let claimed = state.running_sweep(*guild, *now).is_none() && state.is_sweep_undoable(*guild, *sweep_id);
+                    if claimed {
+                        let sweep = &mut state.sweeps[*sweep_id as usize - 1];
+                        sweep.owner = owner.clone();
+                        sweep.lease_until = *lease_until;
+                    }
+                    Observation::Bool(claimed)
+                }
+                Transition::RunningSweep { guild, now } => Observation::Running(state.running_sweep(*guild, *now)),
+                Transition::RenewSweepLease { sweep_id, owner, lease_until } => {
+                    let owned = state.sweep(*sweep_id).is_some_and(|sweep| sweep.owner == *owner);
+                    if owned {
+                        state.sweeps[*sweep_id as usize - 1].lease_until = *lease_until;
+                    }
+                    Observation::Bool(owned)
+                }
+                Transition::ReleaseSweep { sweep_id, owner } => {
+                    if state.sweep(*sweep_id).is_some_and(|sweep| sweep.owner == *owner) {
+                        state.sweeps[*sweep_id as usize - 1].lease_until = 0;
+                    }
+                    Observation::Done
+                }
+                Transition::FinishSweepUndo { sweep_id, owner } => {
+                    if state.sweep(*sweep_id).is_some_and(|sweep| sweep.owner == *owner) {
+                        let sweep = &mut state.sweeps[*sweep_id as usize - 1];
+                        sweep.undone = true;
+                        sweep.lease_until = 0;
+                    }
+                    Observation::Done
+                }
+                Transition::RecordSweepRemoval { sweep_id, user, roles } => {
+                    state.sweeps[*sweep_id as usize - 1].removals.extend(roles.iter().map(|role| (*user, *role)));
+                    Observation::Done
+                }
+                Transition::GetLastSweep(guild) => Observation::Sweep(state.last_sweep(*guild)),
+                Transition::IsSweepUndoable(guild, sweep_id) => Observation::Bool(state.is_sweep_undoable(*guild, *sweep_id)),
+                Transition::GetSweepRemovals(sweep_id) => Observation::Removals(state.sweep_removals(*sweep_id)),
+                Transition::Ping => Observation::Done,
+                Transition::IntegrityCheck => Observation::Bool(true),
+                Transition::SchemaVersion => Observation::Count(MIGRATIONS.len()),
+                Transition::PendingMigrations | Transition::Migrate => Observation::Count(0),
+            };
+
             state
         }
     }
@@ -195,25 +2018,97 @@ mod test {
 
         fn apply(
             mut state: Self::SystemUnderTest,
-            _ref_state: &<Self::Reference as ReferenceStateMachine>::State,
+            ref_state: &<Self::Reference as ReferenceStateMachine>::State,
             transition: <Self::Reference as ReferenceStateMachine>::Transition,
         ) -> Self::SystemUnderTest {
-            match transition {
-                Transition::NewServer(value) => {
-                    let _ = state.new_server(&GuildId::new(value));
+            let observed = match &transition {
+                Transition::NewServer(guild) => state.new_server(&GuildId::new(*guild)).map(|_| Observation::Done),
+                Transition::SetDefaultAutoScan(enabled) => {
+                    state.set_default_auto_scan(*enabled);
+                    Ok(Observation::Done)
                 }
-                Transition::UpdateRole(values) => {
-                    let _ = state.update_server_primary_role(&GuildId::new(values.0), &RoleId::new(values.1));
+                Transition::UpdateRole(guild, role) => state.update_server_primary_role(&GuildId::new(*guild), &RoleId::new(*role)).map(|_| Observation::Done),
+                Transition::EnableScan(guild) => state.enable_auto_scan(&GuildId::new(*guild)).map(|_| Observation::Done),
+                Transition::DisableScan(guild) => state.disable_auto_scan(&GuildId::new(*guild)).map(|_| Observation::Done),
+                Transition::IsAutoScanEnabled(guild) => Ok(Observation::Bool(state.is_auto_scan_enabled(&GuildId::new(*guild)))),
+                Transition::GetPrimaryRole(guild) => Ok(Observation::Role(state.get_primary_role(&GuildId::new(*guild)))),
+                Transition::AddExemptRole(guild, role) => state.add_exempt_role(&GuildId::new(*guild), &RoleId::new(*role)).map(|_| Observation::Done),
+                Transition::RemoveExemptRole(guild, role) => state.remove_exempt_role(&GuildId::new(*guild), &RoleId::new(*role)).map(|_| Observation::Done),
+                Transition::GetExemptRoles(guild) => Ok(Observation::Roles(state.get_exempt_roles(&GuildId::new(*guild)))),
+                Transition::GetGuildIds => Ok(Observation::Guilds(state.get_guild_ids())),
+                Transition::GetGuildConfig(guild) => Ok(Observation::Config(state.get_guild_config(&GuildId::new(*guild)))),
+                Transition::SetGuildConfig(config) => state.set_guild_config(config).map(|_| Observation::Done),
+                Transition::SetPolicyAction(guild, action) => state.set_policy_action(&GuildId::new(*guild), action).map(|_| Observation::Done),
+                Transition::GetPolicyAction(guild) => Ok(Observation::Action(state.get_policy_action(&GuildId::new(*guild)))),
+                Transition::AddRule(guild, rule) => state.add_rule(&GuildId::new(*guild), rule).map(Observation::Rule),
+                Transition::RemoveRule(guild, rule_id) => state.remove_rule(&GuildId::new(*guild), *rule_id).map(Observation::Bool),
+                Transition::GetRules(guild) => Ok(Observation::Rules(state.get_rules(&GuildId::new(*guild)))),
+                Transition::AddGroupRole(guild, name, role) => state.add_group_role(&GuildId::new(*guild), name, &RoleId::new(*role)).map(|_| Observation::Done),
+                Transition::RemoveGroupRole(guild, role) => state.remove_group_role(&GuildId::new(*guild), &RoleId::new(*role)).map(Observation::Bool),
+                Transition::GetRoleGroups(guild) => Ok(Observation::Groups(state.get_role_groups(&GuildId::new(*guild)))),
+                Transition::AddTempRole { guild, user, role, expires_at } => {
+                    state.add_temp_role(&GuildId::new(*guild), &UserId::new(*user), &RoleId::new(*role), *expires_at).map(|_| Observation::Done)
                 }
-                Transition::EnableScan(value) => {
-                    let _ = state.enable_auto_scan(&GuildId::new(value));
+                Transition::RemoveTempRole { guild, user, role } => state.remove_temp_role(&GuildId::new(*guild), &UserId::new(*user), &RoleId::new(*role)).map(Observation::Bool),
+                Transition::GetTempRoles(guild) => Ok(Observation::TempRoles(state.get_temp_roles(&GuildId::new(*guild)))),
+                Transition::GetExpiredTempRoles(now) => Ok(Observation::TempRoles(state.get_expired_temp_roles(*now))),
+                Transition::SetVerification(guild, settings) => state.set_verification(&GuildId::new(*guild), settings).map(|_| Observation::Done),
+                Transition::RemoveVerification(guild) => state.remove_verification(&GuildId::new(*guild)).map(Observation::Bool),
+                Transition::GetVerification(guild) => Ok(Observation::Verification(state.get_verification(&GuildId::new(*guild)))),
+                Transition::AddApplication { guild, user, answers, now } => {
+                    state.add_application(&GuildId::new(*guild), &UserId::new(*user), answers, *now).map(Observation::NewApplication)
                 }
-                Transition::DisableScan(value) => {
-                    let _ = state.disable_auto_scan(&GuildId::new(value));
+                Transition::RemoveApplication(application_id) => state.remove_application(*application_id).map(Observation::Bool),
+                Transition::GetApplication(application_id) => Ok(Observation::Application(state.get_application(*application_id))),
+                Transition::GetApplications(guild) => Ok(Observation::Applications(state.get_applications(&GuildId::new(*guild)))),
+                Transition::StartSweep { guild, owner, now, lease_until } => state.start_sweep(&GuildId::new(*guild), owner, *now, *lease_until).map(Observation::Sweep),
+                Transition::ClaimSweepUndo { guild, sweep_id, owner, now, lease_until } => {
+                    state.claim_sweep_undo(&GuildId::new(*guild), *sweep_id, owner, *now, *lease_until).map(Observation::Bool)
                 }
-            }
+                Transition::RunningSweep { guild, now } => state.running_sweep(&GuildId::new(*guild), *now).map(Observation::Running),
+                Transition::RenewSweepLease { sweep_id, owner, lease_until } => state.renew_sweep_lease(*sweep_id, owner, *lease_until).map(Observation::Bool),
+                Transition::ReleaseSweep { sweep_id, owner } => state.release_sweep(*sweep_id, owner).map(|_| Observation::Done),
+                Transition::FinishSweepUndo { sweep_id, owner } => state.finish_sweep_undo(*sweep_id, owner).map(|_| Observation::Done),
+                Transition::RecordSweepRemoval { sweep_id, user, roles } => {
+                    let roles = roles.iter().map(|role| RoleId::new(*role)).collect::<Vec<_>>();
+                    state.record_sweep_removal(*sweep_id, &UserId::new(*user), &roles).map(|_| Observation::Done)
+                }
+                Transition::GetLastSweep(guild) => Ok(Observation::Sweep(state.get_last_sweep(&GuildId::new(*guild)))),
+                Transition::IsSweepUndoable(guild, sweep_id) => Ok(Observation::Bool(state.is_sweep_undoable(&GuildId::new(*guild), *sweep_id))),
+                Transition::GetSweepRemovals(sweep_id) => Ok(Observation::Removals(state.get_sweep_removals(*sweep_id))),
+                Transition::Ping => state.ping().map(|_| Observation::Done),
+                Transition::IntegrityCheck => state.integrity_check().map(Observation::Bool),
+                Transition::SchemaVersion => state.schema_version().map(Observation::Count),
+                Transition::PendingMigrations => state.pending_migrations().map(Observation::Count),
+                Transition::Migrate => state.migrate().map(Observation::Count),
+            };
+
+            let observed = observed.unwrap_or_else(|error| panic!("{:?} failed: {}", transition, error));
+            assert_eq!(ref_state.expected, observed, "{:?} returned the wrong result", transition);
 
             state
         }
+
+        fn check_invariants(state: &Self::SystemUnderTest, ref_state: &<Self::Reference as ReferenceStateMachine>::State) {
+            assert_eq!(ref_state.guilds.keys().map(|guild| GuildId::new(*guild)).collect::<Vec<_>>(), state.get_guild_ids());
+
+            for guild in ref_state.known_guilds() {
+                let guild_id = GuildId::new(guild);
+                assert_eq!(ref_state.guild_config(guild), state.get_guild_config(&guild_id));
+                assert_eq!(ref_state.exempt_roles(guild), state.get_exempt_roles(&guild_id));
+                assert_eq!(ref_state.rules(guild), state.get_rules(&guild_id));
+                assert_eq!(ref_state.role_groups(guild), state.get_role_groups(&guild_id));
+                assert_eq!(ref_state.temp_roles(|temp_guild, _| temp_guild == guild), state.get_temp_roles(&guild_id));
+                assert_eq!(ref_state.verification.get(&guild).cloned(), state.get_verification(&guild_id));
+                assert_eq!(ref_state.applications(guild), state.get_applications(&guild_id));
+                assert_eq!(ref_state.last_sweep(guild), state.get_last_sweep(&guild_id));
+            }
+
+            for (index, sweep) in ref_state.sweeps.iter().enumerate() {
+                let sweep_id = index as i64 + 1;
+                assert_eq!(!sweep.undone, state.is_sweep_undoable(&GuildId::new(sweep.guild), sweep_id));
+                assert_eq!(ref_state.sweep_removals(sweep_id), state.get_sweep_removals(sweep_id));
+            }
+        }
     }
 }
@@ -0,0 +1,197 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serenity::all::GuildId;
+
+use crate::data::{AppData, GuildConfig};
+
+/// Version written to new documents, bumped whenever the format changes incompatibly
+pub const DOCUMENT_VERSION: u32 = 1;
+
+/// Portable copy of the configuration of one or more servers
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ConfigDocument {
+    pub version: u32,
+    pub guilds: Vec<GuildConfig>,
+}
+
+/// How imported settings are combined with what is already stored
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum ImportMode {
+    /// Keep existing settings the document leaves out, and add to the existing exempt roles
+    #[default]
+    Merge,
+    /// Overwrite the stored settings of every server in the document
+    Replace,
+}
+
+impl ImportMode {
+    /// Parse the mode given to a slash command option
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::from_str(name, true).ok()
+    }
+}
+
+impl ConfigDocument {
+    /// Export the configuration of the given servers, skipping any that are not registered
+    ///
+    /// @param app_data Database to export from
+    /// @param guilds Servers to export
+    pub fn export(app_data: &AppData, guilds: &[GuildId]) -> Self {
+        Self {
+            version: DOCUMENT_VERSION,
+            guilds: guilds.iter().filter_map(|guild_id| app_data.get_guild_config(guild_id)).collect(),
+        }
+    }
+
+    /// Parse a document, rejecting versions this build does not understand
+    ///
+    /// @param document JSON text of the document
+    ///
+    /// @return Parsed document, or a message describing the problem
+    pub fn parse(document: &str) -> Result<Self, String> {
+        let parsed: Self = serde_json::from_str(document).map_err(|error| format!("Invalid configuration document: {}", error))?;
+
+        if parsed.version == 0 || parsed.version > DOCUMENT_VERSION {
+            return Err(format!("Unsupported configuration document version {}, expected at most {}", parsed.version, DOCUMENT_VERSION));
+        }
+
+        return Ok(parsed);
+    }
+
+    /// Serialize the document as pretty printed JSON
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self).map_err(|error| format!("Failed to serialize the configuration: {}", error))
+    }
+
+    /// Write the servers in this document to the database
+    ///
+    /// @param app_data Database to import into
+    /// @param mode How to combine the document with the stored settings
+    /// @param only_guild Restrict the import to this server, ignoring the rest of the document
+    ///
+    /// @return Number of servers imported, or a message describing the failure
+    pub fn import(&self, app_data: &mut AppData, mode: ImportMode, only_guild: Option<GuildId>) -> Result<usize, String> {
+        let mut imported = 0;
+
+        for guild in self.guilds.iter().filter(|guild| only_guild.is_none_or(|only| only == guild.guild_id)) {
+            let config = match (mode, app_data.get_guild_config(&guild.guild_id)) {
+                (ImportMode::Merge, Some(existing)) => merge(existing, guild),
+                _ => guild.clone(),
+            };
+
+            app_data.set_guild_config(&config).map_err(|error| format!("Failed to import server {}: {}", guild.guild_id, error))?;
+            imported += 1;
+        }
+
+        return Ok(imported);
+    }
+}
+
+/// Layer imported settings on top of the stored ones
+fn merge(mut existing: GuildConfig, imported: &GuildConfig) -> GuildConfig {
+    existing.primary_role = imported.primary_role.or(existing.primary_role);
+    existing.auto_scan = imported.auto_scan;
+    existing.policy_action = imported.policy_action.clone().or(existing.policy_action);
+    existing.verification = imported.verification.clone().or(existing.verification);
+
+    for role in &imported.exempt_roles {
+        if !existing.exempt_roles.contains(role) {
+            existing.exempt_roles.push(*role);
+        }
+    }
+    for rule in &imported.rules {
+        if !existing.rules.contains(rule) {
+            existing.rules.push(rule.clone());
+        }
+    }
+    for group in &imported.role_groups {
+        // Imported groups win, as a role can only be in one group
+        for existing_group in &mut existing.role_groups {
+            existing_group.roles.retain(|role| !group.roles.contains(role));
+        }
+        match existing.role_groups.iter_mut().find(|existing_group| existing_group.name == group.name) {
+            Some(existing_group) => existing_group.roles.extend(group.roles.iter().copied()),
+            None => existing.role_groups.push(group.clone()),
+        }
+    }
+
+    existing
+}
+
+#[cfg(test)]
+mod test {
+    use serenity::all::RoleId;
+
+    use super::*;
+    use crate::policy::PolicyAction;
+
+    fn guild(id: u64, primary_role: Option<u64>, exempt_roles: &[u64]) -> GuildConfig {
+        GuildConfig {
+            guild_id: GuildId::new(id),
+            primary_role: primary_role.map(RoleId::new),
+            auto_scan: true,
+            exempt_roles: exempt_roles.iter().map(|role| RoleId::new(*role)).collect(),
+            policy_action: Some(PolicyAction::default()),
+            rules: Vec::new(),
+            role_groups: Vec::new(),
+            verification: None,
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut source = AppData::new(":memory:");
+        source.set_guild_config(&guild(1, Some(10), &[11])).unwrap();
+        source.set_guild_config(&GuildConfig { policy_action: Some(PolicyAction::Kick { after_days: 3 }), ..guild(2, None, &[]) }).unwrap();
+
+        let document = ConfigDocument::export(&source, &source.get_guild_ids());
+        let parsed = ConfigDocument::parse(&document.to_json().unwrap()).unwrap();
+        assert_eq!(document, parsed);
+
+        let mut target = AppData::new(":memory:");
+        assert_eq!(1, parsed.import(&mut target, ImportMode::Replace, Some(GuildId::new(2))).unwrap());
+        assert_eq!(vec![GuildId::new(2)], target.get_guild_ids());
+        assert_eq!(PolicyAction::Kick { after_days: 3 }, target.get_policy_action(&GuildId::new(2)));
+    }
+
+    #[test]
+    fn test_import_modes() {
+        let document = ConfigDocument {
+            version: DOCUMENT_VERSION,
+            guilds: vec![guild(1, None, &[12])],
+        };
+
+        let mut merged = AppData::new(":memory:");
+        merged.set_guild_config(&guild(1, Some(10), &[11])).unwrap();
+        document.import(&mut merged, ImportMode::Merge, None).unwrap();
+        assert_eq!(Some(guild(1, Some(10), &[11, 12])), merged.get_guild_config(&GuildId::new(1)));
+
+        let mut replaced = AppData::new(":memory:");
+        replaced.set_guild_config(&guild(1, Some(10), &[11])).unwrap();
+        document.import(&mut replaced, ImportMode::Replace, None).unwrap();
+        assert_eq!(Some(guild(1, None, &[12])), replaced.get_guild_config(&GuildId::new(1)));
+    }
+
+    #[test]
+    fn test_merge_keeps_missing_action() {
+        // Written before policy actions existed
+        let document = ConfigDocument::parse(r#"{"version": 1, "guilds": [{"guild_id": "1", "primary_role": "10", "auto_scan": true}]}"#).unwrap();
+        let kick = GuildConfig { policy_action: Some(PolicyAction::Kick { after_days: 3 }), ..guild(1, Some(10), &[]) };
+
+        let mut merged = AppData::new(":memory:");
+        merged.set_guild_config(&kick).unwrap();
+        document.import(&mut merged, ImportMode::Merge, None).unwrap();
+        assert_eq!(PolicyAction::Kick { after_days: 3 }, merged.get_policy_action(&GuildId::new(1)));
+
+        let mut replaced = AppData::new(":memory:");
+        replaced.set_guild_config(&kick).unwrap();
+        document.import(&mut replaced, ImportMode::Replace, None).unwrap();
+        assert_eq!(PolicyAction::RemoveAll, replaced.get_policy_action(&GuildId::new(1)));
+    }
+
+    #[test]
+    fn test_rejects_unknown_versions() {
+        assert!(ConfigDocument::parse(r#"{"version": 99, "guilds": []}"#).is_err());
+        assert!(ConfigDocument::parse(r#"[]"#).is_err());
+    }
+}
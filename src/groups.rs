@@ -0,0 +1,106 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use serde::{Deserialize, Serialize};
+use serenity::all::{GuildId, Mentionable, RoleId, UserId};
+
+/// Roles of which members may only hold one, the latest one they gain replacing the others
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RoleGroup {
+    pub name: String,
+    pub roles: Vec<RoleId>,
+}
+
+impl RoleGroup {
+    /// Describe the group for display to server admins
+    pub fn describe(&self) -> String {
+        return format!("{}: {}", self.name, self.roles.iter().map(|role| role.mention().to_string()).collect::<Vec<_>>().join(", "));
+    }
+}
+
+/// Roles of members as of the last update the bot saw, so it can tell which roles an update added
+///
+/// Only members of servers with role groups are remembered, and nothing survives a restart
+#[derive(Default)]
+pub struct RoleHistory(Mutex<HashMap<(GuildId, UserId), Vec<RoleId>>>);
+
+impl RoleHistory {
+    /// Remember the roles of a member, returning the ones remembered before
+    ///
+    /// @param guild_id ID of the member's server
+    /// @param user_id ID of the member
+    /// @param roles Roles the member holds now
+    ///
+    /// @return Roles the member held at the previous update, None if the bot has not seen them since starting
+    pub fn replace(&self, guild_id: GuildId, user_id: UserId, roles: Vec<RoleId>) -> Option<Vec<RoleId>> {
+        return self.0.lock().unwrap().insert((guild_id, user_id), roles);
+    }
+}
+
+/// Work out which roles to take from a member so they hold at most one role of each group
+///
+/// The roles the member just gained are kept. If the update gained none or several of a group's roles, the role
+/// listed first in the group is kept. Nothing is removed when the previous roles are unknown, as the bot cannot
+/// tell which role is the member's new pick
+///
+/// @param groups Role groups of the member's server
+/// @param previous Roles the member held before the update, None if unknown
+/// @param current Roles the member holds now
+/// @param exempt_roles Roles the bot must never remove
+///
+/// @return Roles to remove
+pub fn removals(groups: &[RoleGroup], previous: Option<&[RoleId]>, current: &[RoleId], exempt_roles: &[RoleId]) -> Vec<RoleId> {
+    let mut removed = Vec::new();
+    let Some(previous) = previous else {
+        return removed;
+    };
+
+    for group in groups {
+        let held: Vec<RoleId> = group.roles.iter().filter(|role| current.contains(role)).copied().collect();
+        if held.len() < 2 {
+            continue;
+        }
+
+        let gained: Vec<RoleId> = held.iter().filter(|role| !previous.contains(role)).copied().collect();
+        let keep = if gained.len() == 1 { gained[0] } else { held[0] };
+        removed.extend(held.into_iter().filter(|role| *role != keep && !exempt_roles.contains(role)));
+    }
+
+    return removed;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn roles(ids: &[u64]) -> Vec<RoleId> {
+        ids.iter().map(|id| RoleId::new(*id)).collect()
+    }
+
+    #[test]
+    fn test_removals() {
+        let groups = [RoleGroup { name: "colour".to_string(), roles: roles(&[1, 2, 3]) }, RoleGroup { name: "region".to_string(), roles: roles(&[4, 5]) }];
+
+        // Gaining a colour replaces the old one, leaving other groups alone
+        assert_eq!(roles(&[1]), removals(&groups, Some(&roles(&[1, 4])), &roles(&[1, 4, 3]), &[]));
+        assert!(removals(&groups, Some(&roles(&[1])), &roles(&[1, 4, 6]), &[]).is_empty());
+
+        // Gaining several roles of a group at once keeps the first of them
+        assert_eq!(roles(&[3]), removals(&groups, Some(&roles(&[6])), &roles(&[2, 3]), &[]));
+
+        // Without knowing what was gained, nothing is removed rather than risk taking the new pick
+        assert!(removals(&groups, None, &roles(&[2, 1, 4, 5]), &[]).is_empty());
+
+        // Exempt roles stay
+        assert!(removals(&groups, Some(&roles(&[1])), &roles(&[1, 2]), &roles(&[1])).is_empty());
+    }
+
+    #[test]
+    fn test_history() {
+        let history = RoleHistory::default();
+        let (guild, user) = (GuildId::new(1), UserId::new(2));
+
+        assert_eq!(None, history.replace(guild, user, roles(&[1])));
+        assert_eq!(Some(roles(&[1])), history.replace(guild, user, roles(&[2])));
+        assert_eq!(None, history.replace(GuildId::new(3), user, Vec::new()));
+    }
+}
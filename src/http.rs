@@ -0,0 +1,115 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
+use log::{error, info};
+use serde_json::{json, Value};
+use tokio::{net::TcpListener, sync::Mutex};
+
+use crate::{data::AppData, metrics, shutdown::Shutdown, status::BotStatus};
+
+/// How long to wait for the database before reporting it as unreachable
+const DATABASE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long the gateway may stay disconnected before the bot is considered wedged
+const GATEWAY_GRACE_PERIOD: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Clone)]
+struct HttpState {
+    status: Arc<BotStatus>,
+    app_data: Arc<Mutex<AppData>>,
+    shutdown: Arc<Shutdown>,
+}
+
+/// Serve the health and metrics endpoints until the process exits
+///
+/// @param listen Address to listen on
+/// @param status Live bot status
+/// @param app_data Shared database, checked for reachability
+/// @param shutdown Shutdown state, the bot stops reporting ready once it starts draining
+pub async fn serve(listen: SocketAddr, status: Arc<BotStatus>, app_data: Arc<Mutex<AppData>>, shutdown: Arc<Shutdown>) {
+    let router = Router::new()
+        .route("/healthz", get(liveness))
+        .route("/readyz", get(readiness))
+        .route("/metrics", get(render_metrics))
+        .with_state(HttpState { status, app_data, shutdown });
+
+    let listener = match TcpListener::bind(listen).await {
+        Ok(listener) => listener,
+        Err(error) => {
+            error!("Failed to listen for HTTP requests on {}: {}", listen, error);
+            return;
+        }
+    };
+
+    info!("Serving health and metrics endpoints on {}", listen);
+    if let Err(error) = axum::serve(listener, router).await {
+        error!("HTTP server stopped: {}", error);
+    }
+}
+
+/// Check that the database lock can be taken and the database answers a query
+async fn database_reachable(app_data: &Mutex<AppData>) -> bool {
+    match tokio::time::timeout(DATABASE_TIMEOUT, app_data.lock()).await {
+        Ok(app_data) => app_data.ping().is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Describe the bot's state for the health endpoints
+///
+/// @param state Shared state of the HTTP server
+/// @param database Whether the database could be reached, None if it was not checked
+fn report(state: &HttpState, database: Option<bool>) -> Value {
+    let (connected_shards, total_shards) = state.status.shard_counts();
+
+    json!({
+        "gateway": if state.status.is_connected() { "connected" } else { "disconnected" },
+        "database": match database { Some(true) => "ok", Some(false) => "unreachable", None => "not checked" },
+        "running_sweeps": state.status.running_sweeps(),
+        "shutting_down": state.shutdown.is_shutting_down(),
+        "shards": { "connected": connected_shards, "total": total_shards },
+    })
+}
+
+/// Fails when the bot looks wedged, so it gets restarted
+///
+/// Answering at all shows the runtime is alive. The database is left to the readiness check, as commands and member
+/// updates hold its lock while they wait on Discord, and restarting the bot would not make a slow database any faster
+async fn liveness(State(state): State<HttpState>) -> (StatusCode, Json<Value>) {
+    let wedged = state.status.disconnected_for().is_some_and(|duration| duration > GATEWAY_GRACE_PERIOD);
+
+    let code = if !wedged { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    return (code, Json(report(&state, None)));
+}
+
+/// Succeeds only while the bot is connected to Discord, can reach its database and is not shutting down
+async fn readiness(State(state): State<HttpState>) -> (StatusCode, Json<Value>) {
+    let database = database_reachable(&state.app_data).await;
+
+    let ready = database && state.status.is_connected() && !state.shutdown.is_shutting_down();
+    let code = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    return (code, Json(report(&state, Some(database))));
+}
+
+/// Export every metric in the Prometheus text format
+async fn render_metrics() -> String {
+    metrics::render()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_readiness() {
+        let state = HttpState { status: Arc::new(BotStatus::default()), app_data: Arc::new(Mutex::new(AppData::new(":memory:"))), shutdown: Arc::new(Shutdown::default()) };
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, readiness(State(state.clone())).await.0);
+
+        state.status.set_connected(0, true);
+        assert_eq!(StatusCode::OK, readiness(State(state.clone())).await.0);
+
+        // Draining bots stop taking traffic, but are not restarted for it
+        state.shutdown.shutdown(Duration::ZERO).await;
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, readiness(State(state.clone())).await.0);
+        assert_eq!(StatusCode::OK, liveness(State(state)).await.0);
+    }
+}
@@ -1,52 +1,167 @@
-use data::AppData;
+#![allow(clippy::needless_return)] // Explicit returns are the style of this codebase
+
+use config::{Cli, Config, ConfigKey, Sharding};
+use data::{AppData, AppDataKey};
 use futures::future::OptionFuture;
+use groups::RoleHistory;
 use log::*;
 use phf::phf_map;
+use policy::MemberSnapshot;
 use serenity::{all::*, async_trait, Client};
-use std::fs;
+use shutdown::{Shutdown, ShutdownKey};
+use status::{BotStatus, BotStatusKey};
+use std::{process, sync::Arc};
 use tokio::sync::Mutex;
+use verification::{Cooldowns, CooldownsKey};
 
-use crate::commands::commands::DiscordCommand;
+use crate::commands::{
+    commands::DiscordCommand,
+    components::{split_custom_id, DiscordComponent},
+};
 
+mod actions;
+mod admin;
+mod backup;
 mod commands;
+mod config;
 mod data;
+mod database;
+mod export;
+mod groups;
+mod http;
+mod metrics;
+#[cfg(test)]
+mod mock_discord;
+mod policy;
+mod rules;
+mod shutdown;
+mod status;
+mod temp_roles;
+mod verification;
 
 struct Handler {
-    app_data: Mutex<AppData>,
+    app_data: Arc<Mutex<AppData>>,
+    status: Arc<BotStatus>,
+    shutdown: Arc<Shutdown>,
+    role_history: RoleHistory,
 }
 
+/// Reply sent to interactions that arrive while the bot is shutting down
+const SHUTTING_DOWN_MESSAGE: &str = "The bot is restarting, try again in a moment";
+
 const COMMANDS: phf::Map<&'static str, &dyn DiscordCommand> = phf_map! {
     "sweep" => &commands::sweep::SweepCommand,
     "primaryrole" => &commands::primary_role::PrimaryRoleCommands,
     "scanning" => &commands::bot_management::ScanningCommands,
+    "enforce" => &commands::enforce::EnforceCommand,
+    "rules" => &commands::rules::RulesCommands,
+    "rolegroup" => &commands::role_groups::RoleGroupCommands,
+    "temprole" => &commands::temp_role::TempRoleCommands,
+    "verification" => &commands::verification::VerificationCommands,
+    "config" => &commands::server_config::ConfigCommands,
+    "Check role gate status" => &commands::gate_status::GateStatusCommand,
 };
 
-#[async_trait]
-impl EventHandler for Handler {
-    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
-        let Interaction::Command(command) = interaction else {
-            return;
-        };
+/// Button, select menu and modal handlers, keyed by the prefix of the custom ID they own
+const COMPONENTS: phf::Map<&'static str, &dyn DiscordComponent> = phf_map! {
+    "verify" => &commands::verification::VerifyButton,
+    "review" => &commands::verification::ReviewButtons,
+};
 
+impl Handler {
+    async fn handle_command(&self, ctx: &Context, command: CommandInteraction) {
         let mut app_data = self.app_data.lock().await;
 
         let command_func = COMMANDS.get(&command.data.name);
-        let content = Into::<OptionFuture<_>>::into(command_func.map(|cmd| cmd.run(&ctx, &command, &mut app_data))).await;
+        if command_func.is_some() {
+            metrics::COMMANDS_EXECUTED.with_label_values(&[command.data.name.as_str()]).inc();
+        }
 
-        if let Some(content) = content {
-            let data = CreateInteractionResponseMessage::new().content(content).ephemeral(true);
-            let builder = CreateInteractionResponse::Message(data);
+        let content = Into::<OptionFuture<_>>::into(command_func.map(|cmd| cmd.run(ctx, &command, &mut app_data))).await;
 
-            command.create_response(&ctx, builder).await.unwrap_or_else(|error| {
+        if let Some(content) = content {
+            command.create_response(ctx, content.into_interaction_response()).await.unwrap_or_else(|error| {
                 error!("Failed to send response for command {}: {}", command.data.name, error);
+                metrics::DISCORD_ERRORS.with_label_values(&["create_response"]).inc();
             });
         } else {
             error!("No command function found for {}", command.data.name);
         }
     }
 
+    async fn handle_autocomplete(&self, ctx: &Context, command: CommandInteraction) {
+        let Some(command_func) = COMMANDS.get(&command.data.name) else {
+            error!("No command function found for {}", command.data.name);
+            return;
+        };
+
+        let Some(suggestions) = command_func.autocomplete(ctx, &command, &mut *self.app_data.lock().await).await else {
+            debug!("Command {} has no autocomplete handler", command.data.name);
+            return;
+        };
+
+        command.create_response(ctx, CreateInteractionResponse::Autocomplete(suggestions)).await.unwrap_or_else(|error| {
+            error!("Failed to send autocomplete suggestions for command {}: {}", command.data.name, error);
+        });
+    }
+
+    async fn handle_component(&self, ctx: &Context, component: ComponentInteraction) {
+        let (prefix, args) = split_custom_id(&component.data.custom_id);
+        let Some(handler) = COMPONENTS.get(prefix) else {
+            error!("No component handler found for {}", component.data.custom_id);
+            return;
+        };
+
+        let content = handler.run_component(ctx, &component, args, &mut *self.app_data.lock().await).await;
+        component.create_response(ctx, content.into_interaction_response()).await.unwrap_or_else(|error| {
+            error!("Failed to send response for component {}: {}", component.data.custom_id, error);
+            metrics::DISCORD_ERRORS.with_label_values(&["create_response"]).inc();
+        });
+    }
+
+    async fn handle_modal(&self, ctx: &Context, modal: ModalInteraction) {
+        let (prefix, args) = split_custom_id(&modal.data.custom_id);
+        let Some(handler) = COMPONENTS.get(prefix) else {
+            error!("No modal handler found for {}", modal.data.custom_id);
+            return;
+        };
+
+        let content = handler.run_modal(ctx, &modal, args, &mut *self.app_data.lock().await).await;
+        modal.create_response(ctx, content.into_interaction_response()).await.unwrap_or_else(|error| {
+            error!("Failed to send response for modal {}: {}", modal.data.custom_id, error);
+            metrics::DISCORD_ERRORS.with_label_values(&["create_response"]).inc();
+        });
+    }
+}
+
+#[async_trait]
+impl EventHandler for Handler {
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        if self.shutdown.is_shutting_down() {
+            let response = CreateInteractionResponse::Message(CreateInteractionResponseMessage::new().content(SHUTTING_DOWN_MESSAGE).ephemeral(true));
+            let result = match &interaction {
+                Interaction::Command(command) => command.create_response(&ctx, response).await,
+                Interaction::Component(component) => component.create_response(&ctx, response).await,
+                Interaction::Modal(modal) => modal.create_response(&ctx, response).await,
+                _ => Ok(()),
+            };
+
+            result.unwrap_or_else(|error| debug!("Failed to reject interaction during shutdown: {}", error));
+            return;
+        }
+
+        match interaction {
+            Interaction::Command(command) => self.handle_command(&ctx, command).await,
+            Interaction::Autocomplete(command) => self.handle_autocomplete(&ctx, command).await,
+            Interaction::Component(component) => self.handle_component(&ctx, component).await,
+            Interaction::Modal(modal) => self.handle_modal(&ctx, modal).await,
+            _ => debug!("Ignoring unsupported interaction {:?}", interaction.kind()),
+        }
+    }
+
     async fn ready(&self, ctx: Context, ready: Ready) {
         println!("Connected to server successfully");
+        self.status.set_connected(ctx.shard_id.0, true);
 
         for guild in ready.guilds {
             let guild = guild.id;
@@ -63,52 +178,577 @@ impl EventHandler for Handler {
         }
     }
 
-    async fn guild_member_update(&self, ctx: Context, _old: Option<Member>, _new: Option<Member>, event: GuildMemberUpdateEvent) {
-        debug!("Got a guild member update");
-        let app_data = self.app_data.lock().await;
+    async fn resume(&self, ctx: Context, _event: ResumedEvent) {
+        info!("Resumed the gateway connection of shard {}", ctx.shard_id);
+        self.status.set_connected(ctx.shard_id.0, true);
+    }
 
-        if !app_data.is_auto_scan_enabled(&event.guild_id) {
-            return; // Do nothing, auto scan is disabled.
-        }
+    async fn shard_stage_update(&self, _ctx: Context, event: ShardStageUpdateEvent) {
+        debug!("Shard {} moved from {:?} to {:?}", event.shard_id, event.old, event.new);
+        self.status.set_connected(event.shard_id.0, matches!(event.new, ConnectionStage::Connected));
+    }
 
-        let Some(primary_role) = app_data.get_primary_role(&event.guild_id) else {
-            error!("Failed to get primary role for {}", event.guild_id);
+    async fn guild_member_update(&self, ctx: Context, old: Option<Member>, _new: Option<Member>, event: GuildMemberUpdateEvent) {
+        debug!("Got a guild member update");
+        if self.shutdown.is_shutting_down() {
+            debug!("Ignoring the update of {}, shutting down", event.user.id);
             return;
+        }
+
+        let mut member = MemberSnapshot {
+            user_id: event.user.id,
+            roles: event.roles,
+            bot: event.user.bot,
+            joined_at: Some(event.joined_at),
+            timed_out_until: event.communication_disabled_until,
         };
 
-        if !event.roles.contains(&primary_role) {
-            // Remove all other roles
-            let Ok(member) = ctx.http.get_member(event.guild_id, event.user.id).await else {
-                error!("Could not find member for ID: {}", event.user.id);
-                return;
-            };
+        let mut app_data = self.app_data.lock().await;
+        let removed = actions::enforce_groups(&*ctx.http, &mut app_data, &self.role_history, event.guild_id, &member, old.map(|old| old.roles)).await;
+        member.roles.retain(|role| !removed.contains(role));
 
-            match member.remove_roles(&ctx, &event.roles).await {
-                Ok(_) => info!("Removed roles from {member:?}"),
-                Err(_) => error!("Failed to remove roles from {member:?}"),
-            };
-        }
+        actions::auto_scan(&*ctx.http, &mut app_data, event.guild_id, &member).await;
     }
 }
 
-const TOKEN_FILE: &str = "/run/secrets/DISCORD_TOKEN";
-const DATABASE_FILE: &str = "/app/data/config.sqlite";
+/// Build the Discord client, sharing the bot's state with its event handler and background tasks
+///
+/// @param builder Client builder holding the token and intents
+/// @param config Resolved bot configuration
+/// @param app_data Database of server configuration
+/// @param status Live state reported by the health endpoints
+/// @param shutdown Coordinator that stops the bot's background work
+async fn create_client(
+    builder: ClientBuilder,
+    config: Arc<Config>,
+    app_data: Arc<Mutex<AppData>>,
+    status: Arc<BotStatus>,
+    shutdown: Arc<Shutdown>,
+) -> serenity::Result<Client> {
+    builder
+        .type_map_insert::<AppDataKey>(app_data.clone())
+        .type_map_insert::<ConfigKey>(config)
+        .type_map_insert::<BotStatusKey>(status.clone())
+        .type_map_insert::<ShutdownKey>(shutdown.clone())
+        .type_map_insert::<CooldownsKey>(Arc::new(Cooldowns::default()))
+        .event_handler(Handler { app_data, status, shutdown, role_history: RoleHistory::default() })
+        .await
+}
 
 #[tokio::main]
 async fn main() {
-    env_logger::init();
-    let token = fs::read_to_string(TOKEN_FILE).expect("Expected token file to exist");
+    let cli = Cli::parse_with_sources(std::env::args_os());
+    let config = Config::load(&cli).unwrap_or_else(|errors| {
+        for error in errors {
+            eprintln!("Invalid configuration: {error}");
+        }
+        process::exit(1);
+    });
 
-    let intents = GatewayIntents::GUILD_MESSAGES | GatewayIntents::GUILD_MEMBERS;
+    env_logger::Builder::new().parse_filters(&config.log_level).init();
 
-    let mut client = Client::builder(&token, intents)
-        .event_handler(Handler {
-            app_data: Mutex::new(AppData::new(DATABASE_FILE)),
-        })
+    if let Some(command) = cli.command {
+        if let Err(error) = admin::run(command, &config) {
+            eprintln!("{error}");
+            process::exit(1);
+        }
+        return;
+    }
+
+    let token = config.read_token().unwrap_or_else(|error| {
+        eprintln!("Invalid configuration: {error}");
+        process::exit(1);
+    });
+
+    if let Some(source) = &cli.restore_backup {
+        if config.database_url.is_some() {
+            eprintln!("Backups can only be restored to the SQLite database");
+            process::exit(1);
+        }
+
+        match backup::restore(&config.database, source, &config.backup) {
+            Ok(path) => info!("Restored the database from {}", path.display()),
+            Err(message) => {
+                eprintln!("Failed to restore backup: {message}");
+                process::exit(1);
+            }
+        }
+    }
+
+    let mut app_data = AppData::open_configured(&config).and_then(|mut app_data| app_data.migrate().map(|_| app_data)).unwrap_or_else(|error| {
+        eprintln!("Could not open the database: {error}");
+        process::exit(1);
+    });
+    app_data.set_default_auto_scan(config.guild_defaults.auto_scan);
+    let app_data = Arc::new(Mutex::new(app_data));
+
+    let status = Arc::new(BotStatus::default());
+    let shutdown = Arc::new(Shutdown::default());
+    metrics::init(COMMANDS.keys().copied());
+
+    tokio::spawn(backup::run_periodic(config.database.clone(), config.backup.clone()));
+    if let Some(listen) = config.http_listen {
+        tokio::spawn(http::serve(listen, status.clone(), app_data.clone(), shutdown.clone()));
+    }
+
+    let shutdown_timeout = config.shutdown_timeout;
+    let sharding = config.sharding.clone();
+    let intents = config.intents;
+    let mut client = create_client(Client::builder(&token, intents), Arc::new(config), app_data.clone(), status, shutdown.clone())
         .await
         .expect("Error creating client");
+    shutdown.spawn(temp_roles::run_periodic(client.http.clone(), app_data.clone(), shutdown.clone()));
+
+    let shard_manager = client.shard_manager.clone();
+    tokio::spawn(async move {
+        shutdown::wait_for_signal().await;
+        info!("Shutting down, no longer accepting commands");
 
-    if let Err(why) = client.start().await {
+        shutdown.shutdown(shutdown_timeout).await;
+        // Every commit is already in the database file, so waiting out the write in progress is enough
+        drop(app_data.lock().await);
+
+        shard_manager.shutdown_all().await;
+    });
+
+    let result = match sharding {
+        Sharding::Single => client.start().await,
+        Sharding::Auto => client.start_autosharded().await,
+        Sharding::Range { first, last, total } => {
+            info!("Running shards {} to {} of {}", first, last, total);
+            // Serenity treats the end of this range as inclusive
+            client.start_shard_range(first..last, total).await
+        }
+    };
+
+    if let Err(why) = result {
         println!("Client error: {why:?}");
     }
+
+    info!("Shut down cleanly");
+}
+
+#[cfg(test)]
+mod test {
+    use clap::Parser;
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::mock_discord::{member_json, message_json, FakeGuild, FakeMember, MockDiscord, RecordedRequest, APPLICATION_ID};
+    use crate::rules::Rule;
+
+    const GUILD: u64 = 1;
+    const PRIMARY: u64 = 10;
+    const OTHER: u64 = 11;
+    const EXEMPT: u64 = 12;
+    const ADMIN: u64 = 50;
+
+    struct TestBot {
+        discord: MockDiscord,
+        app_data: Arc<Mutex<AppData>>,
+    }
+
+    /// Connect the bot to a mock of Discord serving the given server, and wait until it has registered its commands
+    ///
+    /// @param members Members of the server
+    /// @param setup Prepares the database before the bot connects
+    async fn start_bot(members: Vec<FakeMember>, setup: impl FnOnce(&mut AppData)) -> TestBot {
+        let discord = MockDiscord::start(FakeGuild { id: GUILD, roles: vec![PRIMARY, OTHER, EXEMPT], members }).await;
+
+        let config = Config::load(&Cli::parse_from(["discord_bot", "--token", "mock-token", "--sweep-member-delay-ms", "0", "--sweep-batch-delay-ms", "0"])).unwrap();
+        let mut app_data = AppData::new(":memory:");
+        app_data.new_server(&GuildId::new(GUILD)).unwrap();
+        setup(&mut app_data);
+        let app_data = Arc::new(Mutex::new(app_data));
+
+        let builder = ClientBuilder::new_with_http(discord.http(), GatewayIntents::GUILD_MEMBERS);
+        let mut client = create_client(builder, Arc::new(config), app_data.clone(), Arc::new(BotStatus::default()), Arc::new(Shutdown::default())).await.unwrap();
+        tokio::spawn(async move { client.start().await });
+
+        discord.wait_for_request(|request| request.method == "PUT" && request.path.ends_with("/commands")).await;
+
+        TestBot { discord, app_data }
+    }
+
+    /// Build a slash command interaction sent by a server admin
+    ///
+    /// @param name Name of the command
+    /// @param options Options of the command, as Discord sends them
+    fn command(name: &str, options: Value) -> Value {
+        let mut member = member_json(GUILD, &FakeMember::new(ADMIN, &[]));
+        member["permissions"] = json!("8");
+
+        json!({
+            "id": "9000",
+            "application_id": APPLICATION_ID.to_string(),
+            "type": 2,
+            "data": { "id": "9100", "name": name, "type": 1, "options": options, "guild_id": GUILD.to_string() },
+            "guild_id": GUILD.to_string(),
+            "channel_id": "5000",
+            "member": member,
+            "token": "interaction-token",
+            "version": 1,
+            "locale": "en-US",
+            "guild_locale": "en-US",
+            "app_permissions": "8",
+            "entitlements": [],
+        })
+    }
+
+    fn is_role_removal(request: &RecordedRequest) -> bool {
+        request.method == "DELETE" && request.path.contains("/roles/")
+    }
+
+    /// Build an interaction sent by a member without any permissions
+    ///
+    /// @param id ID of the interaction, so its reply can be told apart
+    /// @param kind Interaction type, 3 for a button click or 5 for a form submission
+    /// @param member Member sending the interaction
+    /// @param data Data of the interaction, as Discord sends it
+    fn member_interaction(id: &str, kind: u8, member: &FakeMember, data: Value) -> Value {
+        let mut interaction = command("", json!([]));
+        interaction["id"] = json!(id);
+        interaction["type"] = json!(kind);
+        interaction["data"] = data;
+        interaction["member"] = member_json(GUILD, member);
+        interaction["member"]["permissions"] = json!("0");
+        interaction["message"] = message_json("6000", &json!("Click the button below"));
+
+        interaction
+    }
+
+    /// Get the reply the bot sent to an interaction
+    async fn reply(discord: &MockDiscord) -> String {
+        reply_to(discord, "9000").await["data"]["content"].as_str().unwrap_or_default().to_string()
+    }
+
+    /// Get the full reply the bot sent to the interaction with the given ID
+    async fn reply_to(discord: &MockDiscord, interaction_id: &str) -> Value {
+        let prefix = format!("/interactions/{}/", interaction_id);
+        let request = discord.wait_for_request(|request| request.method == "POST" && request.path.starts_with(&prefix)).await;
+
+        request.body
+    }
+
+    #[tokio::test]
+    async fn test_primary_role_set() {
+        let bot = start_bot(Vec::new(), |_| {}).await;
+
+        bot.discord.dispatch("INTERACTION_CREATE", command("primaryrole", json!([{ "name": "set", "type": 1, "options": [{ "name": "role_id", "type": 8, "value": OTHER.to_string() }] }])));
+
+        assert_eq!(format!("Updated primary role to {}", OTHER), reply(&bot.discord).await);
+        assert_eq!(Some(RoleId::new(OTHER)), bot.app_data.lock().await.get_primary_role(&GuildId::new(GUILD)));
+        assert!(bot.discord.requests().iter().any(|request| request.method == "GET" && request.path == format!("/guilds/{}/roles", GUILD)));
+    }
+
+    #[tokio::test]
+    async fn test_exempt_role_autocomplete() {
+        let bot = start_bot(Vec::new(), |app_data| {
+            app_data.add_exempt_role(&GuildId::new(GUILD), &RoleId::new(EXEMPT)).unwrap();
+        })
+        .await;
+
+        let mut typing = command("primaryrole", json!([{ "name": "exempt", "type": 2, "options": [{ "name": "remove", "type": 1, "options": [{ "name": "role", "type": 3, "value": "", "focused": true }] }] }]));
+        typing["type"] = json!(4);
+        bot.discord.dispatch("INTERACTION_CREATE", typing);
+
+        // The server is not cached yet, so the role is suggested by ID instead of asking the API on every keystroke
+        assert_eq!(json!([{ "name": EXEMPT.to_string(), "value": EXEMPT.to_string() }]), reply_to(&bot.discord, "9000").await["data"]["choices"]);
+        assert!(!bot.discord.requests().iter().any(|request| request.path == format!("/guilds/{}/roles", GUILD)));
+    }
+
+    #[tokio::test]
+    async fn test_sweep_run() {
+        let members = vec![
+            FakeMember::new(100, &[PRIMARY, OTHER]),
+            FakeMember::new(101, &[OTHER, EXEMPT]),
+            FakeMember { bot: true, ..FakeMember::new(102, &[OTHER]) },
+            FakeMember::new(103, &[]),
+        ];
+        let bot = start_bot(members, |app_data| {
+            app_data.update_server_primary_role(&GuildId::new(GUILD), &RoleId::new(PRIMARY)).unwrap();
+            app_data.add_exempt_role(&GuildId::new(GUILD), &RoleId::new(EXEMPT)).unwrap();
+        })
+        .await;
+
+        bot.discord.dispatch("INTERACTION_CREATE", command("sweep", json!([{ "name": "run", "type": 1, "options": [] }])));
+
+        assert_eq!("Sweep 1 is going through 4 members", reply(&bot.discord).await);
+        let report = bot.discord.wait_for_request(|request| request.method == "POST" && request.path.starts_with("/channels/")).await;
+        assert!(report.body["content"].as_str().unwrap().starts_with("Sweep 1 completed sweeping through 4 members, removed roles from 1 members"));
+
+        let removals = bot.discord.requests().into_iter().filter(is_role_removal).map(|request| request.path).collect::<Vec<_>>();
+        assert_eq!(vec![format!("/guilds/{}/members/101/roles/{}", GUILD, OTHER)], removals);
+        assert_eq!(vec![EXEMPT], bot.discord.member_roles(101));
+        assert_eq!(vec![PRIMARY, OTHER], bot.discord.member_roles(100));
+        assert_eq!(vec![(UserId::new(101), vec![RoleId::new(OTHER)])], bot.app_data.lock().await.get_sweep_removals(1));
+
+        let mut undo = command("sweep", json!([{ "name": "undo", "type": 1, "options": [] }]));
+        undo["id"] = json!("9001");
+        bot.discord.dispatch("INTERACTION_CREATE", undo);
+        assert_eq!("Restoring roles removed by sweep 1 to 1 members", reply_to(&bot.discord, "9001").await["data"]["content"]);
+        bot.discord.wait_for_request(|request| request.body["content"].as_str().is_some_and(|content| content.starts_with("Completed undoing sweep 1"))).await;
+        assert_eq!(vec![EXEMPT, OTHER], bot.discord.member_roles(101));
+        assert!(!bot.app_data.lock().await.is_sweep_undoable(&GuildId::new(GUILD), 1));
+    }
+
+    #[tokio::test]
+    async fn test_sweep_partial_removal() {
+        // Role 999 is not in the server, so Discord refuses to remove it while removing the others
+        let bot = start_bot(vec![FakeMember::new(101, &[OTHER, 999])], |app_data| {
+            app_data.update_server_primary_role(&GuildId::new(GUILD), &RoleId::new(PRIMARY)).unwrap();
+        })
+        .await;
+
+        bot.discord.dispatch("INTERACTION_CREATE", command("sweep", json!([{ "name": "run", "type": 1, "options": [] }])));
+        let report = bot.discord.wait_for_request(|request| request.method == "POST" && request.path.starts_with("/channels/")).await;
+        assert!(report.body["content"].as_str().unwrap().starts_with("Sweep 1 completed sweeping through 1 members, removed roles from 1 members"));
+
+        assert_eq!(vec![999], bot.discord.member_roles(101));
+        assert_eq!(vec![(UserId::new(101), vec![RoleId::new(OTHER)])], bot.app_data.lock().await.get_sweep_removals(1));
+    }
+
+    #[tokio::test]
+    async fn test_sweep_kick() {
+        let members = vec![FakeMember::new(100, &[PRIMARY, OTHER]), FakeMember::new(101, &[]), FakeMember { bot: true, ..FakeMember::new(102, &[]) }];
+        let bot = start_bot(members, |app_data| {
+            app_data.update_server_primary_role(&GuildId::new(GUILD), &RoleId::new(PRIMARY)).unwrap();
+            app_data.add_rule(&GuildId::new(GUILD), &Rule::Requires { role: Some(RoleId::new(OTHER)), required: RoleId::new(EXEMPT) }).unwrap();
+        })
+        .await;
+
+        let kick = json!([{ "name": "action", "type": 2, "options": [{ "name": "kick", "type": 1, "options": [{ "name": "after_days", "type": 4, "value": 7 }] }] }]);
+        bot.discord.dispatch("INTERACTION_CREATE", command("primaryrole", kick));
+        assert!(reply(&bot.discord).await.ends_with("Kick members without the primary role 7 days after they join"));
+
+        bot.discord.dispatch("INTERACTION_CREATE", command("sweep", json!([{ "name": "run", "type": 1, "options": [] }])));
+        let report = bot.discord.wait_for_request(|request| request.method == "POST" && request.path.starts_with("/channels/")).await;
+        // Member 100 only breaks a role rule, so loses the role instead of being counted as kicked
+        assert!(report.body["content"].as_str().unwrap().starts_with("Sweep 1 completed sweeping through 3 members, removed roles from 1 members and kicked 1 members"));
+
+        let kicks = bot.discord.requests().into_iter().filter(|request| request.method == "DELETE" && !is_role_removal(request)).map(|request| request.path).collect::<Vec<_>>();
+        assert_eq!(vec![format!("/guilds/{}/members/101", GUILD)], kicks);
+        assert_eq!(vec![(UserId::new(100), vec![RoleId::new(OTHER)])], bot.app_data.lock().await.get_sweep_removals(1));
+    }
+
+    #[tokio::test]
+    async fn test_rules() {
+        let member = FakeMember::new(101, &[PRIMARY, OTHER]);
+        let bot = start_bot(vec![member.clone()], |app_data| {
+            app_data.update_server_primary_role(&GuildId::new(GUILD), &RoleId::new(PRIMARY)).unwrap();
+        })
+        .await;
+
+        let requires = json!([{ "name": "requires", "type": 1, "options": [
+            { "name": "required", "type": 8, "value": EXEMPT.to_string() },
+            { "name": "role", "type": 8, "value": OTHER.to_string() },
+        ] }]);
+        bot.discord.dispatch("INTERACTION_CREATE", command("rules", requires));
+        assert_eq!(format!("Added rule 1: <@&{}> requires <@&{}>", OTHER, EXEMPT), reply(&bot.discord).await);
+
+        bot.discord.dispatch("GUILD_MEMBER_UPDATE", member_json(GUILD, &member));
+
+        let removal = bot.discord.wait_for_request(is_role_removal).await;
+        assert_eq!(format!("/guilds/{}/members/101/roles/{}", GUILD, OTHER), removal.path);
+        assert_eq!(vec![PRIMARY], bot.discord.member_roles(101));
+    }
+
+    #[tokio::test]
+    async fn test_role_groups() {
+        let unknown = FakeMember::new(102, &[PRIMARY, OTHER, EXEMPT]);
+        let bot = start_bot(vec![FakeMember::new(101, &[PRIMARY, OTHER, EXEMPT]), unknown.clone()], |app_data| {
+            app_data.update_server_primary_role(&GuildId::new(GUILD), &RoleId::new(PRIMARY)).unwrap();
+            app_data.add_group_role(&GuildId::new(GUILD), "colour", &RoleId::new(OTHER)).unwrap();
+            app_data.add_group_role(&GuildId::new(GUILD), "colour", &RoleId::new(EXEMPT)).unwrap();
+        })
+        .await;
+
+        // The bot has not seen either member before, so it cannot tell which colour is new and leaves both alone
+        bot.discord.dispatch("GUILD_MEMBER_UPDATE", member_json(GUILD, &unknown));
+        bot.discord.dispatch("GUILD_MEMBER_UPDATE", member_json(GUILD, &FakeMember::new(101, &[PRIMARY, OTHER])));
+
+        // Once it has seen a member, the colour they pick next replaces the old one
+        bot.discord.dispatch("GUILD_MEMBER_UPDATE", member_json(GUILD, &FakeMember::new(101, &[PRIMARY, OTHER, EXEMPT])));
+
+        let removal = bot.discord.wait_for_request(is_role_removal).await;
+        assert_eq!(format!("/guilds/{}/members/101/roles/{}", GUILD, OTHER), removal.path);
+        assert_eq!(vec![PRIMARY, EXEMPT], bot.discord.member_roles(101));
+        assert_eq!(vec![PRIMARY, OTHER, EXEMPT], bot.discord.member_roles(102));
+    }
+
+    #[tokio::test]
+    async fn test_temp_role() {
+        let bot = start_bot(vec![FakeMember::new(101, &[PRIMARY, OTHER])], |app_data| {
+            app_data.update_server_primary_role(&GuildId::new(GUILD), &RoleId::new(PRIMARY)).unwrap();
+            app_data.add_exempt_role(&GuildId::new(GUILD), &RoleId::new(EXEMPT)).unwrap();
+        })
+        .await;
+
+        let give = |id: &str, role: u64, duration: &str| {
+            let mut interaction = command("temprole", json!([{ "name": "give", "type": 1, "options": [
+                { "name": "user", "type": 6, "value": "101" },
+                { "name": "role", "type": 8, "value": role.to_string() },
+                { "name": "duration", "type": 3, "value": duration },
+            ] }]));
+            interaction["id"] = json!(id);
+            interaction
+        };
+        bot.discord.dispatch("INTERACTION_CREATE", give("9000", EXEMPT, "1h"));
+        assert!(reply(&bot.discord).await.starts_with(&format!("Gave <@&{}> to <@101> until", EXEMPT)));
+        assert_eq!(vec![PRIMARY, OTHER, EXEMPT], bot.discord.member_roles(101));
+
+        // Giving a temporary role again extends it, but roles held for good are left alone
+        bot.discord.dispatch("INTERACTION_CREATE", give("9001", EXEMPT, "2h"));
+        assert!(reply_to(&bot.discord, "9001").await["data"]["content"].as_str().unwrap().starts_with("Gave"));
+        bot.discord.dispatch("INTERACTION_CREATE", give("9002", OTHER, "2h"));
+        assert_eq!(format!("<@101> already holds <@&{}> permanently", OTHER), reply_to(&bot.discord, "9002").await["data"]["content"]);
+
+        let temp_roles = bot.app_data.lock().await.get_temp_roles(&GuildId::new(GUILD));
+        assert_eq!(vec![RoleId::new(EXEMPT)], temp_roles.iter().map(|temp_role| temp_role.role_id).collect::<Vec<_>>());
+        assert!((temp_roles[0].expires_at - Timestamp::now().unix_timestamp() - 2 * 60 * 60).abs() < 60);
+
+        // Losing the primary role takes the temporary role with it, even though it is exempt
+        bot.discord.dispatch("GUILD_MEMBER_UPDATE", member_json(GUILD, &FakeMember::new(101, &[EXEMPT])));
+
+        let removal = bot.discord.wait_for_request(is_role_removal).await;
+        assert_eq!(format!("/guilds/{}/members/101/roles/{}", GUILD, EXEMPT), removal.path);
+    }
+
+    #[tokio::test]
+    async fn test_verification() {
+        let bot = start_bot(vec![FakeMember::new(101, &[OTHER])], |app_data| {
+            app_data.update_server_primary_role(&GuildId::new(GUILD), &RoleId::new(PRIMARY)).unwrap();
+            app_data.disable_auto_scan(&GuildId::new(GUILD)).unwrap();
+        })
+        .await;
+
+        let panel = json!([{ "name": "panel", "type": 1, "options": [
+            { "name": "channel", "type": 7, "value": "6000" },
+            { "name": "log_channel", "type": 7, "value": "6001" },
+            { "name": "rules", "type": 3, "value": "Be nice" },
+        ] }]);
+        bot.discord.dispatch("INTERACTION_CREATE", command("verification", panel));
+        assert_eq!(format!("Posted the verification panel in <#6000>, members clicking it get <@&{}>", PRIMARY), reply(&bot.discord).await);
+
+        let posted = bot.discord.wait_for_request(|request| request.path == "/channels/6000/messages").await;
+        assert_eq!("verify", posted.body["components"][0]["components"][0]["custom_id"]);
+
+        // Clicking shows the rules, and clicking again straight away is refused
+        let member = FakeMember::new(101, &[OTHER]);
+        let click = json!({ "custom_id": "verify", "component_type": 2 });
+        bot.discord.dispatch("INTERACTION_CREATE", member_interaction("9001", 3, &member, click.clone()));
+        let modal = reply_to(&bot.discord, "9001").await;
+        assert_eq!(9, modal["type"]);
+        assert_eq!("verify:form", modal["data"]["custom_id"]);
+
+        bot.discord.dispatch("INTERACTION_CREATE", member_interaction("9002", 3, &member, click));
+        assert!(reply_to(&bot.discord, "9002").await["data"]["content"].as_str().unwrap().starts_with("Please wait"));
+
+        let submit = |agreement: &str| {
+            json!({ "custom_id": "verify:form", "components": [
+                { "type": 1, "components": [{ "type": 4, "custom_id": "rules", "value": "Be nice" }] },
+                { "type": 1, "components": [{ "type": 4, "custom_id": "agree", "value": agreement }] },
+            ] })
+        };
+        bot.discord.dispatch("INTERACTION_CREATE", member_interaction("9003", 5, &member, submit("no")));
+        assert_eq!("You need to type \"I agree\" to accept the rules, click Verify to try again", reply_to(&bot.discord, "9003").await["data"]["content"]);
+
+        bot.discord.dispatch("INTERACTION_CREATE", member_interaction("9004", 5, &member, submit(" i AGREE ")));
+        assert_eq!(format!("You are verified and now have <@&{}>", PRIMARY), reply_to(&bot.discord, "9004").await["data"]["content"]);
+        assert_eq!(vec![OTHER, PRIMARY], bot.discord.member_roles(101));
+
+        let log = bot.discord.wait_for_request(|request| request.path == "/channels/6001/messages").await;
+        assert_eq!(format!("<@101> verified and got <@&{}>", PRIMARY), log.body["content"]);
+    }
+
+    #[tokio::test]
+    async fn test_verification_review() {
+        let bot = start_bot(vec![FakeMember::new(101, &[OTHER]), FakeMember::new(102, &[])], |app_data| {
+            app_data.update_server_primary_role(&GuildId::new(GUILD), &RoleId::new(PRIMARY)).unwrap();
+            app_data.disable_auto_scan(&GuildId::new(GUILD)).unwrap();
+        })
+        .await;
+
+        let panel = json!([{ "name": "panel", "type": 1, "options": [
+            { "name": "channel", "type": 7, "value": "6000" },
+            { "name": "questions", "type": 3, "value": "Why join? | Who invited you?" },
+            { "name": "review_channel", "type": 7, "value": "6002" },
+        ] }]);
+        bot.discord.dispatch("INTERACTION_CREATE", command("verification", panel));
+        assert_eq!(format!("Posted the verification panel in <#6000>, members get <@&{}> once their answers are approved in <#6002>", PRIMARY), reply(&bot.discord).await);
+
+        let member = FakeMember::new(101, &[OTHER]);
+        bot.discord.dispatch("INTERACTION_CREATE", member_interaction("9001", 3, &member, json!({ "custom_id": "verify", "component_type": 2 })));
+        let modal = reply_to(&bot.discord, "9001").await;
+        assert_eq!("Who invited you?", modal["data"]["components"][1]["components"][0]["label"]);
+
+        let submit = json!({ "custom_id": "verify:form", "components": [
+            { "type": 1, "components": [{ "type": 4, "custom_id": "q0", "value": "To chat @everyone" }] },
+            { "type": 1, "components": [{ "type": 4, "custom_id": "q1", "value": "Nobody" }] },
+        ] });
+        bot.discord.dispatch("INTERACTION_CREATE", member_interaction("9002", 5, &member, submit.clone()));
+        assert_eq!("Thanks, your answers were sent to the moderators and you get access once they approve them", reply_to(&bot.discord, "9002").await["data"]["content"]);
+        bot.discord.dispatch("INTERACTION_CREATE", member_interaction("9003", 5, &member, submit));
+        assert_eq!("Your answers are waiting for a moderator to review them", reply_to(&bot.discord, "9003").await["data"]["content"]);
+
+        let review = bot.discord.wait_for_request(|request| request.path == "/channels/6002/messages").await;
+        assert!(review.body["content"].as_str().unwrap().ends_with("**Why join?**\nTo chat @everyone\n**Who invited you?**\nNobody"));
+        assert_eq!(json!([]), review.body["allowed_mentions"]["parse"]);
+        assert_eq!("review:approve:1", review.body["components"][0]["components"][0]["custom_id"]);
+        assert_eq!(vec![OTHER], bot.discord.member_roles(101));
+
+        // Only moderators may review
+        let click = json!({ "custom_id": "review:approve:1", "component_type": 2 });
+        let moderator = FakeMember::new(102, &[]);
+        bot.discord.dispatch("INTERACTION_CREATE", member_interaction("9004", 3, &moderator, click.clone()));
+        assert_eq!("You need the Manage Roles permission to review applications", reply_to(&bot.discord, "9004").await["data"]["content"]);
+
+        let mut approve = member_interaction("9005", 3, &moderator, click);
+        approve["member"]["permissions"] = json!("268435456");
+        approve["message"] = message_json("6002", &json!("Application 1"));
+        bot.discord.dispatch("INTERACTION_CREATE", approve);
+        let update = reply_to(&bot.discord, "9005").await;
+        assert_eq!(7, update["type"]);
+        assert_eq!("Application 1\n\nApproved by <@102>", update["data"]["content"]);
+        assert_eq!(json!([]), update["data"]["components"]);
+        assert_eq!(vec![OTHER, PRIMARY], bot.discord.member_roles(101));
+    }
+
+    #[tokio::test]
+    async fn test_auto_scan() {
+        let member = FakeMember::new(101, &[OTHER, EXEMPT]);
+        let bot = start_bot(vec![member.clone()], |app_data| {
+            app_data.update_server_primary_role(&GuildId::new(GUILD), &RoleId::new(PRIMARY)).unwrap();
+            app_data.add_exempt_role(&GuildId::new(GUILD), &RoleId::new(EXEMPT)).unwrap();
+        })
+        .await;
+
+        bot.discord.dispatch("GUILD_MEMBER_UPDATE", member_json(GUILD, &member));
+
+        let removal = bot.discord.wait_for_request(is_role_removal).await;
+        assert_eq!(format!("/guilds/{}/members/101/roles/{}", GUILD, OTHER), removal.path);
+        assert_eq!(vec![EXEMPT], bot.discord.member_roles(101));
+    }
+
+    #[tokio::test]
+    async fn test_scanning_toggle() {
+        let ignored = FakeMember::new(101, &[OTHER]);
+        let scanned = FakeMember::new(102, &[OTHER]);
+        let bot = start_bot(vec![ignored.clone(), scanned.clone()], |app_data| {
+            app_data.update_server_primary_role(&GuildId::new(GUILD), &RoleId::new(PRIMARY)).unwrap();
+        })
+        .await;
+
+        bot.discord.dispatch("INTERACTION_CREATE", command("scanning", json!([{ "name": "disable", "type": 1, "options": [] }])));
+        assert_eq!("Automatic Role Scanning is no longer active", reply(&bot.discord).await);
+        bot.discord.dispatch("GUILD_MEMBER_UPDATE", member_json(GUILD, &ignored));
+
+        bot.discord.dispatch("INTERACTION_CREATE", command("scanning", json!([{ "name": "enable", "type": 1, "options": [] }])));
+        bot.discord.wait_for_request(|request| request.body["data"]["content"] == "Automatic role scanning is now active").await;
+        bot.discord.dispatch("GUILD_MEMBER_UPDATE", member_json(GUILD, &scanned));
+
+        let removal = bot.discord.wait_for_request(is_role_removal).await;
+        assert_eq!(format!("/guilds/{}/members/102/roles/{}", GUILD, OTHER), removal.path);
+        assert_eq!(vec![OTHER], bot.discord.member_roles(101));
+    }
 }
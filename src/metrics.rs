@@ -0,0 +1,100 @@
+use std::sync::LazyLock;
+
+use prometheus::{
+    exponential_buckets, register_histogram, register_histogram_vec, register_int_counter, register_int_counter_vec, Encoder, Histogram, HistogramVec, IntCounter,
+    IntCounterVec, TextEncoder,
+};
+
+/// Commands run, by command name
+pub static COMMANDS_EXECUTED: LazyLock<IntCounterVec> =
+    LazyLock::new(|| register_int_counter_vec!("discord_bot_commands_executed_total", "Commands run, by command name", &["command"]).unwrap());
+
+/// Roles removed from members, by what triggered the removal
+pub static ROLES_REMOVED: LazyLock<IntCounterVec> =
+    LazyLock::new(|| register_int_counter_vec!("discord_bot_roles_removed_total", "Roles removed from members, by trigger", &["trigger"]).unwrap());
+
+/// Members quarantined, timed out or kicked, by action and trigger
+pub static MEMBERS_PUNISHED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("discord_bot_members_punished_total", "Members quarantined, timed out or kicked, by action and trigger", &["action", "trigger"]).unwrap()
+});
+
+/// Members given the primary role by the verify button or an approved application
+pub static MEMBERS_VERIFIED: LazyLock<IntCounter> =
+    LazyLock::new(|| register_int_counter!("discord_bot_members_verified_total", "Members given the primary role by the verify button or an approved application").unwrap());
+
+/// Failed Discord API calls, by the operation being attempted
+pub static DISCORD_ERRORS: LazyLock<IntCounterVec> =
+    LazyLock::new(|| register_int_counter_vec!("discord_bot_discord_http_errors_total", "Failed Discord API calls, by operation", &["operation"]).unwrap());
+
+/// Time taken by each sweep
+pub static SWEEP_DURATION: LazyLock<Histogram> = LazyLock::new(|| {
+    register_histogram!(
+        "discord_bot_sweep_duration_seconds",
+        "Time taken to sweep a server",
+        exponential_buckets(1.0, 4.0, 8).unwrap()
+    )
+    .unwrap()
+});
+
+/// Members visited by sweeps
+pub static SWEEP_MEMBERS_PROCESSED: LazyLock<IntCounter> =
+    LazyLock::new(|| register_int_counter!("discord_bot_sweep_members_processed_total", "Members visited by sweeps").unwrap());
+
+/// Member updates seen by the auto scanner, by whether they were handled or skipped
+pub static AUTO_SCAN_EVENTS: LazyLock<IntCounterVec> =
+    LazyLock::new(|| register_int_counter_vec!("discord_bot_auto_scan_events_total", "Member updates seen by the auto scanner, by outcome", &["outcome"]).unwrap());
+
+/// Time taken by database queries, by query
+pub static DB_QUERY_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "discord_bot_db_query_duration_seconds",
+        "Time taken by database queries",
+        &["query"],
+        exponential_buckets(0.0001, 4.0, 8).unwrap()
+    )
+    .unwrap()
+});
+
+/// Create every metric up front so they are exported before their first use
+///
+/// @param commands Names of the registered commands, so each starts at zero
+pub fn init<'a>(commands: impl Iterator<Item = &'a str>) {
+    for command in commands {
+        COMMANDS_EXECUTED.with_label_values(&[command]);
+    }
+
+    LazyLock::force(&ROLES_REMOVED);
+    LazyLock::force(&MEMBERS_PUNISHED);
+    LazyLock::force(&MEMBERS_VERIFIED);
+    LazyLock::force(&DISCORD_ERRORS);
+    LazyLock::force(&SWEEP_DURATION);
+    LazyLock::force(&SWEEP_MEMBERS_PROCESSED);
+    LazyLock::force(&AUTO_SCAN_EVENTS);
+    LazyLock::force(&DB_QUERY_DURATION);
+}
+
+/// Render every registered metric in the Prometheus text format
+pub fn render() -> String {
+    let mut buffer = Vec::new();
+
+    if let Err(error) = TextEncoder::new().encode(&prometheus::gather(), &mut buffer) {
+        log::error!("Failed to encode metrics: {}", error);
+    }
+
+    String::from_utf8(buffer).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        init(["sweep"].into_iter());
+        ROLES_REMOVED.with_label_values(&["sweep"]).inc_by(2);
+
+        let rendered = render();
+        assert!(rendered.contains(r#"discord_bot_commands_executed_total{command="sweep"} 0"#));
+        assert!(rendered.contains(r#"discord_bot_roles_removed_total{trigger="sweep"} 2"#));
+    }
+}
@@ -0,0 +1,372 @@
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    body::Bytes,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    http::{Method, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde_json::{json, Value};
+use serenity::http::{Http, HttpBuilder};
+use tokio::{net::TcpListener, sync::mpsc};
+
+/// Application ID the fake gateway hands to the bot
+pub const APPLICATION_ID: u64 = 4242;
+/// User ID of the bot itself
+pub const BOT_USER_ID: u64 = 4343;
+
+/// Server served by the mock Discord API
+#[derive(Clone, Debug)]
+pub struct FakeGuild {
+    pub id: u64,
+    pub roles: Vec<u64>,
+    pub members: Vec<FakeMember>,
+}
+
+#[derive(Clone, Debug)]
+pub struct FakeMember {
+    pub id: u64,
+    pub roles: Vec<u64>,
+    pub bot: bool,
+}
+
+impl FakeMember {
+    pub fn new(id: u64, roles: &[u64]) -> Self {
+        Self { id, roles: roles.to_vec(), bot: false }
+    }
+}
+
+/// Request the bot made to the REST API
+#[derive(Clone, Debug)]
+pub struct RecordedRequest {
+    pub method: Method,
+    /// Path below /api/v10, such as /guilds/1/members
+    pub path: String,
+    pub body: Value,
+}
+
+struct MockState {
+    guild: FakeGuild,
+    requests: Vec<RecordedRequest>,
+    gateway_url: String,
+    /// Events waiting to be sent to the bot, taken by the first gateway connection
+    events: Option<mpsc::UnboundedReceiver<(String, Value)>>,
+}
+
+type SharedState = Arc<Mutex<MockState>>;
+
+/// Stand-in for Discord's REST API and gateway, serving a single fake server on a local port
+pub struct MockDiscord {
+    url: String,
+    state: SharedState,
+    events: mpsc::UnboundedSender<(String, Value)>,
+}
+
+impl MockDiscord {
+    /// Start serving the fake server
+    pub async fn start(guild: FakeGuild) -> Self {
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.expect("Expected a free local port");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (events, receiver) = mpsc::unbounded_channel();
+
+        let state = Arc::new(Mutex::new(MockState {
+            guild,
+            requests: Vec::new(),
+            gateway_url: url.replace("http://", "ws://") + "/gateway",
+            events: Some(receiver),
+        }));
+
+        let router = Router::new().route("/gateway", get(gateway)).fallback(rest).with_state(state.clone());
+        tokio::spawn(async move { axum::serve(listener, router).await.ok() });
+
+        Self { url, state, events }
+    }
+
+    /// Build an HTTP client that sends every request to this mock
+    pub fn http(&self) -> Http {
+        HttpBuilder::new("mock-token").proxy(&self.url).ratelimiter_disabled(true).build()
+    }
+
+    /// Send a gateway event to the bot, as a dispatch with the given event name
+    ///
+    /// @param event Name of the event, such as GUILD_MEMBER_UPDATE
+    /// @param data Payload of the event
+    pub fn dispatch(&self, event: &str, data: Value) {
+        self.events.send((event.to_string(), data)).expect("Expected the gateway to be running");
+    }
+
+    /// Get every REST request the bot has made so far
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Get the current roles of a member of the fake server
+    pub fn member_roles(&self, user_id: u64) -> Vec<u64> {
+        let state = self.state.lock().unwrap();
+
+        state.guild.members.iter().find(|member| member.id == user_id).map(|member| member.roles.clone()).unwrap_or_default()
+    }
+
+    /// Wait until the bot makes a matching request, failing the test if it takes too long
+    pub async fn wait_for_request(&self, mut predicate: impl FnMut(&RecordedRequest) -> bool) -> RecordedRequest {
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+
+        loop {
+            if let Some(request) = self.requests().into_iter().find(&mut predicate) {
+                return request;
+            }
+            if tokio::time::Instant::now() > deadline {
+                panic!("Timed out waiting for a request, got {:#?}", self.requests());
+            }
+
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+}
+
+pub fn user_json(id: u64, bot: bool) -> Value {
+    json!({
+        "id": id.to_string(),
+        "username": format!("user{}", id),
+        "discriminator": "0",
+        "global_name": null,
+        "avatar": null,
+        "bot": bot,
+    })
+}
+
+pub fn message_json(channel_id: &str, content: &Value) -> Value {
+    json!({
+        "id": "7001",
+        "channel_id": channel_id,
+        "author": user_json(BOT_USER_ID, true),
+        "content": content,
+        "timestamp": "2020-01-01T00:00:00.000000+00:00",
+        "edited_timestamp": null,
+        "tts": false,
+        "mention_everyone": false,
+        "mentions": [],
+        "mention_roles": [],
+        "attachments": [],
+        "embeds": [],
+        "pinned": false,
+        "type": 0,
+    })
+}
+
+pub fn member_json(guild_id: u64, member: &FakeMember) -> Value {
+    json!({
+        "guild_id": guild_id.to_string(),
+        "user": user_json(member.id, member.bot),
+        "nick": null,
+        "avatar": null,
+        "roles": member.roles.iter().map(|role| role.to_string()).collect::<Vec<_>>(),
+        "joined_at": "2020-01-01T00:00:00.000000+00:00",
+        "premium_since": null,
+        "deaf": false,
+        "mute": false,
+        "flags": 0,
+        "pending": false,
+        "communication_disabled_until": null,
+    })
+}
+
+fn role_json(guild_id: u64, role_id: u64) -> Value {
+    json!({
+        "id": role_id.to_string(),
+        "guild_id": guild_id.to_string(),
+        "name": format!("role{}", role_id),
+        "color": 0,
+        "hoist": false,
+        "icon": null,
+        "unicode_emoji": null,
+        "position": 1,
+        "permissions": "0",
+        "managed": false,
+        "mentionable": false,
+        "flags": 0,
+    })
+}
+
+fn guild_json(guild: &FakeGuild) -> Value {
+    json!({
+        "id": guild.id.to_string(),
+        "name": "Fake server",
+        "icon": null,
+        "splash": null,
+        "discovery_splash": null,
+        "owner_id": BOT_USER_ID.to_string(),
+        "afk_channel_id": null,
+        "afk_timeout": 300,
+        "verification_level": 0,
+        "default_message_notifications": 0,
+        "explicit_content_filter": 0,
+        "roles": guild.roles.iter().map(|role| role_json(guild.id, *role)).collect::<Vec<_>>(),
+        "emojis": [],
+        "features": [],
+        "mfa_level": 0,
+        "application_id": null,
+        "system_channel_id": null,
+        "system_channel_flags": 0,
+        "rules_channel_id": null,
+        "max_members": 1000,
+        "vanity_url_code": null,
+        "description": null,
+        "banner": null,
+        "premium_tier": 0,
+        "preferred_locale": "en-US",
+        "public_updates_channel_id": null,
+        "nsfw_level": 0,
+        "stickers": [],
+        "premium_progress_bar_enabled": false,
+        "approximate_member_count": guild.members.len(),
+        "approximate_presence_count": 0,
+    })
+}
+
+fn not_found() -> Response {
+    (StatusCode::NOT_FOUND, Json(json!({ "message": "404: Not Found", "code": 0 }))).into_response()
+}
+
+/// Answer a REST request from the state of the fake server
+async fn rest(State(state): State<SharedState>, method: Method, uri: Uri, body: Bytes) -> Response {
+    let path = uri.path().trim_start_matches("/api/v10").to_string();
+    let body = serde_json::from_slice(&body).unwrap_or(Value::Null);
+    let query = uri.query().unwrap_or_default().to_string();
+
+    let mut state = state.lock().unwrap();
+    state.requests.push(RecordedRequest { method: method.clone(), path: path.clone(), body: body.clone() });
+
+    let segments = path.trim_matches('/').split('/').collect::<Vec<_>>();
+    let guild_id = state.guild.id;
+    let is_guild = |id: &str| id == guild_id.to_string();
+
+    match (method, segments.as_slice()) {
+        (Method::GET, ["gateway"]) => Json(json!({ "url": state.gateway_url })).into_response(),
+        (Method::GET, ["guilds", id]) if is_guild(id) => Json(guild_json(&state.guild)).into_response(),
+        (Method::GET, ["guilds", id, "roles"]) if is_guild(id) => {
+            Json(state.guild.roles.iter().map(|role| role_json(guild_id, *role)).collect::<Vec<_>>()).into_response()
+        }
+        (Method::GET, ["guilds", id, "members"]) if is_guild(id) => {
+            let param = |name: &str| query.split('&').find_map(|pair| pair.strip_prefix(&format!("{}=", name))).and_then(|value| value.parse::<u64>().ok());
+            let (limit, after) = (param("limit").unwrap_or(1) as usize, param("after").unwrap_or(0));
+
+            let mut members = state.guild.members.iter().filter(|member| member.id > after).collect::<Vec<_>>();
+            members.sort_by_key(|member| member.id);
+            Json(members.into_iter().take(limit).map(|member| member_json(guild_id, member)).collect::<Vec<_>>()).into_response()
+        }
+        (Method::GET, ["guilds", id, "members", user]) if is_guild(id) => match state.guild.members.iter().find(|member| member.id.to_string() == *user) {
+            Some(member) => Json(member_json(guild_id, member)).into_response(),
+            None => not_found(),
+        },
+        (Method::DELETE, ["guilds", id, "members", user]) if is_guild(id) => {
+            let count = state.guild.members.len();
+            state.guild.members.retain(|member| member.id.to_string() != *user);
+            if state.guild.members.len() == count {
+                return not_found();
+            }
+            StatusCode::NO_CONTENT.into_response()
+        }
+        (method @ (Method::PUT | Method::DELETE), ["guilds", id, "members", user, "roles", role]) if is_guild(id) => {
+            let role = role.parse::<u64>().unwrap();
+            if !state.guild.roles.contains(&role) {
+                return not_found(); // Unknown role
+            }
+            let Some(member) = state.guild.members.iter_mut().find(|member| member.id.to_string() == *user) else {
+                return not_found();
+            };
+
+            member.roles.retain(|held| *held != role);
+            if method == Method::PUT {
+                member.roles.push(role);
+            }
+            StatusCode::NO_CONTENT.into_response()
+        }
+        (Method::PUT, ["applications", _, "guilds", _, "commands"]) => Json(json!([])).into_response(),
+        (Method::POST, ["interactions", _, _, "callback"]) => StatusCode::NO_CONTENT.into_response(),
+        (Method::POST, ["users", "@me", "channels"]) => Json(json!({
+            "id": "7000",
+            "type": 1,
+            "last_message_id": null,
+            "recipients": [user_json(body["recipient_id"].as_str().and_then(|id| id.parse().ok()).unwrap_or(1), false)],
+        }))
+        .into_response(),
+        (Method::POST, ["channels", channel, "messages"]) => Json(message_json(channel, &body["content"])).into_response(),
+        _ => not_found(),
+    }
+}
+
+/// Accept the bot's gateway connection
+async fn gateway(State(state): State<SharedState>, upgrade: WebSocketUpgrade) -> Response {
+    let events = state.lock().unwrap().events.take();
+
+    upgrade.on_upgrade(move |socket| gateway_session(socket, state, events))
+}
+
+/// Play the gateway side of a session: say hello, answer identify with READY and heartbeats with acks, then forward injected events
+async fn gateway_session(mut socket: WebSocket, state: SharedState, mut events: Option<mpsc::UnboundedReceiver<(String, Value)>>) {
+    let mut sequence = 0;
+    let mut ready = false;
+
+    if send(&mut socket, json!({ "op": 10, "d": { "heartbeat_interval": 45000 } })).await.is_err() {
+        return;
+    }
+
+    loop {
+        tokio::select! {
+            message = socket.recv() => {
+                let Some(Ok(Message::Text(text))) = message else {
+                    return; // Closed by the bot
+                };
+                let payload: Value = serde_json::from_str(&text).unwrap_or(Value::Null);
+
+                let reply = match payload["op"].as_u64() {
+                    Some(1) => json!({ "op": 11 }),
+                    Some(2) => {
+                        sequence += 1;
+                        ready = true;
+                        let state = state.lock().unwrap();
+                        json!({
+                            "op": 0,
+                            "t": "READY",
+                            "s": sequence,
+                            "d": {
+                                "v": 10,
+                                "user": user_json(BOT_USER_ID, true),
+                                "guilds": [{ "id": state.guild.id.to_string(), "unavailable": true }],
+                                "session_id": "mock-session",
+                                "resume_gateway_url": state.gateway_url,
+                                "shard": [0, 1],
+                                "application": { "id": APPLICATION_ID.to_string(), "flags": 0 },
+                            },
+                        })
+                    }
+                    _ => continue,
+                };
+
+                if send(&mut socket, reply).await.is_err() {
+                    return;
+                }
+            }
+            Some((event, data)) = async { events.as_mut()?.recv().await }, if ready => {
+                sequence += 1;
+                if send(&mut socket, json!({ "op": 0, "t": event, "s": sequence, "d": data })).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+async fn send(socket: &mut WebSocket, payload: Value) -> Result<(), axum::Error> {
+    socket.send(Message::Text(payload.to_string().into())).await
+}
@@ -0,0 +1,422 @@
+use serde::{Deserialize, Serialize};
+use serenity::all::{GuildId, Member, Mentionable, RoleId, Timestamp, UserId};
+
+use crate::{data::AppData, rules::{self, Rule}};
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Longest timeout Discord allows, 28 days
+pub const MAX_TIMEOUT_MINUTES: u32 = 28 * 24 * 60;
+
+/// What caused the policy to be applied to a member
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Trigger {
+    /// A member update seen by the auto scanner
+    AutoScan,
+    /// A member visited by `/sweep run`
+    Sweep,
+    /// A member picked by `/enforce`
+    Enforce,
+    /// A member who gained a role of a role group
+    RoleGroup,
+    /// A temporary role that ran out
+    TempRole,
+}
+
+impl Trigger {
+    /// Get the label this trigger is reported under in metrics
+    pub fn label(&self) -> &'static str {
+        match self {
+            Trigger::AutoScan => "auto_scan",
+            Trigger::Sweep => "sweep",
+            Trigger::Enforce => "enforce",
+            Trigger::RoleGroup => "role_group",
+            Trigger::TempRole => "temp_role",
+        }
+    }
+}
+
+/// What the bot does to members who hold roles without holding the primary role
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PolicyAction {
+    /// Remove every role that is not exempt
+    #[default]
+    RemoveAll,
+    /// Remove only these roles, leaving any others in place
+    RemoveListed { roles: Vec<RoleId> },
+    /// Give the member a quarantine role, leaving their roles in place
+    Quarantine { role: RoleId },
+    /// Stop the member from talking for a number of minutes
+    Timeout { minutes: u32 },
+    /// Kick members still without the primary role this many days after joining, whatever roles they hold
+    Kick { after_days: u32 },
+}
+
+impl PolicyAction {
+    /// Describe the action for display to server admins
+    pub fn describe(&self) -> String {
+        match self {
+            PolicyAction::RemoveAll => "Remove every role that is not exempt".to_string(),
+            PolicyAction::RemoveListed { roles } => {
+                format!("Remove only {}", roles.iter().map(|role| role.mention().to_string()).collect::<Vec<_>>().join(", "))
+            }
+            PolicyAction::Quarantine { role } => format!("Give the {} quarantine role", role.mention()),
+            PolicyAction::Timeout { minutes } => format!("Time out for {} minutes", minutes),
+            PolicyAction::Kick { after_days } => format!("Kick members without the primary role {} days after they join", after_days),
+        }
+    }
+
+    /// Describe what was done to members in a report, such as "removed roles from" 3 members
+    pub fn past_tense(&self) -> &'static str {
+        match self {
+            PolicyAction::RemoveAll | PolicyAction::RemoveListed { .. } => "removed roles from",
+            PolicyAction::Quarantine { .. } => "quarantined",
+            PolicyAction::Timeout { .. } => "timed out",
+            PolicyAction::Kick { .. } => "kicked",
+        }
+    }
+
+    /// Check if the action only ever takes roles away
+    fn removes_roles(&self) -> bool {
+        matches!(self, PolicyAction::RemoveAll | PolicyAction::RemoveListed { .. })
+    }
+}
+
+/// State of a member the policy is decided on
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MemberSnapshot {
+    pub user_id: UserId,
+    pub roles: Vec<RoleId>,
+    pub bot: bool,
+    pub joined_at: Option<Timestamp>,
+    /// End of the member's current timeout, if they have one
+    pub timed_out_until: Option<Timestamp>,
+}
+
+impl From<&Member> for MemberSnapshot {
+    fn from(member: &Member) -> Self {
+        Self {
+            user_id: member.user.id,
+            roles: member.roles.clone(),
+            bot: member.user.bot,
+            joined_at: member.joined_at,
+            timed_out_until: member.communication_disabled_until,
+        }
+    }
+}
+
+/// Configuration of a server the policy is decided with
+#[derive(Clone, Debug, PartialEq)]
+pub struct GuildPolicy {
+    /// Role members must hold to keep their other roles, None if only role rules are enforced
+    pub primary_role: Option<RoleId>,
+    pub exempt_roles: Vec<RoleId>,
+    pub action: PolicyAction,
+    /// Rules added with `/rules`, on top of the primary role rule
+    pub rules: Vec<Rule>,
+    /// Roles given with `/temprole`, by member
+    pub temp_roles: Vec<(UserId, RoleId)>,
+}
+
+impl GuildPolicy {
+    /// Read the policy of a server from the database
+    ///
+    /// @param app_data Database of server configuration
+    /// @param guild_id ID of the server
+    ///
+    /// @return Policy of the server, None if it has neither a primary role nor role rules
+    pub fn load(app_data: &AppData, guild_id: &GuildId) -> Option<Self> {
+        let primary_role = app_data.get_primary_role(guild_id);
+        let rules: Vec<Rule> = app_data.get_rules(guild_id).into_iter().map(|(_, rule)| rule).collect();
+        if primary_role.is_none() && rules.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            primary_role,
+            exempt_roles: app_data.get_exempt_roles(guild_id),
+            action: app_data.get_policy_action(guild_id),
+            rules,
+            temp_roles: app_data.get_temp_roles(guild_id).into_iter().map(|temp_role| (temp_role.user_id, temp_role.role_id)).collect(),
+        })
+    }
+
+    /// Get the temporary roles a member holds without the primary role, which they lose even if the roles are exempt
+    ///
+    /// @param member Member to check
+    pub fn lost_temp_roles(&self, member: &MemberSnapshot) -> Vec<RoleId> {
+        if self.primary_role.is_none_or(|primary_role| member.roles.contains(&primary_role)) {
+            return Vec::new();
+        }
+
+        return member.roles.iter().filter(|role| self.temp_roles.contains(&(member.user_id, **role))).copied().collect();
+    }
+
+    /// Get the rules that stand for the primary role under the configured action, none if no primary role is set
+    pub fn primary_rules(&self) -> Vec<Rule> {
+        let Some(primary_role) = self.primary_role else {
+            return Vec::new();
+        };
+
+        match &self.action {
+            PolicyAction::RemoveListed { roles } => roles.iter().map(|role| Rule::Requires { role: Some(*role), required: primary_role }).collect(),
+            _ => vec![Rule::Requires { role: None, required: primary_role }],
+        }
+    }
+
+    /// Get every rule members are held to, the primary role rule first
+    pub fn all_rules(&self) -> Vec<Rule> {
+        return self.primary_rules().into_iter().chain(self.rules.iter().cloned()).collect();
+    }
+}
+
+/// Outcome of applying the policy to a member
+#[derive(Clone, Debug, PartialEq)]
+pub enum Decision {
+    /// The member breaks no rule, holds only exempt roles, or has already been dealt with
+    Compliant,
+    /// The member is a bot. Bots are not granted the primary role, so sweeps leave them alone, and no trigger kicks,
+    /// times out or quarantines them
+    SkipBot,
+    /// These roles must be removed from the member
+    RemoveRoles(Vec<RoleId>),
+    /// The member must be given this role
+    AssignRole(RoleId),
+    /// The member must be timed out until this time
+    Timeout(Timestamp),
+    /// The member must be kicked
+    Kick,
+}
+
+impl Decision {
+    /// Check if the decision asks for anything to be done
+    pub fn is_action(&self) -> bool {
+        !matches!(self, Decision::Compliant | Decision::SkipBot)
+    }
+
+    /// Describe what the bot would do, for display to server admins
+    pub fn describe(&self) -> String {
+        match self {
+            Decision::Compliant | Decision::SkipBot => "Nothing".to_string(),
+            Decision::RemoveRoles(roles) => format!("Remove {}", roles.iter().map(|role| role.mention().to_string()).collect::<Vec<_>>().join(", ")),
+            Decision::AssignRole(role) => format!("Give them {}", role.mention()),
+            Decision::Timeout(until) => format!("Time them out until <t:{}:f>", until.unix_timestamp()),
+            Decision::Kick => "Kick them".to_string(),
+        }
+    }
+}
+
+/// Decide what to do with a member, shared by every path that enforces the policy
+///
+/// @param member Member the policy is applied to
+/// @param policy Policy of the member's server
+/// @param trigger What caused the policy to be applied
+/// @param now Current time, to measure timeouts and time since joining against
+///
+/// @return Decision for the member
+pub fn decide(member: &MemberSnapshot, policy: &GuildPolicy, trigger: Trigger, now: Timestamp) -> Decision {
+    if member.bot && (trigger == Trigger::Sweep || !policy.action.removes_roles()) {
+        return Decision::SkipBot;
+    }
+    // Roles breaking role rules are removed whatever the action, the configured action is for the primary role
+    let lost_temp_roles = policy.lost_temp_roles(member);
+    let broken = rules::evaluate(&policy.rules, &member.roles, &policy.exempt_roles);
+    let Some(primary_role) = policy.primary_role.filter(|primary_role| !member.roles.contains(primary_role)) else {
+        // Members who got the primary role, for example by verifying, are let out of quarantine even if the role is exempt
+        let released = match (&policy.action, policy.primary_role) {
+            (PolicyAction::Quarantine { role }, Some(_)) => Some(*role),
+            _ => None,
+        };
+        return removal(member.roles.iter().filter(|role| broken.contains(role) || lost_temp_roles.contains(role) || released == Some(**role)).copied().collect());
+    };
+    if policy.action.removes_roles() {
+        let removed = rules::evaluate(&policy.all_rules(), &member.roles, &policy.exempt_roles);
+        return removal(member.roles.iter().filter(|role| removed.contains(role) || lost_temp_roles.contains(role)).copied().collect());
+    }
+    // Roles breaking rules and temporary roles go first, the configured action follows on the member update their removal causes
+    let removed: Vec<RoleId> = member.roles.iter().filter(|role| broken.contains(role) || lost_temp_roles.contains(role)).copied().collect();
+    if !removed.is_empty() {
+        return Decision::RemoveRoles(removed);
+    }
+
+    let held_roles = || roles_to_remove(&member.roles, primary_role, &policy.exempt_roles);
+    match &policy.action {
+        PolicyAction::RemoveAll | PolicyAction::RemoveListed { .. } => Decision::Compliant,
+        PolicyAction::Quarantine { role } => {
+            if member.roles.contains(role) || held_roles().is_empty() {
+                return Decision::Compliant;
+            }
+            Decision::AssignRole(*role)
+        }
+        PolicyAction::Timeout { minutes } => {
+            if member.timed_out_until.is_some_and(|until| until > now) || held_roles().is_empty() {
+                return Decision::Compliant;
+            }
+            match Timestamp::from_unix_timestamp(now.unix_timestamp() + i64::from(*minutes) * 60) {
+                Ok(until) => Decision::Timeout(until),
+                Err(_) => Decision::Compliant,
+            }
+        }
+        PolicyAction::Kick { after_days } => {
+            let deadline = now.unix_timestamp() - i64::from(*after_days) * SECONDS_PER_DAY;
+            if member.joined_at.is_some_and(|joined_at| joined_at.unix_timestamp() <= deadline) {
+                return Decision::Kick;
+            }
+            Decision::Compliant
+        }
+    }
+}
+
+fn removal(roles: Vec<RoleId>) -> Decision {
+    if roles.is_empty() {
+        return Decision::Compliant;
+    }
+
+    Decision::RemoveRoles(roles)
+}
+
+/// Determine which roles the auto scanner removes from a member
+///
+/// @param member_roles Roles the member currently holds
+/// @param primary_role Primary role configured for the server
+/// @param exempt_roles Roles the bot must never remove
+///
+/// @return Roles to remove, empty if the member holds the primary role or only exempt roles
+pub fn roles_to_remove(member_roles: &[RoleId], primary_role: RoleId, exempt_roles: &[RoleId]) -> Vec<RoleId> {
+    return rules::evaluate(&[Rule::Requires { role: None, required: primary_role }], member_roles, exempt_roles);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_roles_to_remove() {
+        let primary = RoleId::new(1);
+        let exempt = [RoleId::new(2)];
+
+        assert!(roles_to_remove(&[primary, RoleId::new(3)], primary, &exempt).is_empty());
+        assert!(roles_to_remove(&[RoleId::new(2)], primary, &exempt).is_empty());
+        assert!(roles_to_remove(&[], primary, &exempt).is_empty());
+        assert_eq!(vec![RoleId::new(3), RoleId::new(4)], roles_to_remove(&[RoleId::new(3), RoleId::new(2), RoleId::new(4)], primary, &exempt));
+    }
+
+    fn at(seconds: i64) -> Timestamp {
+        Timestamp::from_unix_timestamp(seconds).unwrap()
+    }
+
+    fn member(roles: &[u64], bot: bool) -> MemberSnapshot {
+        MemberSnapshot { user_id: UserId::new(5), roles: roles.iter().map(|role| RoleId::new(*role)).collect(), bot, ..Default::default() }
+    }
+
+    fn policy(action: PolicyAction) -> GuildPolicy {
+        GuildPolicy { primary_role: Some(RoleId::new(1)), exempt_roles: vec![RoleId::new(2)], action, rules: Vec::new(), temp_roles: Vec::new() }
+    }
+
+    #[test]
+    fn test_decide() {
+        let policy = policy(PolicyAction::RemoveAll);
+        let now = at(0);
+
+        assert_eq!(Decision::Compliant, decide(&member(&[1, 3], false), &policy, Trigger::AutoScan, now));
+        assert_eq!(Decision::Compliant, decide(&member(&[2], false), &policy, Trigger::Sweep, now));
+        assert_eq!(Decision::RemoveRoles(vec![RoleId::new(3)]), decide(&member(&[2, 3], false), &policy, Trigger::Enforce, now));
+
+        // Only sweeps leave bots alone
+        assert_eq!(Decision::SkipBot, decide(&member(&[3], true), &policy, Trigger::Sweep, now));
+        assert_eq!(Decision::RemoveRoles(vec![RoleId::new(3)]), decide(&member(&[3], true), &policy, Trigger::AutoScan, now));
+    }
+
+    #[test]
+    fn test_policy_actions() {
+        let now = at(100 * SECONDS_PER_DAY);
+
+        let listed = policy(PolicyAction::RemoveListed { roles: vec![RoleId::new(2), RoleId::new(4)] });
+        assert_eq!(Decision::RemoveRoles(vec![RoleId::new(4)]), decide(&member(&[2, 3, 4], false), &listed, Trigger::AutoScan, now));
+        assert_eq!(Decision::Compliant, decide(&member(&[3], false), &listed, Trigger::AutoScan, now));
+
+        let quarantine = policy(PolicyAction::Quarantine { role: RoleId::new(9) });
+        assert_eq!(Decision::AssignRole(RoleId::new(9)), decide(&member(&[3], false), &quarantine, Trigger::Sweep, now));
+        assert_eq!(Decision::Compliant, decide(&member(&[3, 9], false), &quarantine, Trigger::Sweep, now));
+        assert_eq!(Decision::Compliant, decide(&member(&[], false), &quarantine, Trigger::Sweep, now));
+        // Getting the primary role ends the quarantine
+        assert_eq!(Decision::RemoveRoles(vec![RoleId::new(9)]), decide(&member(&[1, 3, 9], false), &quarantine, Trigger::AutoScan, now));
+        assert_eq!(Decision::Compliant, decide(&member(&[1, 3], false), &quarantine, Trigger::AutoScan, now));
+
+        let timeout = policy(PolicyAction::Timeout { minutes: 10 });
+        let mut timed_out = member(&[3], false);
+        assert_eq!(Decision::Timeout(at(now.unix_timestamp() + 600)), decide(&timed_out, &timeout, Trigger::AutoScan, now));
+        timed_out.timed_out_until = Some(at(now.unix_timestamp() + 1));
+        assert_eq!(Decision::Compliant, decide(&timed_out, &timeout, Trigger::AutoScan, now));
+
+        let kick = policy(PolicyAction::Kick { after_days: 7 });
+        let mut newcomer = member(&[], false);
+        newcomer.joined_at = Some(at(now.unix_timestamp() - 6 * SECONDS_PER_DAY));
+        assert_eq!(Decision::Compliant, decide(&newcomer, &kick, Trigger::Sweep, now));
+        newcomer.joined_at = Some(at(now.unix_timestamp() - 7 * SECONDS_PER_DAY));
+        assert_eq!(Decision::Kick, decide(&newcomer, &kick, Trigger::Sweep, now));
+
+        // Bots are never kicked, and members with the primary role are always left alone
+        newcomer.bot = true;
+        assert_eq!(Decision::SkipBot, decide(&newcomer, &kick, Trigger::AutoScan, now));
+        newcomer.bot = false;
+        newcomer.roles = vec![RoleId::new(1)];
+        assert_eq!(Decision::Compliant, decide(&newcomer, &kick, Trigger::Sweep, now));
+    }
+
+    #[test]
+    fn test_custom_rules() {
+        let now = at(0);
+        let mut policy = policy(PolicyAction::Kick { after_days: 7 });
+        policy.rules = vec![Rule::Requires { role: Some(RoleId::new(3)), required: RoleId::new(4) }];
+
+        // Rules apply on top of the primary role, whatever the action
+        assert_eq!(Decision::RemoveRoles(vec![RoleId::new(3)]), decide(&member(&[1, 3], false), &policy, Trigger::AutoScan, now));
+        assert_eq!(Decision::Compliant, decide(&member(&[1, 3, 4], false), &policy, Trigger::AutoScan, now));
+
+        // Members without the primary role lose roles breaking rules before the action applies
+        let mut newcomer = member(&[3, 5], false);
+        newcomer.joined_at = Some(at(-30 * SECONDS_PER_DAY));
+        assert_eq!(Decision::RemoveRoles(vec![RoleId::new(3)]), decide(&newcomer, &policy, Trigger::AutoScan, now));
+        newcomer.roles = vec![RoleId::new(5)];
+        assert_eq!(Decision::Kick, decide(&newcomer, &policy, Trigger::AutoScan, now));
+
+        policy.action = PolicyAction::RemoveListed { roles: vec![RoleId::new(4)] };
+        assert_eq!(Decision::RemoveRoles(vec![RoleId::new(3), RoleId::new(4)]), decide(&member(&[3, 4, 5], false), &policy, Trigger::Sweep, now));
+
+        // Rules hold without a primary role too
+        policy.primary_role = None;
+        assert_eq!(Decision::RemoveRoles(vec![RoleId::new(3)]), decide(&member(&[3, 5], false), &policy, Trigger::Sweep, now));
+        assert_eq!(Decision::Compliant, decide(&member(&[4, 5], false), &policy, Trigger::Sweep, now));
+    }
+
+    #[test]
+    fn test_load() {
+        let mut app_data = AppData::new(":memory:");
+        let guild = GuildId::new(1);
+        assert_eq!(None, GuildPolicy::load(&app_data, &guild));
+
+        let rule = Rule::Requires { role: Some(RoleId::new(3)), required: RoleId::new(4) };
+        app_data.add_rule(&guild, &rule).unwrap();
+        let policy = GuildPolicy::load(&app_data, &guild).unwrap();
+        assert_eq!(None, policy.primary_role);
+        assert_eq!(vec![rule], policy.all_rules());
+    }
+
+    #[test]
+    fn test_temp_roles() {
+        let now = at(0);
+        let mut policy = policy(PolicyAction::RemoveAll);
+        policy.temp_roles = vec![(UserId::new(5), RoleId::new(2)), (UserId::new(6), RoleId::new(3))];
+
+        // Temporary roles go with the primary role, even when exempt
+        assert_eq!(Decision::RemoveRoles(vec![RoleId::new(2), RoleId::new(3)]), decide(&member(&[2, 3], false), &policy, Trigger::AutoScan, now));
+        assert_eq!(Decision::Compliant, decide(&member(&[1, 2], false), &policy, Trigger::AutoScan, now));
+
+        // Other actions wait until the temporary roles are gone
+        policy.action = PolicyAction::Quarantine { role: RoleId::new(9) };
+        assert_eq!(Decision::RemoveRoles(vec![RoleId::new(2)]), decide(&member(&[2, 3], false), &policy, Trigger::Sweep, now));
+        assert_eq!(Decision::AssignRole(RoleId::new(9)), decide(&member(&[3], false), &policy, Trigger::Sweep, now));
+    }
+}
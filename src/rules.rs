@@ -0,0 +1,122 @@
+use serde::{Deserialize, Serialize};
+use serenity::all::{Mentionable, RoleId};
+
+/// Dependency between roles that members must satisfy, or lose the roles breaking it
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Rule {
+    /// Holding `role` requires holding `required`. Without a role, every role other than `required` requires it
+    Requires { role: Option<RoleId>, required: RoleId },
+    /// Holding `role` requires holding at least one of `any_of`. Without a role, every other role requires one of them
+    RequiresAnyOf { role: Option<RoleId>, any_of: Vec<RoleId> },
+    /// At most one of these roles may be held, the earliest listed one is kept
+    MutuallyExclusive { roles: Vec<RoleId> },
+}
+
+impl Rule {
+    /// Describe the rule for display to server admins
+    pub fn describe(&self) -> String {
+        let subject = |role: &Option<RoleId>| role.map_or("Every role that is not exempt".to_string(), |role| role.mention().to_string());
+
+        match self {
+            Rule::Requires { role, required } => format!("{} requires {}", subject(role), required.mention()),
+            Rule::RequiresAnyOf { role, any_of } => format!("{} requires one of {}", subject(role), mention_all(any_of)),
+            Rule::MutuallyExclusive { roles } => format!("Only one of {} may be held, earlier roles win", mention_all(roles)),
+        }
+    }
+
+    /// Find the held roles breaking this rule
+    ///
+    /// @param held Roles the member holds
+    ///
+    /// @return Roles to remove so the member satisfies the rule
+    fn violations(&self, held: &[RoleId]) -> Vec<RoleId> {
+        let subjects = |role: &Option<RoleId>, satisfying: &[RoleId]| -> Vec<RoleId> {
+            match role {
+                Some(role) => held.iter().filter(|held| *held == role).copied().collect(),
+                None => held.iter().filter(|held| !satisfying.contains(held)).copied().collect(),
+            }
+        };
+
+        match self {
+            Rule::Requires { role, required } if !held.contains(required) => subjects(role, &[*required]),
+            Rule::RequiresAnyOf { role, any_of } if !any_of.iter().any(|role| held.contains(role)) => subjects(role, any_of),
+            Rule::MutuallyExclusive { roles } => roles.iter().filter(|role| held.contains(role)).skip(1).copied().collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+fn mention_all(roles: &[RoleId]) -> String {
+    return roles.iter().map(|role| role.mention().to_string()).collect::<Vec<_>>().join(", ");
+}
+
+/// Work out which roles a member loses under a set of rules
+///
+/// Removing a role can break another rule that depended on it, so rules are applied until none is broken
+///
+/// @param rules Rules of the member's server
+/// @param held Roles the member holds
+/// @param exempt_roles Roles no rule may remove
+///
+/// @return Roles to remove, in the order the member holds them
+pub fn evaluate(rules: &[Rule], held: &[RoleId], exempt_roles: &[RoleId]) -> Vec<RoleId> {
+    let mut remaining = held.to_vec();
+
+    loop {
+        let broken: Vec<RoleId> = rules.iter().flat_map(|rule| rule.violations(&remaining)).filter(|role| !exempt_roles.contains(role)).collect();
+        if broken.is_empty() {
+            break;
+        }
+
+        remaining.retain(|role| !broken.contains(role));
+    }
+
+    return held.iter().filter(|role| !remaining.contains(role)).copied().collect();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn roles(ids: &[u64]) -> Vec<RoleId> {
+        ids.iter().map(|id| RoleId::new(*id)).collect()
+    }
+
+    #[test]
+    fn test_rules() {
+        let requires = Rule::Requires { role: Some(RoleId::new(3)), required: RoleId::new(4) };
+        assert_eq!(roles(&[3]), evaluate(std::slice::from_ref(&requires), &roles(&[3, 5]), &[]));
+        assert!(evaluate(&[requires], &roles(&[3, 4]), &[]).is_empty());
+
+        let any_of = Rule::RequiresAnyOf { role: Some(RoleId::new(3)), any_of: roles(&[4, 5]) };
+        assert_eq!(roles(&[3]), evaluate(std::slice::from_ref(&any_of), &roles(&[3, 6]), &[]));
+        assert!(evaluate(&[any_of], &roles(&[3, 5]), &[]).is_empty());
+
+        let exclusive = Rule::MutuallyExclusive { roles: roles(&[7, 6, 5]) };
+        assert_eq!(roles(&[5, 6]), evaluate(std::slice::from_ref(&exclusive), &roles(&[5, 6, 7]), &[]));
+        assert!(evaluate(&[exclusive], &roles(&[5, 1]), &[]).is_empty());
+    }
+
+    #[test]
+    fn test_primary_role_rule() {
+        let primary = Rule::Requires { role: None, required: RoleId::new(1) };
+
+        assert_eq!(roles(&[3, 4]), evaluate(std::slice::from_ref(&primary), &roles(&[3, 2, 4]), &roles(&[2])));
+        assert!(evaluate(std::slice::from_ref(&primary), &roles(&[1, 3]), &[]).is_empty());
+        assert!(evaluate(&[primary], &[], &[]).is_empty());
+    }
+
+    #[test]
+    fn test_chained_rules() {
+        // Losing 4 to the exclusive rule takes 3 with it, but exempt roles always stay
+        let rules = [
+            Rule::MutuallyExclusive { roles: roles(&[5, 4]) },
+            Rule::Requires { role: Some(RoleId::new(3)), required: RoleId::new(4) },
+            Rule::Requires { role: Some(RoleId::new(2)), required: RoleId::new(3) },
+        ];
+
+        assert_eq!(roles(&[2, 3, 4]), evaluate(&rules, &roles(&[2, 3, 4, 5]), &[]));
+        assert_eq!(roles(&[3, 4]), evaluate(&rules, &roles(&[2, 3, 4, 5]), &roles(&[2])));
+    }
+}
@@ -0,0 +1,129 @@
+use std::{future::Future, time::Duration};
+
+use log::{info, warn};
+use serenity::{client::Context, prelude::TypeMapKey};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+/// Coordinates stopping the bot, tracking background work that must finish first
+#[derive(Default)]
+pub struct Shutdown {
+    token: CancellationToken,
+    tasks: TaskTracker,
+}
+
+/// Key to retrieve the shutdown coordinator from the client's data
+pub struct ShutdownKey;
+
+impl TypeMapKey for ShutdownKey {
+    type Value = std::sync::Arc<Shutdown>;
+}
+
+impl Shutdown {
+    /// Check if the bot has been asked to stop
+    pub fn is_shutting_down(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Wait until the bot has been asked to stop
+    pub async fn cancelled(&self) {
+        self.token.cancelled().await
+    }
+
+    /// Run a background task that shutdown waits for, tasks should stop early once `is_shutting_down` is true
+    ///
+    /// @param task Task to run
+    pub fn spawn<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.tasks.spawn(task);
+    }
+
+    /// Signal every task to stop and wait for them to finish
+    ///
+    /// @param deadline Longest time to wait for the tasks
+    ///
+    /// @return True if every task finished before the deadline
+    pub async fn shutdown(&self, deadline: Duration) -> bool {
+        self.token.cancel();
+        self.tasks.close();
+
+        info!("Waiting up to {:?} for {} background tasks", deadline, self.tasks.len());
+        let finished = tokio::time::timeout(deadline, self.tasks.wait()).await.is_ok();
+        if !finished {
+            warn!("{} background tasks did not finish before the shutdown deadline", self.tasks.len());
+        }
+
+        finished
+    }
+}
+
+/// Run a background task tracked by the shutdown coordinator in the client's data, if there is one
+///
+/// @param ctx Context holding the coordinator
+/// @param task Task to run
+pub async fn spawn<F>(ctx: &Context, task: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    match ctx.data.read().await.get::<ShutdownKey>() {
+        Some(shutdown) => shutdown.spawn(task),
+        None => {
+            tokio::spawn(task);
+        }
+    }
+}
+
+/// Wait until the process is asked to stop, by SIGTERM or Ctrl+C
+pub async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()).expect("Expected to listen for SIGTERM");
+
+        tokio::select! {
+            _ = terminate.recv() => info!("Received SIGTERM"),
+            _ = tokio::signal::ctrl_c() => info!("Received Ctrl+C"),
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await.ok();
+        info!("Received Ctrl+C");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_waits_for_tasks() {
+        let shutdown = Arc::new(Shutdown::default());
+        let checkpointed = Arc::new(AtomicBool::new(false));
+
+        let (task_shutdown, task_checkpointed) = (shutdown.clone(), checkpointed.clone());
+        shutdown.spawn(async move {
+            while !task_shutdown.is_shutting_down() {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+            task_checkpointed.store(true, Ordering::SeqCst);
+        });
+
+        assert!(shutdown.shutdown(Duration::from_secs(5)).await);
+        assert!(checkpointed.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_deadline() {
+        let shutdown = Shutdown::default();
+        shutdown.spawn(tokio::time::sleep(Duration::from_secs(60)));
+
+        assert!(!shutdown.shutdown(Duration::from_millis(10)).await);
+    }
+}
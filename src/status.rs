@@ -0,0 +1,128 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use serenity::prelude::TypeMapKey;
+
+/// Live state of the bot, reported by the health endpoint
+pub struct BotStatus {
+    gateway: Mutex<GatewayState>,
+    running_sweeps: AtomicUsize,
+}
+
+struct GatewayState {
+    /// Connection state of every shard this process has started
+    shards: HashMap<u32, bool>,
+    /// Whether every shard was connected at the last change
+    connected: bool,
+    /// When the overall connection state last changed
+    since: Instant,
+}
+
+/// Key to retrieve the bot status from the client's data
+pub struct BotStatusKey;
+
+impl TypeMapKey for BotStatusKey {
+    type Value = Arc<BotStatus>;
+}
+
+/// Counts a sweep as running until dropped
+pub struct SweepGuard(Arc<BotStatus>);
+
+impl Drop for SweepGuard {
+    fn drop(&mut self) {
+        self.0.running_sweeps.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Default for BotStatus {
+    fn default() -> Self {
+        Self {
+            gateway: Mutex::new(GatewayState {
+                shards: HashMap::new(),
+                connected: false,
+                since: Instant::now(),
+            }),
+            running_sweeps: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl BotStatus {
+    /// Record a change in the gateway connection of a shard
+    ///
+    /// @param shard_id Shard whose connection changed
+    /// @param connected True if the shard is now connected
+    pub fn set_connected(&self, shard_id: u32, connected: bool) {
+        let mut gateway = self.gateway.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        gateway.shards.insert(shard_id, connected);
+
+        // The bot only counts as connected once every shard it runs is
+        let connected = gateway.shards.values().all(|connected| *connected);
+        if gateway.connected != connected {
+            gateway.connected = connected;
+            gateway.since = Instant::now();
+        }
+    }
+
+    /// Get the number of connected shards and the number of shards seen so far
+    pub fn shard_counts(&self) -> (usize, usize) {
+        let gateway = self.gateway.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        (gateway.shards.values().filter(|connected| **connected).count(), gateway.shards.len())
+    }
+
+    /// Check if every shard is currently connected
+    pub fn is_connected(&self) -> bool {
+        self.gateway.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).connected
+    }
+
+    /// Get how long the gateway has been disconnected
+    ///
+    /// @return Time since the connection was lost, or since startup if never connected. None while connected
+    pub fn disconnected_for(&self) -> Option<Duration> {
+        let gateway = self.gateway.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        if gateway.connected {
+            return None;
+        }
+
+        return Some(gateway.since.elapsed());
+    }
+
+    /// Mark a sweep as running, until the returned guard is dropped
+    pub fn start_sweep(self: &Arc<Self>) -> SweepGuard {
+        self.running_sweeps.fetch_add(1, Ordering::SeqCst);
+        SweepGuard(self.clone())
+    }
+
+    /// Get the number of sweeps currently running
+    pub fn running_sweeps(&self) -> usize {
+        self.running_sweeps.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_shards() {
+        let status = BotStatus::default();
+        assert!(!status.is_connected());
+
+        status.set_connected(0, true);
+        status.set_connected(1, false);
+        assert!(!status.is_connected());
+        assert_eq!((1, 2), status.shard_counts());
+
+        status.set_connected(1, true);
+        assert!(status.is_connected());
+        assert_eq!(None, status.disconnected_for());
+    }
+}
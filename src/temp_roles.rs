@@ -0,0 +1,204 @@
+use std::{sync::Arc, time::Duration};
+
+use log::{error, info};
+use serenity::{
+    all::{GuildId, Http, Mentionable, RoleId, Timestamp, UserId},
+    http::{HttpError, StatusCode},
+};
+use tokio::sync::Mutex;
+
+use crate::{
+    actions::{self, GuildActions},
+    data::AppData,
+    policy::{Decision, Trigger},
+    shutdown::Shutdown,
+};
+
+/// How often the bot looks for temporary roles that ran out
+pub const EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Longest time a role can be given for, one year
+pub const MAX_DURATION_SECS: i64 = 365 * 24 * 60 * 60;
+
+/// Role given to a member until a set time
+#[derive(Clone, Debug, PartialEq)]
+pub struct TempRole {
+    pub guild_id: GuildId,
+    pub user_id: UserId,
+    pub role_id: RoleId,
+    /// Unix timestamp the role is taken away at
+    pub expires_at: i64,
+}
+
+impl TempRole {
+    /// Describe the temporary role for display to server admins
+    pub fn describe(&self) -> String {
+        return format!("{} has {} until <t:{}:f>", self.user_id.mention(), self.role_id.mention(), self.expires_at);
+    }
+}
+
+/// Parse a duration such as "30m", "12h", "7d" or "1d12h"
+///
+/// @param text Duration made of numbers followed by a unit, m for minutes, h for hours, d for days or w for weeks
+///
+/// @return Length of the duration in seconds, None if it is invalid, zero or longer than a year
+pub fn parse_duration(text: &str) -> Option<i64> {
+    let mut total: i64 = 0;
+    let mut number = String::new();
+
+    for character in text.trim().to_lowercase().chars() {
+        if character.is_ascii_digit() {
+            number.push(character);
+            continue;
+        }
+
+        let unit = match character {
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return None,
+        };
+        let value: i64 = number.parse().ok()?;
+        total = total.checked_add(value.checked_mul(unit)?)?;
+        number.clear();
+    }
+
+    if !number.is_empty() || total == 0 || total > MAX_DURATION_SECS {
+        return None;
+    }
+
+    return Some(total);
+}
+
+/// Check if Discord reported that the member or role of a request no longer exists
+fn is_gone(error: &serenity::Error) -> bool {
+    matches!(error, serenity::Error::Http(HttpError::UnsuccessfulRequest(response)) if response.status_code == StatusCode::NOT_FOUND)
+}
+
+/// Take away every temporary role that ran out
+///
+/// A role is forgotten once it is removed, or once Discord reports the member or role is gone. Other failures are
+/// retried on the next run. Replicas sharing a database may remove the same role twice, which Discord ignores
+///
+/// @param actions Connection to Discord
+/// @param app_data Database holding the temporary roles
+/// @param now Current Unix timestamp
+/// @param shutdown Shutdown coordinator, no more roles are taken away once the bot is stopping
+///
+/// @return Number of roles taken away
+pub async fn expire(actions: &dyn GuildActions, app_data: &Mutex<AppData>, now: i64, shutdown: &Shutdown) -> usize {
+    // The database is only locked between calls to Discord, so commands are not held up by a slow API
+    let expired = app_data.lock().await.get_expired_temp_roles(now);
+    let mut removed = 0;
+
+    for temp_role in expired {
+        // Roles left over are still in the database, so the next start takes them away
+        if shutdown.is_shutting_down() {
+            break;
+        }
+
+        let decision = Decision::RemoveRoles(vec![temp_role.role_id]);
+        match actions::carry_out(actions, temp_role.guild_id, temp_role.user_id, &decision, Trigger::TempRole).await {
+            Ok(_) => removed += 1,
+            Err(error) if is_gone(&error) => info!("Forgetting temporary role {} of {}, it or the member is gone", temp_role.role_id, temp_role.user_id),
+            Err(_) => continue,
+        }
+
+        if let Err(error) = app_data.lock().await.remove_temp_role(&temp_role.guild_id, &temp_role.user_id, &temp_role.role_id) {
+            error!("Failed to forget temporary role {} of {}: {}", temp_role.role_id, temp_role.user_id, error);
+        }
+    }
+
+    return removed;
+}
+
+/// Take away temporary roles as they run out, catching up on roles that ran out while the bot was offline
+///
+/// @param http Connection to Discord
+/// @param app_data Database holding the temporary roles
+/// @param shutdown Shutdown coordinator tracking this task, the loop stops once the bot is stopping
+pub async fn run_periodic(http: Arc<Http>, app_data: Arc<Mutex<AppData>>, shutdown: Arc<Shutdown>) {
+    let mut interval = tokio::time::interval(EXPIRY_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.cancelled() => return,
+        }
+
+        let removed = expire(&*http, &app_data, Timestamp::now().unix_timestamp(), &shutdown).await;
+        if removed > 0 {
+            info!("Took away {} expired temporary roles", removed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        actions::{Action, FakeActions},
+        mock_discord::{FakeGuild, FakeMember, MockDiscord},
+    };
+
+    const GUILD: GuildId = GuildId::new(1);
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(Some(30 * 60), parse_duration("30m"));
+        assert_eq!(Some(36 * 60 * 60), parse_duration(" 1D12h "));
+        assert_eq!(Some(14 * 24 * 60 * 60), parse_duration("2w"));
+
+        for invalid in ["", "10", "h", "0m", "5s", "1h30", "-1h", "53w", "99999999999999999999d"] {
+            assert_eq!(None, parse_duration(invalid), "{} should be invalid", invalid);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_expire() {
+        let discord = MockDiscord::start(FakeGuild { id: GUILD.get(), roles: vec![20, 21], members: vec![FakeMember::new(5, &[20, 21])] }).await;
+        let app_data = Mutex::new(AppData::new(":memory:"));
+        {
+            let mut app_data = app_data.lock().await;
+            app_data.add_temp_role(&GUILD, &UserId::new(5), &RoleId::new(20), 100).unwrap();
+            app_data.add_temp_role(&GUILD, &UserId::new(5), &RoleId::new(21), 200).unwrap();
+            // Member 6 left the server, so Discord no longer knows them
+            app_data.add_temp_role(&GUILD, &UserId::new(6), &RoleId::new(20), 100).unwrap();
+        }
+
+        assert_eq!(1, expire(&discord.http(), &app_data, 150, &Shutdown::default()).await);
+        assert_eq!(vec![21], discord.member_roles(5));
+
+        let remaining = app_data.lock().await.get_temp_roles(&GUILD);
+        assert_eq!(vec![TempRole { guild_id: GUILD, user_id: UserId::new(5), role_id: RoleId::new(21), expires_at: 200 }], remaining);
+    }
+
+    #[tokio::test]
+    async fn test_expire_retries() {
+        let discord = FakeActions::with_members(&[(5, &[20])]);
+        let app_data = Mutex::new(AppData::new(":memory:"));
+        app_data.lock().await.add_temp_role(&GUILD, &UserId::new(7), &RoleId::new(20), 100).unwrap();
+
+        // Failures other than a missing member or role are tried again later
+        assert_eq!(0, expire(&discord, &app_data, 100, &Shutdown::default()).await);
+        assert_eq!(1, app_data.lock().await.get_expired_temp_roles(100).len());
+
+        app_data.lock().await.add_temp_role(&GUILD, &UserId::new(5), &RoleId::new(20), 100).unwrap();
+        assert_eq!(1, expire(&discord, &app_data, 100, &Shutdown::default()).await);
+        assert_eq!(vec![Action::RemoveRoles(UserId::new(5), vec![RoleId::new(20)])], discord.actions());
+    }
+
+    #[tokio::test]
+    async fn test_expire_stops_on_shutdown() {
+        let discord = FakeActions::with_members(&[(5, &[20])]);
+        let app_data = Mutex::new(AppData::new(":memory:"));
+        app_data.lock().await.add_temp_role(&GUILD, &UserId::new(5), &RoleId::new(20), 100).unwrap();
+
+        let shutdown = Shutdown::default();
+        shutdown.shutdown(Duration::ZERO).await;
+
+        assert_eq!(0, expire(&discord, &app_data, 100, &shutdown).await);
+        assert!(discord.actions().is_empty());
+        assert_eq!(1, app_data.lock().await.get_expired_temp_roles(100).len());
+    }
+}
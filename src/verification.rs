@@ -0,0 +1,150 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use serenity::{
+    all::{ChannelId, GuildId, Mentionable, UserId},
+    prelude::TypeMapKey,
+};
+
+/// Wait between two clicks of the verify button by the same member, unless the server sets its own
+pub const DEFAULT_COOLDOWN_SECS: u32 = 60;
+
+/// Most text inputs Discord allows in a form, shared between accepting the rules and the questions
+pub const MAX_FORM_INPUTS: usize = 5;
+
+/// Longest question, as Discord limits the label of a form input
+pub const MAX_QUESTION_LENGTH: usize = 45;
+
+/// How members verify themselves to get the primary role
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct VerificationSettings {
+    /// Channel the verify button was posted in
+    pub channel: ChannelId,
+    /// Channel to log verifications to
+    #[serde(default)]
+    pub log_channel: Option<ChannelId>,
+    /// Rules members must accept before they are verified
+    #[serde(default)]
+    pub rules: Option<String>,
+    /// Seconds a member must wait between clicks of the verify button
+    #[serde(default = "default_cooldown_secs")]
+    pub cooldown_secs: u32,
+    /// Questions members answer before a moderator reviews them, instead of getting the role straight away
+    #[serde(default)]
+    pub questions: Vec<String>,
+    /// Channel answers are posted to for review
+    #[serde(default)]
+    pub review_channel: Option<ChannelId>,
+}
+
+fn default_cooldown_secs() -> u32 {
+    DEFAULT_COOLDOWN_SECS
+}
+
+impl VerificationSettings {
+    /// Describe the settings for display to server admins
+    pub fn describe(&self) -> String {
+        let mut lines = vec![format!("Verify button in {}", self.channel.mention()), format!("Cooldown: {} seconds", self.cooldown_secs)];
+        if let Some(log_channel) = self.log_channel {
+            lines.push(format!("Logged to {}", log_channel.mention()));
+        }
+        if self.rules.is_some() {
+            lines.push("Members must accept the rules first".to_string());
+        }
+        if let Some(review_channel) = self.review_channel.filter(|_| !self.questions.is_empty()) {
+            lines.push(format!("Answers to {} questions are reviewed in {}", self.questions.len(), review_channel.mention()));
+        }
+
+        return lines.join("\n");
+    }
+}
+
+/// Answers a member gave to the questions, waiting for a moderator to review them
+#[derive(Clone, Debug, PartialEq)]
+pub struct Application {
+    pub application_id: i64,
+    pub guild_id: GuildId,
+    pub user_id: UserId,
+    /// Each question with the member's answer
+    pub answers: Vec<(String, String)>,
+    /// Unix timestamp the answers were sent at
+    pub submitted_at: i64,
+}
+
+impl Application {
+    /// Describe the application for the moderators reviewing it
+    pub fn describe(&self) -> String {
+        let answers = self.answers.iter().map(|(question, answer)| format!("**{}**\n{}", question, answer)).collect::<Vec<_>>().join("\n");
+
+        return format!("Application {} from {}, sent <t:{}:R>\n{}", self.application_id, self.user_id.mention(), self.submitted_at, answers);
+    }
+}
+
+/// When each member may click the verify button again, so nobody can hammer it
+///
+/// Nothing survives a restart, which at worst lets a member try once more
+#[derive(Default)]
+pub struct Cooldowns(Mutex<HashMap<(GuildId, UserId), Instant>>);
+
+/// Key to retrieve the verify button cooldowns from the client's data
+pub struct CooldownsKey;
+
+impl TypeMapKey for CooldownsKey {
+    type Value = std::sync::Arc<Cooldowns>;
+}
+
+impl Cooldowns {
+    /// Record an attempt by a member, unless their last attempt was too recent
+    ///
+    /// @param guild_id ID of the member's server
+    /// @param user_id ID of the member
+    /// @param cooldown Time members must wait between attempts
+    /// @param now Time of the attempt
+    ///
+    /// @return None if the attempt may go ahead, otherwise how much longer the member must wait
+    pub fn try_attempt(&self, guild_id: GuildId, user_id: UserId, cooldown: Duration, now: Instant) -> Option<Duration> {
+        let mut retry_times = self.0.lock().unwrap();
+        retry_times.retain(|_, retry_at| *retry_at > now);
+
+        if let Some(retry_at) = retry_times.get(&(guild_id, user_id)) {
+            return Some(*retry_at - now);
+        }
+
+        retry_times.insert((guild_id, user_id), now + cooldown);
+        return None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cooldowns() {
+        let cooldowns = Cooldowns::default();
+        let (guild, user) = (GuildId::new(1), UserId::new(2));
+        let cooldown = Duration::from_secs(60);
+        let start = Instant::now();
+
+        assert_eq!(None, cooldowns.try_attempt(guild, user, cooldown, start));
+        assert_eq!(Some(Duration::from_secs(20)), cooldowns.try_attempt(guild, user, cooldown, start + Duration::from_secs(40)));
+        // Other members and servers are not held up
+        assert_eq!(None, cooldowns.try_attempt(guild, UserId::new(3), cooldown, start + Duration::from_secs(40)));
+        assert_eq!(None, cooldowns.try_attempt(GuildId::new(4), user, cooldown, start + Duration::from_secs(40)));
+
+        assert_eq!(None, cooldowns.try_attempt(guild, user, cooldown, start + cooldown));
+    }
+
+    #[test]
+    fn test_settings_defaults() {
+        let settings: VerificationSettings = serde_json::from_str(r#"{ "channel": "5" }"#).unwrap();
+
+        let expected =
+            VerificationSettings { channel: ChannelId::new(5), log_channel: None, rules: None, cooldown_secs: DEFAULT_COOLDOWN_SECS, questions: Vec::new(), review_channel: None };
+        assert_eq!(expected, settings);
+    }
+}